@@ -0,0 +1,328 @@
+//! Three-C miss classification (Hill and Smith, 1989).
+//!
+//! Alongside the configured cache, two shadows see every access: an infinite
+//! cache, which misses only on first touch, and a fully associative LRU
+//! cache with the same number of blocks. A miss in the real cache is then
+//!
+//! - compulsory if the infinite cache missed too: no cache could have hit;
+//! - capacity if the fully associative cache missed: only a bigger cache
+//!   would have hit;
+//! - conflict otherwise: the same capacity with more associativity (or a
+//!   better mapping) would have hit.
+//!
+//! The shadows allocate on every access whatever the real cache's write
+//! policy, so a no-write-allocate write miss is classified like any other.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write};
+
+use super::functional::{FunctionalCache, Outcome};
+use super::params::{CacheParams, ParamError};
+use super::policy::PolicyKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MissClass {
+    Compulsory,
+    Capacity,
+    Conflict,
+}
+
+impl MissClass {
+    pub const ALL: [MissClass; 3] = [
+        MissClass::Compulsory,
+        MissClass::Capacity,
+        MissClass::Conflict,
+    ];
+}
+
+impl fmt::Display for MissClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MissClass::Compulsory => "compulsory",
+            MissClass::Capacity => "capacity",
+            MissClass::Conflict => "conflict",
+        })
+    }
+}
+
+/// Miss counts by class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThreeC {
+    pub compulsory: u64,
+    pub capacity: u64,
+    pub conflict: u64,
+}
+
+impl ThreeC {
+    pub fn misses(&self) -> u64 {
+        self.compulsory + self.capacity + self.conflict
+    }
+
+    pub fn add(&mut self, class: MissClass) {
+        match class {
+            MissClass::Compulsory => self.compulsory += 1,
+            MissClass::Capacity => self.capacity += 1,
+            MissClass::Conflict => self.conflict += 1,
+        }
+    }
+
+    pub fn count(&self, class: MissClass) -> u64 {
+        match class {
+            MissClass::Compulsory => self.compulsory,
+            MissClass::Capacity => self.capacity,
+            MissClass::Conflict => self.conflict,
+        }
+    }
+
+    /// The fraction of misses in `class`.
+    pub fn share(&self, class: MissClass) -> f64 {
+        if self.misses() == 0 {
+            0.0
+        } else {
+            self.count(class) as f64 / self.misses() as f64
+        }
+    }
+}
+
+impl fmt::Display for ThreeC {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} misses: {} compulsory, {} capacity, {} conflict",
+            self.misses(),
+            self.compulsory,
+            self.capacity,
+            self.conflict
+        )
+    }
+}
+
+/// A fully associative LRU cache of block addresses.
+#[derive(Debug, Clone)]
+pub struct FullyAssociativeLru {
+    capacity: usize,
+    clock: u64,
+    /// Each resident block's last use.
+    last_use: HashMap<u64, u64>,
+    /// Resident blocks by last use, oldest first.
+    by_age: BTreeMap<u64, u64>,
+}
+
+impl FullyAssociativeLru {
+    pub fn new(capacity: usize) -> Self {
+        FullyAssociativeLru {
+            capacity: capacity.max(1),
+            clock: 0,
+            last_use: HashMap::new(),
+            by_age: BTreeMap::new(),
+        }
+    }
+
+    /// Touches `block`, filling it on a miss; returns whether it hit.
+    pub fn access(&mut self, block: u64) -> bool {
+        self.clock += 1;
+        let hit = match self.last_use.insert(block, self.clock) {
+            Some(previous) => {
+                self.by_age.remove(&previous);
+                true
+            }
+            None => false,
+        };
+        self.by_age.insert(self.clock, block);
+        if self.by_age.len() > self.capacity {
+            if let Some((_, oldest)) = self.by_age.pop_first() {
+                self.last_use.remove(&oldest);
+            }
+        }
+        hit
+    }
+}
+
+/// A functional cache that classifies each of its misses.
+pub struct MissClassifier {
+    cache: FunctionalCache,
+    seen: HashSet<u64>,
+    shadow: FullyAssociativeLru,
+    totals: ThreeC,
+    per_set: Vec<ThreeC>,
+}
+
+impl MissClassifier {
+    pub fn new(params: CacheParams, policy: PolicyKind) -> Result<Self, ParamError> {
+        Ok(Self::from_cache(FunctionalCache::new(params, policy)?))
+    }
+
+    /// Classifies the misses of `cache`, which should not have seen any
+    /// accesses yet.
+    pub fn from_cache(cache: FunctionalCache) -> Self {
+        let params = *cache.params();
+        MissClassifier {
+            cache,
+            seen: HashSet::new(),
+            shadow: FullyAssociativeLru::new(params.num_blocks() as usize),
+            totals: ThreeC::default(),
+            per_set: vec![ThreeC::default(); params.num_sets() as usize],
+        }
+    }
+
+    pub fn cache(&self) -> &FunctionalCache {
+        &self.cache
+    }
+
+    /// The breakdown over every access so far.
+    pub fn totals(&self) -> &ThreeC {
+        &self.totals
+    }
+
+    /// The breakdown for each set of the real cache.
+    pub fn per_set(&self) -> &[ThreeC] {
+        &self.per_set
+    }
+
+    /// One access; the class is `None` for a hit.
+    pub fn access(&mut self, addr: u64, write: bool) -> (Outcome, Option<MissClass>) {
+        let p = *self.cache.params();
+        let block = p.block_addr(p.addr_tag(addr), p.addr_index(addr));
+        let first_touch = self.seen.insert(block);
+        let shadow_hit = self.shadow.access(block);
+        let outcome = self.cache.access(addr, write);
+        if outcome.hit {
+            return (outcome, None);
+        }
+        let class = if first_touch {
+            MissClass::Compulsory
+        } else if !shadow_hit {
+            MissClass::Capacity
+        } else {
+            MissClass::Conflict
+        };
+        self.totals.add(class);
+        self.per_set[outcome.set].add(class);
+        (outcome, Some(class))
+    }
+
+    /// Runs `(addr, is_write)` pairs and returns the breakdown so far.
+    pub fn run<I: IntoIterator<Item = (u64, bool)>>(&mut self, accesses: I) -> ThreeC {
+        for (addr, write) in accesses {
+            self.access(addr, write);
+        }
+        self.totals
+    }
+
+    /// A plain-text report: the run's breakdown, then the `worst_sets` sets
+    /// with the most conflict misses.
+    pub fn report(&self, worst_sets: usize) -> String {
+        let p = self.cache.params();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}KB, {}-way, {}B blocks, {} accesses",
+            p.cache_size_kb,
+            p.num_ways,
+            p.block_size_bytes,
+            self.cache.stats().accesses()
+        );
+        for class in MissClass::ALL {
+            let _ = writeln!(
+                out,
+                "{:<11} {:>12} {:>7.2}%",
+                class,
+                self.totals.count(class),
+                100.0 * self.totals.share(class)
+            );
+        }
+        let mut sets: Vec<usize> = (0..self.per_set.len())
+            .filter(|&s| self.per_set[s].misses() > 0)
+            .collect();
+        sets.sort_by_key(|&s| (std::cmp::Reverse(self.per_set[s].conflict), s));
+        sets.truncate(worst_sets);
+        if !sets.is_empty() {
+            let _ = writeln!(
+                out,
+                "{:>6} {:>11} {:>11} {:>11}",
+                "set", "compulsory", "capacity", "conflict"
+            );
+            for s in sets {
+                let c = &self.per_set[s];
+                let _ = writeln!(
+                    out,
+                    "{:>6} {:>11} {:>11} {:>11}",
+                    s, c.compulsory, c.capacity, c.conflict
+                );
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1KB direct mapped with 32-byte blocks: 32 sets, and a shadow of 32
+    /// blocks.
+    fn classifier() -> MissClassifier {
+        let params = CacheParams {
+            cache_size_kb: 1,
+            num_ways: 1,
+            ..CacheParams::default()
+        };
+        MissClassifier::new(params, PolicyKind::Lru).unwrap()
+    }
+
+    #[test]
+    fn fully_associative_lru_evicts_the_oldest() {
+        let mut lru = FullyAssociativeLru::new(2);
+        assert!(!lru.access(1));
+        assert!(!lru.access(2));
+        assert!(lru.access(1));
+        assert!(!lru.access(3));
+        assert!(lru.access(1));
+        assert!(!lru.access(2));
+    }
+
+    #[test]
+    fn known_breakdown() {
+        let mut classifier = classifier();
+        // 0x000 and 0x400 share set 0: two first touches, then two misses
+        // the shadow would have hit.
+        let mut trace = vec![0x000, 0x400, 0x000, 0x400];
+        // 33 new blocks from 0x1000, the last back in set 0.
+        trace.extend((0..33).map(|i| 0x1000 + 32 * i));
+        // 0x1000 has left the 32-block shadow too. 0x1020 then leaves the
+        // shadow, but the real cache still holds it.
+        trace.extend([0x1000, 0x1020]);
+        let totals = classifier.run(trace.into_iter().map(|addr| (addr, false)));
+        assert_eq!(
+            totals,
+            ThreeC {
+                compulsory: 35,
+                capacity: 1,
+                conflict: 2,
+            }
+        );
+        assert_eq!(classifier.cache().stats().accesses(), 39);
+        assert_eq!(
+            classifier.per_set()[0],
+            ThreeC {
+                compulsory: 4,
+                capacity: 1,
+                conflict: 2,
+            }
+        );
+        assert_eq!(classifier.per_set()[1].misses(), 1);
+        let report = classifier.report(1);
+        assert!(report.starts_with("1KB, 1-way, 32B blocks, 39 accesses\n"));
+        assert!(report.ends_with("     0           4           1           2\n"));
+    }
+
+    #[test]
+    fn hits_are_not_classified() {
+        let mut classifier = classifier();
+        assert_eq!(classifier.access(0x40, true).1, Some(MissClass::Compulsory));
+        let (outcome, class) = classifier.access(0x44, false);
+        assert!(outcome.hit);
+        assert_eq!(class, None);
+        assert_eq!(classifier.totals().share(MissClass::Compulsory), 1.0);
+    }
+}
@@ -0,0 +1,446 @@
+//! Lockstep co-simulation of the RTL against the golden models.
+//!
+//! A `Dut` is anything that presents the cache's ports: the Verilated RTL in
+//! `verilator`, built only with the `verilator` feature (its module doc has
+//! the build and link steps), or another `CycleModel`. `Cosim` plays both
+//! the CPU of usage.rs, holding each request while `cpu_wait` is high, and
+//! the memory, answering the DUT's `mem_*` requests from any `Memory`. Every cycle the
+//! DUT's outputs are compared with the golden model's before the clock edge;
+//! the first difference stops the run with its cycle number.
+//!
+//! The DUT's ports also go through a `ProtocolChecker`, whose violations
+//! are collected rather than stopping the run.
+//!
+//! Outputs the RTL drives to `'x` are `None` in the golden model and are not
+//! compared, so a two-state simulator is free to put anything there.
+
+#[cfg(feature = "verilator")]
+pub mod verilator;
+
+use std::error::Error;
+use std::fmt;
+
+use super::fsm::{CacheOutputs, CpuRequest, CycleModel, State};
+use super::memory::Memory;
+use super::params::CacheParams;
+use super::protocol::{PortSample, ProtocolChecker};
+use super::system::AccessResult;
+
+#[cfg(feature = "verilator")]
+pub use verilator::VerilatedDut;
+
+/// Which SystemVerilog module a model was Verilated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RtlModule {
+    DirectMapped,
+    SetAssociative,
+}
+
+impl RtlModule {
+    /// The module implementing `params`: one way is `direct_mapped_cache`.
+    pub fn for_params(params: &CacheParams) -> Self {
+        if params.num_ways == 1 {
+            RtlModule::DirectMapped
+        } else {
+            RtlModule::SetAssociative
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RtlModule::DirectMapped => "direct_mapped_cache",
+            RtlModule::SetAssociative => "set_associative_cache",
+        }
+    }
+
+    /// The source file, relative to the directory above this one.
+    pub fn source(self) -> &'static str {
+        match self {
+            RtlModule::DirectMapped => "direct_mapped_64Kb.sv",
+            RtlModule::SetAssociative => "set_associative_64kb.sv",
+        }
+    }
+}
+
+/// A cache implementation driven port by port, as a testbench drives RTL.
+pub trait Dut {
+    /// Applies `cpu` to the inputs and returns the settled outputs.
+    fn eval(&mut self, cpu: &CpuRequest) -> CacheOutputs;
+    /// Applies the memory's response and clocks one rising edge. `cpu` is
+    /// the request last passed to `eval`.
+    fn clock(&mut self, cpu: &CpuRequest, mem_wait: bool, mem_rdata: &[u8]);
+    /// Pulses `rst_n`.
+    fn reset(&mut self);
+    /// `state_reg`, if the DUT exposes it.
+    fn state(&self) -> Option<State>;
+}
+
+impl<C: CycleModel> Dut for C {
+    fn eval(&mut self, cpu: &CpuRequest) -> CacheOutputs {
+        CycleModel::eval(self, cpu)
+    }
+
+    fn clock(&mut self, cpu: &CpuRequest, mem_wait: bool, mem_rdata: &[u8]) {
+        CycleModel::clock(self, cpu, mem_wait, mem_rdata)
+    }
+
+    fn reset(&mut self) {
+        CycleModel::reset(self)
+    }
+
+    fn state(&self) -> Option<State> {
+        Some(CycleModel::state(self))
+    }
+}
+
+/// A compared signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Signal {
+    CpuRdata,
+    CpuWait,
+    MemAddr,
+    MemRead,
+    MemWrite,
+    MemWdata,
+    StateReg,
+}
+
+impl Signal {
+    /// The port or register name in the SystemVerilog source.
+    pub fn name(self) -> &'static str {
+        match self {
+            Signal::CpuRdata => "cpu_rdata",
+            Signal::CpuWait => "cpu_wait",
+            Signal::MemAddr => "mem_addr",
+            Signal::MemRead => "mem_read",
+            Signal::MemWrite => "mem_write",
+            Signal::MemWdata => "mem_wdata",
+            Signal::StateReg => "state_reg",
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The first cycle the DUT disagreed with the golden model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub cycle: u64,
+    pub signal: Signal,
+    pub expected: String,
+    pub actual: String,
+    /// The request on the CPU inputs that cycle.
+    pub cpu: CpuRequest,
+    /// The golden model's state that cycle.
+    pub state: State,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cycle {}: {} is {}, expected {} (in {}, cpu_addr {:#x}{})",
+            self.cycle,
+            self.signal,
+            self.actual,
+            self.expected,
+            self.state,
+            self.cpu.cpu_addr,
+            if self.cpu.cpu_write {
+                ", write"
+            } else if self.cpu.cpu_read {
+                ", read"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+impl Error for Divergence {}
+
+fn hex_block(block: &[u8]) -> String {
+    let mut s = String::from("0x");
+    for byte in block.iter().rev() {
+        s.push_str(&format!("{:02x}", byte));
+    }
+    s
+}
+
+/// The first signal where `actual` differs from `expected`, as
+/// `(signal, expected, actual)`. `None` in `expected` matches anything.
+pub fn compare_outputs(
+    expected: &CacheOutputs,
+    actual: &CacheOutputs,
+) -> Option<(Signal, String, String)> {
+    fn word(v: Option<u64>) -> String {
+        v.map_or_else(|| "x".to_string(), |v| format!("{:#x}", v))
+    }
+    fn bit(v: bool) -> String {
+        (v as u8).to_string()
+    }
+
+    if expected.cpu_wait != actual.cpu_wait {
+        return Some((
+            Signal::CpuWait,
+            bit(expected.cpu_wait),
+            bit(actual.cpu_wait),
+        ));
+    }
+    if expected.mem_read != actual.mem_read {
+        return Some((
+            Signal::MemRead,
+            bit(expected.mem_read),
+            bit(actual.mem_read),
+        ));
+    }
+    if expected.mem_write != actual.mem_write {
+        return Some((
+            Signal::MemWrite,
+            bit(expected.mem_write),
+            bit(actual.mem_write),
+        ));
+    }
+    if expected.cpu_rdata.is_some() && expected.cpu_rdata != actual.cpu_rdata {
+        return Some((
+            Signal::CpuRdata,
+            word(expected.cpu_rdata),
+            word(actual.cpu_rdata),
+        ));
+    }
+    if expected.mem_addr.is_some() && expected.mem_addr != actual.mem_addr {
+        return Some((
+            Signal::MemAddr,
+            word(expected.mem_addr),
+            word(actual.mem_addr),
+        ));
+    }
+    if let Some(wdata) = &expected.mem_wdata {
+        if actual.mem_wdata.as_ref() != Some(wdata) {
+            return Some((
+                Signal::MemWdata,
+                hex_block(wdata),
+                actual
+                    .mem_wdata
+                    .as_deref()
+                    .map_or_else(|| "x".to_string(), hex_block),
+            ));
+        }
+    }
+    None
+}
+
+/// A golden model and a DUT run in lockstep against one memory.
+pub struct Cosim<G, D, M> {
+    pub golden: G,
+    pub dut: D,
+    pub memory: M,
+    cycle: u64,
+    protocol: ProtocolChecker,
+}
+
+impl<G: CycleModel, D: Dut, M: Memory> Cosim<G, D, M> {
+    /// Resets the DUT so both sides start from S_IDLE with every line
+    /// invalid.
+    pub fn new(mut golden: G, mut dut: D, memory: M) -> Self {
+        golden.reset();
+        dut.reset();
+        Cosim {
+            golden,
+            dut,
+            memory,
+            cycle: 0,
+            protocol: ProtocolChecker::new(),
+        }
+    }
+
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle
+    }
+
+    /// The handshake checks on the DUT's ports so far.
+    pub fn protocol(&self) -> &ProtocolChecker {
+        &self.protocol
+    }
+
+    /// Advances one clock with `cpu` on the CPU inputs. The memory sees the
+    /// DUT's outputs, which match the golden model's wherever it drives them.
+    pub fn step(&mut self, cpu: &CpuRequest) -> Result<CacheOutputs, Divergence> {
+        let state = self.golden.state();
+        let diverge = |signal, expected, actual| Divergence {
+            cycle: self.cycle,
+            signal,
+            expected,
+            actual,
+            cpu: *cpu,
+            state,
+        };
+        if let Some(actual) = self.dut.state() {
+            if actual != state {
+                return Err(diverge(
+                    Signal::StateReg,
+                    state.name().to_string(),
+                    actual.name().to_string(),
+                ));
+            }
+        }
+        let expected = self.golden.eval(cpu);
+        let out = self.dut.eval(cpu);
+        if let Some((signal, expected, actual)) = compare_outputs(&expected, &out) {
+            return Err(diverge(signal, expected, actual));
+        }
+        let resp = self.memory.cycle(&out);
+        self.protocol.check(
+            self.cycle,
+            &PortSample {
+                cpu: *cpu,
+                out: out.clone(),
+                mem_wait: resp.mem_wait,
+            },
+        );
+        self.golden.clock(cpu, resp.mem_wait, &resp.mem_rdata);
+        self.dut.clock(cpu, resp.mem_wait, &resp.mem_rdata);
+        self.cycle += 1;
+        Ok(out)
+    }
+
+    /// Runs one access to completion, as `System::access` does.
+    pub fn access(&mut self, cpu: CpuRequest) -> Result<AccessResult, Divergence> {
+        assert!(cpu.is_active(), "an access needs cpu_read or cpu_write");
+        while self.golden.state() != State::Idle {
+            self.step(&CpuRequest::idle())?;
+        }
+
+        let start = self.cycle;
+        let mut hit = true;
+        let mut write_back = false;
+        self.step(&cpu)?;
+        loop {
+            match self.golden.state() {
+                State::Allocate => hit = false,
+                State::WriteBack => write_back = true,
+                _ => {}
+            }
+            let out = self.step(&cpu)?;
+            if !out.cpu_wait {
+                return Ok(AccessResult {
+                    rdata: out.cpu_rdata,
+                    hit,
+                    write_back,
+                    cycles: self.cycle - start,
+                });
+            }
+        }
+    }
+
+    /// Runs every request in turn; returns how many completed.
+    pub fn run<I: IntoIterator<Item = CpuRequest>>(
+        &mut self,
+        requests: I,
+    ) -> Result<u64, Divergence> {
+        let mut count = 0;
+        for cpu in requests {
+            self.access(cpu)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::direct_mapped::DirectMappedCache;
+    use super::super::memory::MainMemory;
+    use super::*;
+
+    /// The golden model with bit 0 of `cpu_rdata` flipped.
+    struct FlipRdata(DirectMappedCache);
+
+    impl CycleModel for FlipRdata {
+        fn eval(&self, cpu: &CpuRequest) -> CacheOutputs {
+            let mut out = CycleModel::eval(&self.0, cpu);
+            out.cpu_rdata = out.cpu_rdata.map(|d| d ^ 1);
+            out
+        }
+
+        fn clock(&mut self, cpu: &CpuRequest, mem_wait: bool, mem_rdata: &[u8]) {
+            CycleModel::clock(&mut self.0, cpu, mem_wait, mem_rdata)
+        }
+
+        fn reset(&mut self) {
+            CycleModel::reset(&mut self.0)
+        }
+
+        fn state(&self) -> State {
+            CycleModel::state(&self.0)
+        }
+    }
+
+    fn golden() -> DirectMappedCache {
+        DirectMappedCache::new(CacheParams::default()).unwrap()
+    }
+
+    fn requests() -> Vec<CpuRequest> {
+        vec![
+            CpuRequest::write(0x40, 7),
+            CpuRequest::read(0x40),
+            // A conflict that writes 0x40 back, then reads it in again.
+            CpuRequest::read(0x1_0040),
+            CpuRequest::read(0x44),
+        ]
+    }
+
+    #[test]
+    fn golden_against_itself() {
+        let mut cosim = Cosim::new(golden(), golden(), MainMemory::new(32, 1));
+        assert_eq!(cosim.run(requests()), Ok(4));
+        // 6 + 2 + 8 + 6 cycles.
+        assert_eq!(cosim.cycle_count(), 22);
+        assert!(cosim.protocol().is_clean());
+    }
+
+    #[test]
+    fn reports_the_first_divergence() {
+        let mut cosim = Cosim::new(golden(), FlipRdata(golden()), MainMemory::new(32, 1));
+        // The write miss returns no data; the read hit's S_COMPARE_TAG in
+        // cycle 7 is the first to.
+        let err = cosim.run(requests()).unwrap_err();
+        assert_eq!((err.cycle, err.signal), (7, Signal::CpuRdata));
+        assert_eq!((err.expected.as_str(), err.actual.as_str()), ("0x7", "0x6"));
+        assert_eq!(err.state, State::CompareTag);
+        assert_eq!(
+            err.to_string(),
+            "cycle 7: cpu_rdata is 0x6, expected 0x7 (in S_COMPARE_TAG, cpu_addr 0x40, read)"
+        );
+    }
+
+    #[test]
+    fn compares_driven_outputs_only() {
+        let expected = CacheOutputs {
+            cpu_wait: true,
+            mem_write: true,
+            mem_addr: Some(0x40),
+            mem_wdata: Some(vec![1, 2]),
+            ..CacheOutputs::default()
+        };
+        let mut actual = CacheOutputs {
+            cpu_rdata: Some(0x1234),
+            ..expected.clone()
+        };
+        assert_eq!(compare_outputs(&expected, &actual), None);
+        actual.mem_wdata = Some(vec![1, 3]);
+        assert_eq!(
+            compare_outputs(&expected, &actual),
+            Some((Signal::MemWdata, "0x0201".into(), "0x0301".into()))
+        );
+        actual.mem_read = true;
+        assert_eq!(
+            compare_outputs(&expected, &actual).map(|d| d.0),
+            Some(Signal::MemRead)
+        );
+    }
+}
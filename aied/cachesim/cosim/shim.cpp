@@ -0,0 +1,176 @@
+// C interface to the Verilated caches, for cosim/verilator.rs.
+//
+// Verilate each cache with the parameters the Rust side is given, exposing
+// state_reg, then build this file against whichever models are present:
+//
+//   verilator --cc --public-flat-rw -Mdir obj_dm --prefix Vdirect_mapped_cache \
+//       ../direct_mapped_64Kb.sv
+//   verilator --cc --public-flat-rw -Mdir obj_sa --prefix Vset_associative_cache \
+//       ../set_associative_64kb.sv
+//   c++ -c -DCACHESIM_DIRECT_MAPPED -DCACHESIM_SET_ASSOCIATIVE \
+//       -Iobj_dm -Iobj_sa -I$VERILATOR_ROOT/include shim.cpp
+//
+// and link the result with both obj_*/V*__ALL.a archives and verilated.o.
+
+#include <cstddef>
+#include <cstdint>
+#include <cstring>
+#include <memory>
+
+#include <verilated.h>
+
+#ifdef CACHESIM_DIRECT_MAPPED
+#include "Vdirect_mapped_cache.h"
+#include "Vdirect_mapped_cache___024root.h"
+#endif
+#ifdef CACHESIM_SET_ASSOCIATIVE
+#include "Vset_associative_cache.h"
+#include "Vset_associative_cache___024root.h"
+#endif
+
+extern "C" {
+struct cachesim_outputs {
+    uint64_t cpu_rdata;
+    uint64_t mem_addr;
+    uint8_t cpu_wait;
+    uint8_t mem_read;
+    uint8_t mem_write;
+};
+}
+
+namespace {
+
+// Ports up to 64 bits wide are plain integers, wider ones VlWide words.
+template <typename T>
+void to_bytes(T value, uint8_t* out, size_t len) {
+    for (size_t i = 0; i < len && i < sizeof(T); i++) out[i] = (uint8_t)(value >> (8 * i));
+}
+
+template <std::size_t N>
+void to_bytes(const VlWide<N>& value, uint8_t* out, size_t len) {
+    for (size_t i = 0; i < len && i < 4 * N; i++) out[i] = (uint8_t)(value[i / 4] >> (8 * (i % 4)));
+}
+
+template <typename T>
+void from_bytes(T& value, const uint8_t* in, size_t len) {
+    value = 0;
+    for (size_t i = 0; i < len && i < sizeof(T); i++) value |= (T)in[i] << (8 * i);
+}
+
+template <std::size_t N>
+void from_bytes(VlWide<N>& value, const uint8_t* in, size_t len) {
+    for (size_t w = 0; w < N; w++) value[w] = 0;
+    for (size_t i = 0; i < len && i < 4 * N; i++) value[i / 4] |= (uint32_t)in[i] << (8 * (i % 4));
+}
+
+struct Dut {
+    virtual ~Dut() = default;
+    virtual void set_cpu(uint64_t addr, bool read, bool write, uint64_t wdata) = 0;
+    virtual void set_mem(bool wait, const uint8_t* rdata, size_t len) = 0;
+    virtual void outputs(cachesim_outputs* out, uint8_t* wdata, size_t len) = 0;
+    virtual void set_clk(bool clk) = 0;
+    virtual void set_rst_n(bool rst_n) = 0;
+    virtual void eval() = 0;
+    virtual int state() = 0;
+};
+
+template <typename V>
+struct Model : Dut {
+    std::unique_ptr<VerilatedContext> context{new VerilatedContext};
+    std::unique_ptr<V> top{new V{context.get()}};
+
+    void set_cpu(uint64_t addr, bool read, bool write, uint64_t wdata) override {
+        top->cpu_addr = addr;
+        top->cpu_read = read;
+        top->cpu_write = write;
+        top->cpu_wdata = wdata;
+    }
+    void set_mem(bool wait, const uint8_t* rdata, size_t len) override {
+        top->mem_wait = wait;
+        from_bytes(top->mem_rdata, rdata, len);
+    }
+    void outputs(cachesim_outputs* out, uint8_t* wdata, size_t len) override {
+        out->cpu_rdata = top->cpu_rdata;
+        out->mem_addr = top->mem_addr;
+        out->cpu_wait = top->cpu_wait;
+        out->mem_read = top->mem_read;
+        out->mem_write = top->mem_write;
+        to_bytes(top->mem_wdata, wdata, len);
+    }
+    void set_clk(bool clk) override { top->clk = clk; }
+    void set_rst_n(bool rst_n) override { top->rst_n = rst_n; }
+    void eval() override { top->eval(); }
+    int state() override;
+};
+
+#ifdef CACHESIM_DIRECT_MAPPED
+template <>
+int Model<Vdirect_mapped_cache>::state() {
+    return top->rootp->direct_mapped_cache__DOT__state_reg;
+}
+#endif
+#ifdef CACHESIM_SET_ASSOCIATIVE
+template <>
+int Model<Vset_associative_cache>::state() {
+    return top->rootp->set_associative_cache__DOT__state_reg;
+}
+#endif
+
+}  // namespace
+
+extern "C" {
+
+// module 0 is direct_mapped_cache, 1 set_associative_cache. Returns null
+// for a model this file was not built with.
+void* cachesim_dut_new(uint32_t module) {
+    switch (module) {
+#ifdef CACHESIM_DIRECT_MAPPED
+        case 0: return static_cast<Dut*>(new Model<Vdirect_mapped_cache>);
+#endif
+#ifdef CACHESIM_SET_ASSOCIATIVE
+        case 1: return static_cast<Dut*>(new Model<Vset_associative_cache>);
+#endif
+        default: return nullptr;
+    }
+}
+
+void cachesim_dut_free(void* dut) { delete static_cast<Dut*>(dut); }
+
+// Sets the CPU inputs with clk low and settles the outputs.
+void cachesim_dut_eval(void* handle, uint64_t addr, uint8_t read, uint8_t write, uint64_t wdata,
+                       cachesim_outputs* out, uint8_t* mem_wdata, size_t len) {
+    Dut* dut = static_cast<Dut*>(handle);
+    dut->set_cpu(addr, read, write, wdata);
+    dut->set_clk(false);
+    dut->eval();
+    dut->outputs(out, mem_wdata, len);
+}
+
+// Sets the memory inputs and clocks a rising then falling edge.
+void cachesim_dut_clock(void* handle, uint8_t mem_wait, const uint8_t* mem_rdata, size_t len) {
+    Dut* dut = static_cast<Dut*>(handle);
+    dut->set_mem(mem_wait, mem_rdata, len);
+    dut->eval();
+    dut->set_clk(true);
+    dut->eval();
+    dut->set_clk(false);
+    dut->eval();
+}
+
+// Asserts and releases rst_n with the clock low and the inputs idle.
+void cachesim_dut_reset(void* handle) {
+    Dut* dut = static_cast<Dut*>(handle);
+    dut->set_cpu(0, false, false, 0);
+    dut->set_mem(false, nullptr, 0);
+    dut->set_clk(false);
+    dut->set_rst_n(true);
+    dut->eval();
+    dut->set_rst_n(false);
+    dut->eval();
+    dut->set_rst_n(true);
+    dut->eval();
+}
+
+int32_t cachesim_dut_state(void* handle) { return static_cast<Dut*>(handle)->state(); }
+
+}  // extern "C"
@@ -0,0 +1,180 @@
+//! The Verilated RTL as a `Dut`, through the C interface in shim.cpp.
+//!
+//! Verilator fixes module parameters when it generates the model, so the
+//! `CacheParams` given here must match the `-G` overrides (or the source
+//! defaults) the model was built with. The shim's header comment gives the
+//! build steps.
+//!
+//! This module is only compiled with the `verilator` feature, and a build
+//! with it must link shim.cpp and at least one Verilated model: the
+//! `cachesim_dut_*` symbols are resolved at link time, not on first use.
+//!
+//! # Building
+//!
+//! Nothing here declares the feature or compiles the C++; the crate that
+//! includes `cachesim` does both. First build the models and the shim, from
+//! the `cosim` directory, with the steps in shim.cpp followed by:
+//!
+//! ```text
+//! make -C obj_dm -f Vdirect_mapped_cache.mk
+//! make -C obj_sa -f Vset_associative_cache.mk
+//! ```
+//!
+//! which leave `obj_*/V*__ALL.a` and `verilated.o` (Verilator 5 names them
+//! `libV*.a` and `libverilated.a`) next to `shim.o`. Then either pass the
+//! feature and the objects to `rustc` directly:
+//!
+//! ```text
+//! rustc --cfg 'feature="verilator"' ... \
+//!     -C link-arg=cosim/shim.o \
+//!     -C link-arg=cosim/obj_dm/Vdirect_mapped_cache__ALL.a \
+//!     -C link-arg=cosim/obj_sa/Vset_associative_cache__ALL.a \
+//!     -C link-arg=cosim/obj_dm/verilated.o \
+//!     -l dylib=stdc++
+//! ```
+//!
+//! or, under Cargo, declare `verilator = []` in the host crate's
+//! `[features]` and emit the same objects from its build script when
+//! `CARGO_FEATURE_VERILATOR` is set, one
+//! `cargo:rustc-link-arg=<object>` line each plus
+//! `cargo:rustc-link-lib=stdc++`. Link only the models the shim was
+//! compiled with; `VerilatedDut::new` returns `None` for the others.
+
+use std::ffi::c_void;
+
+use super::super::fsm::{CacheOutputs, CpuRequest, State};
+use super::super::params::{mask, CacheParams};
+use super::{Dut, RtlModule};
+
+/// The mirrored layout of `cachesim_outputs` in shim.cpp.
+#[repr(C)]
+#[derive(Default)]
+struct RawOutputs {
+    cpu_rdata: u64,
+    mem_addr: u64,
+    cpu_wait: u8,
+    mem_read: u8,
+    mem_write: u8,
+}
+
+extern "C" {
+    fn cachesim_dut_new(module: u32) -> *mut c_void;
+    fn cachesim_dut_free(dut: *mut c_void);
+    #[allow(clippy::too_many_arguments)]
+    fn cachesim_dut_eval(
+        dut: *mut c_void,
+        addr: u64,
+        read: u8,
+        write: u8,
+        wdata: u64,
+        out: *mut RawOutputs,
+        mem_wdata: *mut u8,
+        len: usize,
+    );
+    fn cachesim_dut_clock(dut: *mut c_void, mem_wait: u8, mem_rdata: *const u8, len: usize);
+    fn cachesim_dut_reset(dut: *mut c_void);
+    fn cachesim_dut_state(dut: *mut c_void) -> i32;
+}
+
+/// The shim's id for `module`.
+fn module_id(module: RtlModule) -> u32 {
+    match module {
+        RtlModule::DirectMapped => 0,
+        RtlModule::SetAssociative => 1,
+    }
+}
+
+/// One instance of a Verilated cache.
+pub struct VerilatedDut {
+    handle: *mut c_void,
+    module: RtlModule,
+    params: CacheParams,
+}
+
+impl VerilatedDut {
+    /// Instantiates the module for `params`; `None` if the linked shim was
+    /// built without that module.
+    pub fn new(params: CacheParams) -> Option<Self> {
+        let module = RtlModule::for_params(&params);
+        // SAFETY: the shim returns either null or a model owned by the
+        // handle until `cachesim_dut_free`.
+        let handle = unsafe { cachesim_dut_new(module_id(module)) };
+        if handle.is_null() {
+            return None;
+        }
+        Some(VerilatedDut {
+            handle,
+            module,
+            params,
+        })
+    }
+
+    pub fn module(&self) -> RtlModule {
+        self.module
+    }
+
+    pub fn params(&self) -> &CacheParams {
+        &self.params
+    }
+}
+
+impl Dut for VerilatedDut {
+    fn eval(&mut self, cpu: &CpuRequest) -> CacheOutputs {
+        let p = &self.params;
+        let mut raw = RawOutputs::default();
+        let mut wdata = vec![0; p.block_size_bytes as usize];
+        // SAFETY: `handle` is live, and both out-pointers are valid for the
+        // lengths passed.
+        unsafe {
+            cachesim_dut_eval(
+                self.handle,
+                cpu.cpu_addr & mask(p.addr_width),
+                cpu.cpu_read as u8,
+                cpu.cpu_write as u8,
+                cpu.cpu_wdata & mask(p.data_width),
+                &mut raw,
+                wdata.as_mut_ptr(),
+                wdata.len(),
+            );
+        }
+        // A two-state simulator has no 'x: every output has a value.
+        CacheOutputs {
+            cpu_rdata: Some(raw.cpu_rdata & mask(p.data_width)),
+            cpu_wait: raw.cpu_wait != 0,
+            mem_addr: Some(raw.mem_addr & mask(p.addr_width)),
+            mem_read: raw.mem_read != 0,
+            mem_write: raw.mem_write != 0,
+            mem_wdata: Some(wdata),
+        }
+    }
+
+    fn clock(&mut self, _cpu: &CpuRequest, mem_wait: bool, mem_rdata: &[u8]) {
+        // SAFETY: `handle` is live and `mem_rdata` is valid for its length.
+        unsafe {
+            cachesim_dut_clock(
+                self.handle,
+                mem_wait as u8,
+                mem_rdata.as_ptr(),
+                mem_rdata.len(),
+            );
+        }
+    }
+
+    fn reset(&mut self) {
+        // SAFETY: `handle` is live.
+        unsafe { cachesim_dut_reset(self.handle) }
+    }
+
+    fn state(&self) -> Option<State> {
+        // SAFETY: `handle` is live.
+        let encoding = unsafe { cachesim_dut_state(self.handle) };
+        u8::try_from(encoding).ok().and_then(State::from_encoding)
+    }
+}
+
+impl Drop for VerilatedDut {
+    fn drop(&mut self) {
+        // SAFETY: `handle` came from `cachesim_dut_new` and is freed once.
+        unsafe { cachesim_dut_free(self.handle) }
+    }
+}
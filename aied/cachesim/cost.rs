@@ -0,0 +1,243 @@
+//! Storage, area, leakage and access energy of a cache configuration.
+//!
+//! `ArrayBits` counts the bits in each storage array exactly as the RTL
+//! declares them, from the same localparams: `tag_array`, `valid_array`,
+//! `dirty_array` and `data_array` hold one entry per block, and the
+//! set-associative cache adds a `LRU_BITS = NUM_WAYS - 1` entry of
+//! `lru_bits` per set. The direct-mapped RTL has no `lru_bits`.
+//!
+//! `TechParams` turns bits into silicon with a deliberately simple model:
+//! area and leakage scale with bits stored, and an access costs energy per
+//! bit read or written. The defaults are round numbers of the right order
+//! for a modern SRAM macro, there to rank configurations against each
+//! other; calibrate them to a memory compiler before quoting absolute
+//! figures.
+
+use std::fmt;
+
+use super::functional::Stats;
+use super::params::CacheParams;
+
+/// Bits in each storage array of the RTL.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArrayBits {
+    pub tag_array: u64,
+    pub valid_array: u64,
+    pub dirty_array: u64,
+    pub data_array: u64,
+    pub lru_bits: u64,
+}
+
+impl ArrayBits {
+    pub fn for_params(params: &CacheParams) -> Self {
+        let blocks = params.num_blocks();
+        let lru_bits = if params.num_ways > 1 {
+            params.num_sets() * (params.num_ways as u64 - 1)
+        } else {
+            0
+        };
+        ArrayBits {
+            tag_array: blocks * params.tag_bits().max(0) as u64,
+            valid_array: blocks,
+            dirty_array: blocks,
+            data_array: blocks * params.block_size_bits(),
+            lru_bits,
+        }
+    }
+
+    /// Bits that are not data: tags, state and replacement bits.
+    pub fn overhead(&self) -> u64 {
+        self.tag_array + self.valid_array + self.dirty_array + self.lru_bits
+    }
+
+    pub fn total(&self) -> u64 {
+        self.data_array + self.overhead()
+    }
+}
+
+impl fmt::Display for ArrayBits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tag {}, valid {}, dirty {}, data {}, lru {}: {} bits",
+            self.tag_array,
+            self.valid_array,
+            self.dirty_array,
+            self.data_array,
+            self.lru_bits,
+            self.total()
+        )
+    }
+}
+
+/// Per-bit technology figures.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TechParams {
+    /// Area of one bitcell.
+    pub bitcell_um2: f64,
+    /// Fraction of a macro's area that is bitcells; the rest is decoders,
+    /// sense amplifiers and wiring.
+    pub array_efficiency: f64,
+    pub leakage_nw_per_bit: f64,
+    pub read_pj_per_bit: f64,
+    pub write_pj_per_bit: f64,
+    /// Whether a lookup reads the data of every way alongside the tags, as
+    /// the RTL's single-cycle compare does, or only the way that hits.
+    pub parallel_data_read: bool,
+}
+
+impl Default for TechParams {
+    fn default() -> Self {
+        TechParams {
+            bitcell_um2: 0.1,
+            array_efficiency: 0.7,
+            leakage_nw_per_bit: 0.05,
+            read_pj_per_bit: 0.005,
+            write_pj_per_bit: 0.006,
+            parallel_data_read: true,
+        }
+    }
+}
+
+/// What a configuration costs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostEstimate {
+    pub bits: ArrayBits,
+    pub area_mm2: f64,
+    pub leakage_mw: f64,
+    /// One lookup: tags, valid and dirty bits of every way in the set, the
+    /// data read, and the set's `lru_bits`.
+    pub lookup_pj: f64,
+    /// Writing one word into a line on a write hit.
+    pub word_write_pj: f64,
+    /// Reading a victim out for write-back, or writing a fill in.
+    pub block_read_pj: f64,
+    pub block_write_pj: f64,
+}
+
+impl CostEstimate {
+    pub fn new(params: &CacheParams, tech: &TechParams) -> Self {
+        let bits = ArrayBits::for_params(params);
+        let ways = params.num_ways as u64;
+        let block_bits = params.block_size_bits();
+        let state_bits = params.tag_bits().max(0) as u64 + 2;
+        let lru_bits = ways.saturating_sub(1);
+        let data_read = if tech.parallel_data_read {
+            ways * block_bits
+        } else {
+            block_bits
+        };
+        let lookup_bits = ways * state_bits + data_read + lru_bits;
+        CostEstimate {
+            bits,
+            area_mm2: bits.total() as f64 * tech.bitcell_um2 / tech.array_efficiency / 1e6,
+            leakage_mw: bits.total() as f64 * tech.leakage_nw_per_bit / 1e6,
+            lookup_pj: lookup_bits as f64 * tech.read_pj_per_bit,
+            word_write_pj: params.data_width as f64 * tech.write_pj_per_bit,
+            block_read_pj: block_bits as f64 * tech.read_pj_per_bit,
+            block_write_pj: (block_bits + state_bits) as f64 * tech.write_pj_per_bit,
+        }
+    }
+
+    /// Dynamic energy of a run, in nanojoules: a lookup per access, a word
+    /// write per store, a block read per write-back and a block write per
+    /// fill. A store that writes around the cache is still charged its word
+    /// write, which slightly overstates no-write-allocate.
+    pub fn dynamic_energy_nj(&self, stats: &Stats) -> f64 {
+        let pj = stats.accesses() as f64 * self.lookup_pj
+            + stats.writes as f64 * self.word_write_pj
+            + stats.write_backs as f64 * self.block_read_pj
+            + stats.fills as f64 * self.block_write_pj;
+        pj / 1000.0
+    }
+
+    /// Leakage over `cycles` at `clock_mhz`, in nanojoules.
+    pub fn leakage_energy_nj(&self, cycles: u64, clock_mhz: f64) -> f64 {
+        let seconds = cycles as f64 / (clock_mhz * 1e6);
+        self.leakage_mw * 1e-3 * seconds * 1e9
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn set_associative() -> CacheParams {
+        CacheParams {
+            num_ways: 4,
+            ..CacheParams::default()
+        }
+    }
+
+    #[test]
+    fn direct_mapped_64kb_bits() {
+        // 2048 blocks of 256 data bits with a 16-bit tag.
+        let bits = ArrayBits::for_params(&CacheParams::default());
+        assert_eq!(
+            bits,
+            ArrayBits {
+                tag_array: 2048 * 16,
+                valid_array: 2048,
+                dirty_array: 2048,
+                data_array: 2048 * 256,
+                lru_bits: 0,
+            }
+        );
+        assert_eq!(bits.overhead(), 36_864);
+        assert_eq!(bits.total(), 561_152);
+    }
+
+    #[test]
+    fn set_associative_64kb_bits() {
+        // 512 sets of four ways, an 18-bit tag and LRU_BITS = 3 per set.
+        let bits = ArrayBits::for_params(&set_associative());
+        assert_eq!(
+            bits,
+            ArrayBits {
+                tag_array: 2048 * 18,
+                valid_array: 2048,
+                dirty_array: 2048,
+                data_array: 2048 * 256,
+                lru_bits: 512 * 3,
+            }
+        );
+        assert_eq!(bits.total(), 566_784);
+    }
+
+    #[test]
+    fn estimates_from_the_bits() {
+        let tech = TechParams::default();
+        let dm = CostEstimate::new(&CacheParams::default(), &tech);
+        assert!(close(dm.area_mm2, 561_152.0 * 0.1 / 0.7 / 1e6));
+        assert!(close(dm.leakage_mw, 0.028_057_6));
+        // One way of tag, valid and dirty, and its 256 data bits.
+        assert!(close(dm.lookup_pj, 274.0 * 0.005));
+        assert!(close(dm.word_write_pj, 32.0 * 0.006));
+        assert!(close(dm.block_write_pj, 274.0 * 0.006));
+        // Four ways of 20 state bits and 256 data bits, and the LRU bits.
+        let sa = CostEstimate::new(&set_associative(), &tech);
+        assert!(close(sa.lookup_pj, 1107.0 * 0.005));
+        let serial = TechParams {
+            parallel_data_read: false,
+            ..tech
+        };
+        let sa = CostEstimate::new(&set_associative(), &serial);
+        assert!(close(sa.lookup_pj, 339.0 * 0.005));
+
+        let stats = Stats {
+            reads: 10,
+            writes: 2,
+            write_backs: 1,
+            fills: 3,
+            ..Stats::default()
+        };
+        let pj = 12.0 * 1.37 + 2.0 * 0.192 + 256.0 * 0.005 + 3.0 * 1.644;
+        assert!(close(dm.dynamic_energy_nj(&stats), pj / 1000.0));
+        // 1000 cycles at 100MHz is 10us.
+        assert!(close(dm.leakage_energy_nj(1000, 100.0), 0.280_576));
+    }
+}
@@ -0,0 +1,258 @@
+//! Cycle-accurate model of `direct_mapped_cache` (direct_mapped_64Kb.sv).
+//!
+//! The model follows the RTL one clock at a time: S_IDLE accepts a request,
+//! S_COMPARE_TAG answers hits, and a miss walks S_ALLOCATE, an optional
+//! S_WRITE_BACK of a dirty victim, and S_READ_FROM_MEM before comparing the
+//! tag again. `cpu_wait` and the `mem_*` signals are driven exactly as the
+//! `always_comb` block drives them.
+//!
+//! One deliberate difference: a write hit replaces exactly one `DATA_WIDTH`
+//! word. The RTL builds its merge mask only `DATA_WIDTH` bits wide, so for
+//! word offsets above zero the mask truncates to zero and the write data is
+//! ORed into the block instead. Running both side by side exposes this.
+
+use super::fsm::{read_word, write_word, CacheOutputs, CpuRequest, CycleModel, State};
+use super::params::{CacheParams, ParamError};
+
+#[derive(Debug, Clone)]
+pub struct DirectMappedCache {
+    params: CacheParams,
+    state_reg: State,
+    tag_array: Vec<u64>,
+    valid_array: Vec<bool>,
+    dirty_array: Vec<bool>,
+    /// `data_array`, one `BLOCK_SIZE_BYTES` slice per line, least
+    /// significant byte first.
+    data_array: Vec<u8>,
+}
+
+impl DirectMappedCache {
+    pub fn new(params: CacheParams) -> Result<Self, ParamError> {
+        params.validate()?;
+        if params.num_ways != 1 {
+            return Err(ParamError::Ways(params.num_ways));
+        }
+        let blocks = params.num_blocks() as usize;
+        Ok(DirectMappedCache {
+            params,
+            state_reg: State::Idle,
+            tag_array: vec![0; blocks],
+            valid_array: vec![false; blocks],
+            dirty_array: vec![false; blocks],
+            data_array: vec![0; blocks * params.block_size_bytes as usize],
+        })
+    }
+
+    pub fn params(&self) -> &CacheParams {
+        &self.params
+    }
+
+    pub fn is_valid(&self, index: usize) -> bool {
+        self.valid_array[index]
+    }
+
+    pub fn is_dirty(&self, index: usize) -> bool {
+        self.dirty_array[index]
+    }
+
+    pub fn tag(&self, index: usize) -> u64 {
+        self.tag_array[index]
+    }
+
+    pub fn block(&self, index: usize) -> &[u8] {
+        let bytes = self.params.block_size_bytes as usize;
+        &self.data_array[index * bytes..(index + 1) * bytes]
+    }
+
+    fn block_mut(&mut self, index: usize) -> &mut [u8] {
+        let bytes = self.params.block_size_bytes as usize;
+        &mut self.data_array[index * bytes..(index + 1) * bytes]
+    }
+
+    /// `hit = (tag_array[addr_index] == addr_tag) && valid_array[addr_index]`
+    pub fn hit(&self, addr: u64) -> bool {
+        let index = self.params.addr_index(addr);
+        self.valid_array[index] && self.tag_array[index] == self.params.addr_tag(addr)
+    }
+
+    fn next_state(&self, cpu: &CpuRequest, mem_wait: bool) -> State {
+        let index = self.params.addr_index(cpu.cpu_addr);
+        match self.state_reg {
+            State::Idle if cpu.is_active() => State::CompareTag,
+            State::Idle => State::Idle,
+            State::CompareTag if self.hit(cpu.cpu_addr) => State::Idle,
+            State::CompareTag => State::Allocate,
+            State::Allocate if self.valid_array[index] && self.dirty_array[index] => {
+                State::WriteBack
+            }
+            State::Allocate => State::ReadFromMem,
+            State::WriteBack if !mem_wait => State::ReadFromMem,
+            State::ReadFromMem if !mem_wait => State::CompareTag,
+            state => state,
+        }
+    }
+}
+
+impl CycleModel for DirectMappedCache {
+    fn eval(&self, cpu: &CpuRequest) -> CacheOutputs {
+        let p = &self.params;
+        let index = p.addr_index(cpu.cpu_addr);
+        let mut out = CacheOutputs {
+            cpu_wait: true,
+            ..Default::default()
+        };
+
+        match self.state_reg {
+            State::Idle => out.cpu_wait = false,
+            State::CompareTag => {
+                if self.hit(cpu.cpu_addr) {
+                    out.cpu_wait = false;
+                    if cpu.cpu_read {
+                        let word = p.addr_word_offset(cpu.cpu_addr);
+                        out.cpu_rdata =
+                            Some(read_word(self.block(index), word, p.data_bytes() as usize));
+                    }
+                }
+            }
+            State::Allocate => {}
+            State::WriteBack => {
+                out.mem_write = true;
+                out.mem_addr = Some(p.block_addr(self.tag_array[index], index));
+                out.mem_wdata = Some(self.block(index).to_vec());
+            }
+            State::ReadFromMem => {
+                out.mem_read = true;
+                out.mem_addr = Some(p.block_addr(p.addr_tag(cpu.cpu_addr), index));
+            }
+        }
+        out
+    }
+
+    fn clock(&mut self, cpu: &CpuRequest, mem_wait: bool, mem_rdata: &[u8]) {
+        let p = self.params;
+        let index = p.addr_index(cpu.cpu_addr);
+        let state_next = self.next_state(cpu, mem_wait);
+
+        // Case 1: Write HIT
+        if self.state_reg == State::CompareTag && cpu.cpu_write && self.hit(cpu.cpu_addr) {
+            let word = p.addr_word_offset(cpu.cpu_addr);
+            write_word(
+                self.block_mut(index),
+                word,
+                p.data_bytes() as usize,
+                cpu.cpu_wdata,
+            );
+            self.dirty_array[index] = true;
+            self.valid_array[index] = true;
+        }
+
+        // Case 2: READ MISS - data has been fetched from memory. A write miss
+        // leaves the line dirty and merges the word on the following hit.
+        if self.state_reg == State::ReadFromMem && !mem_wait {
+            self.block_mut(index).copy_from_slice(mem_rdata);
+            self.tag_array[index] = p.addr_tag(cpu.cpu_addr);
+            self.valid_array[index] = true;
+            self.dirty_array[index] = cpu.cpu_write;
+        }
+
+        // Case 3: WRITE_BACK has completed, clear dirty bit
+        if self.state_reg == State::WriteBack && !mem_wait {
+            self.dirty_array[index] = false;
+        }
+
+        self.state_reg = state_next;
+    }
+
+    fn reset(&mut self) {
+        self.state_reg = State::Idle;
+        self.valid_array.fill(false);
+        self.dirty_array.fill(false);
+    }
+
+    fn state(&self) -> State {
+        self.state_reg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::memory::MainMemory;
+    use super::super::system::System;
+    use super::*;
+
+    fn system(latency: u32) -> System<DirectMappedCache, MainMemory> {
+        System::new(
+            DirectMappedCache::new(CacheParams::default()).unwrap(),
+            MainMemory::new(32, latency),
+        )
+    }
+
+    #[test]
+    fn read_miss_then_hit() {
+        let mut system = system(3);
+        let block: Vec<u8> = (0..32).collect();
+        system.memory.write_block(0x1000, &block);
+        // S_IDLE, S_COMPARE_TAG, S_ALLOCATE, four cycles of S_READ_FROM_MEM
+        // and the S_COMPARE_TAG hit.
+        let miss = system.read(0x1008);
+        assert!(!miss.hit);
+        assert_eq!(miss.cycles, 8);
+        assert_eq!(miss.rdata, Some(0x0b0a_0908));
+        let hit = system.read(0x100c);
+        assert!(hit.hit);
+        assert_eq!(hit.cycles, 2);
+        assert_eq!(hit.rdata, Some(0x0f0e_0d0c));
+    }
+
+    #[test]
+    fn dirty_victim_is_written_back() {
+        let mut system = system(0);
+        assert_eq!(system.write(0x40, 0xdead_beef).cycles, 5);
+        assert!(system.cache.is_dirty(2));
+        system.record_cycles();
+        // 64KB further on maps to the same line.
+        let result = system.read(0x1_0040);
+        assert!(result.write_back);
+        assert_eq!(result.cycles, 6);
+        let records = system.take_cycle_records();
+        let states: Vec<State> = records.iter().map(|r| r.state).collect();
+        assert_eq!(
+            states,
+            [
+                State::Idle,
+                State::CompareTag,
+                State::Allocate,
+                State::WriteBack,
+                State::ReadFromMem,
+                State::CompareTag,
+            ]
+        );
+        assert_eq!(records[3].out.mem_addr, Some(0x40));
+        assert_eq!(records[4].out.mem_addr, Some(0x1_0040));
+        assert_eq!(
+            &system.memory.read_block(0x40)[..4],
+            [0xef, 0xbe, 0xad, 0xde]
+        );
+        assert!(!system.cache.is_dirty(2));
+        assert_eq!(system.cache.tag(2), 1);
+    }
+
+    #[test]
+    fn write_replaces_one_word() {
+        let params = CacheParams::default();
+        let mut system = System::new(
+            DirectMappedCache::new(params).unwrap(),
+            MainMemory::new(32, 0),
+        );
+        let block = vec![0xF0; 32];
+        system.memory.write_block(0x40, &block);
+        system.read(0x40);
+        system.write(0x44, 0x0102_0304);
+        system.write(0x40, 0x0506_0708);
+        // At word offset 1 the RTL's mask truncates to zero and it ORs the
+        // data in, giving 0xf1f2f3f4; the model replaces the word.
+        let mut expected = block;
+        expected[..8].copy_from_slice(&[0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(system.cache.block(2), &expected[..]);
+    }
+}
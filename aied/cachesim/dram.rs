@@ -0,0 +1,447 @@
+//! A simplified DRAM behind the cache's memory interface.
+//!
+//! Addresses map row : bank : column, so consecutive rows fall in different
+//! banks. Each bank has one row buffer. Under the open-row policy a row stays
+//! open after an access: the next access to it costs only tCAS, an access to
+//! a closed bank tRCD + tCAS, and one to another row tRP + tRCD + tCAS. Under
+//! the closed-row policy every access activates its row and precharges after
+//! itself, so it always costs tRCD + tCAS and leaves the bank busy for tRP.
+//! Every transfer adds `burst` cycles for the block.
+//!
+//! Every tREFI cycles a refresh closes all rows and blocks the device for
+//! tRFC. A refresh that falls due during an operation waits for it to finish;
+//! an operation that arrives during a refresh waits for the refresh.
+//!
+//! The cache sees all of this only as how long `mem_wait` stays high, which
+//! is what makes a write-back from S_WRITE_BACK delay the fill behind it.
+
+use std::fmt;
+
+use super::fsm::CacheOutputs;
+use super::memory::{MainMemory, MemResponse, Memory};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RowPolicy {
+    Open,
+    Closed,
+}
+
+impl fmt::Display for RowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RowPolicy::Open => "open",
+            RowPolicy::Closed => "closed",
+        })
+    }
+}
+
+/// Device timings, in cache clock cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DramTiming {
+    /// Activate to read or write.
+    pub t_rcd: u32,
+    /// Read or write to data.
+    pub t_cas: u32,
+    /// Precharge.
+    pub t_rp: u32,
+    /// Transferring one block.
+    pub burst: u32,
+    /// Refresh interval; 0 disables refresh.
+    pub t_refi: u32,
+    /// Refresh duration.
+    pub t_rfc: u32,
+}
+
+impl Default for DramTiming {
+    /// DDR4-2400-like figures against a 1.2GHz cache clock.
+    fn default() -> Self {
+        DramTiming {
+            t_rcd: 16,
+            t_cas: 16,
+            t_rp: 16,
+            burst: 4,
+            t_refi: 9360,
+            t_rfc: 420,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DramConfig {
+    pub banks: u32,
+    pub row_bytes: u64,
+    pub policy: RowPolicy,
+    pub timing: DramTiming,
+}
+
+impl Default for DramConfig {
+    fn default() -> Self {
+        DramConfig {
+            banks: 8,
+            row_bytes: 2048,
+            policy: RowPolicy::Open,
+            timing: DramTiming::default(),
+        }
+    }
+}
+
+impl DramConfig {
+    /// Checks the geometry can hold whole blocks of `block_bytes`.
+    pub fn validate(&self, block_bytes: usize) -> Result<(), String> {
+        if self.banks == 0 {
+            return Err("a DRAM needs at least one bank".to_string());
+        }
+        if self.row_bytes < block_bytes as u64 || !self.row_bytes.is_multiple_of(block_bytes as u64)
+        {
+            return Err(format!(
+                "row size {} is not a multiple of the {}-byte block",
+                self.row_bytes, block_bytes
+            ));
+        }
+        if self.timing.t_refi != 0 && self.timing.t_rfc >= self.timing.t_refi {
+            return Err(format!(
+                "tRFC {} leaves no time between refreshes every {} cycles",
+                self.timing.t_rfc, self.timing.t_refi
+            ));
+        }
+        Ok(())
+    }
+
+    /// The bank and row `addr` falls in.
+    pub fn map(&self, addr: u64) -> (usize, u64) {
+        let row = addr / self.row_bytes;
+        ((row % self.banks as u64) as usize, row / self.banks as u64)
+    }
+}
+
+/// Counters of what the device did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DramStats {
+    pub reads: u64,
+    pub writes: u64,
+    /// Accesses to the bank's open row.
+    pub row_hits: u64,
+    /// Accesses to a bank with no row open.
+    pub row_empty: u64,
+    /// Accesses that had to close another row first.
+    pub row_conflicts: u64,
+    pub refreshes: u64,
+    /// Cycles operations waited for a refresh to finish.
+    pub refresh_stall_cycles: u64,
+    /// Cycles operations waited for their bank to finish precharging.
+    pub precharge_stall_cycles: u64,
+}
+
+impl DramStats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn row_hit_ratio(&self) -> f64 {
+        if self.accesses() == 0 {
+            0.0
+        } else {
+            self.row_hits as f64 / self.accesses() as f64
+        }
+    }
+}
+
+impl fmt::Display for DramStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} reads, {} writes; rows: {} hit, {} empty, {} conflict; \
+             {} refreshes ({} cycles stalled), {} cycles stalled on precharge",
+            self.reads,
+            self.writes,
+            self.row_hits,
+            self.row_empty,
+            self.row_conflicts,
+            self.refreshes,
+            self.refresh_stall_cycles,
+            self.precharge_stall_cycles
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bank {
+    open_row: Option<u64>,
+    /// The first cycle the bank can be activated again.
+    ready_at: u64,
+}
+
+/// A banked DRAM implementing `Memory`, with its contents in a `MainMemory`.
+#[derive(Debug, Clone)]
+pub struct Dram {
+    config: DramConfig,
+    data: MainMemory,
+    banks: Vec<Bank>,
+    now: u64,
+    next_refresh: u64,
+    /// The cycle the refresh in progress, if any, ends.
+    refresh_until: u64,
+    /// The cycle the operation in flight completes in.
+    done_at: Option<u64>,
+    stats: DramStats,
+}
+
+impl Dram {
+    pub fn new(block_bytes: usize, config: DramConfig) -> Result<Self, String> {
+        config.validate(block_bytes)?;
+        Ok(Dram {
+            config,
+            data: MainMemory::new(block_bytes, 0),
+            banks: vec![Bank::default(); config.banks as usize],
+            now: 0,
+            next_refresh: config.timing.t_refi as u64,
+            refresh_until: 0,
+            done_at: None,
+            stats: DramStats::default(),
+        })
+    }
+
+    pub fn config(&self) -> &DramConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &DramStats {
+        &self.stats
+    }
+
+    /// The backing contents, for preloading or inspecting memory.
+    pub fn data(&self) -> &MainMemory {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut MainMemory {
+        &mut self.data
+    }
+
+    /// Starts any refresh that has fallen due, closing every row.
+    fn refresh_if_due(&mut self) {
+        let t = self.config.timing;
+        if t.t_refi == 0 {
+            return;
+        }
+        while self.now >= self.next_refresh {
+            self.refresh_until = self.now.max(self.refresh_until) + t.t_rfc as u64;
+            self.next_refresh += t.t_refi as u64;
+            self.stats.refreshes += 1;
+            for bank in &mut self.banks {
+                bank.open_row = None;
+            }
+        }
+    }
+
+    /// Schedules an operation on `addr` arriving now; returns the cycle it
+    /// completes in.
+    fn schedule(&mut self, addr: u64, write: bool) -> u64 {
+        let t = self.config.timing;
+        let (bank_index, row) = self.config.map(addr);
+        let bank = &mut self.banks[bank_index];
+
+        let after_refresh = self.now.max(self.refresh_until);
+        self.stats.refresh_stall_cycles += after_refresh - self.now;
+        let start = after_refresh.max(bank.ready_at);
+        self.stats.precharge_stall_cycles += start - after_refresh;
+
+        let access = (t.t_cas + t.burst) as u64;
+        let latency = match bank.open_row {
+            Some(open) if open == row => {
+                self.stats.row_hits += 1;
+                access
+            }
+            Some(_) => {
+                self.stats.row_conflicts += 1;
+                (t.t_rp + t.t_rcd) as u64 + access
+            }
+            None => {
+                self.stats.row_empty += 1;
+                t.t_rcd as u64 + access
+            }
+        };
+        let done = start + latency;
+        match self.config.policy {
+            RowPolicy::Open => bank.open_row = Some(row),
+            RowPolicy::Closed => {
+                // The access ends in cycle `done` and the precharge takes
+                // the `t_rp` cycles after it, so an access arriving at once
+                // waits `t_rp` as it would for an Open-row conflict.
+                bank.open_row = None;
+                bank.ready_at = done + 1 + t.t_rp as u64;
+            }
+        }
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+        done
+    }
+}
+
+impl Memory for Dram {
+    fn cycle(&mut self, out: &CacheOutputs) -> MemResponse {
+        let block_bytes = self.data.block_bytes();
+        let idle = MemResponse {
+            mem_wait: false,
+            mem_rdata: vec![0; block_bytes],
+        };
+        let response = match out.mem_addr {
+            Some(addr) if out.mem_read || out.mem_write => {
+                let done = match self.done_at {
+                    Some(done) => done,
+                    None => {
+                        self.refresh_if_due();
+                        self.schedule(addr, out.mem_write)
+                    }
+                };
+                if self.now < done {
+                    self.done_at = Some(done);
+                    MemResponse {
+                        mem_wait: true,
+                        ..idle
+                    }
+                } else {
+                    self.done_at = None;
+                    if out.mem_write {
+                        if let Some(data) = &out.mem_wdata {
+                            self.data.write_block(addr, data);
+                        }
+                        idle
+                    } else {
+                        MemResponse {
+                            mem_wait: false,
+                            mem_rdata: self.data.read_block(addr),
+                        }
+                    }
+                }
+            }
+            _ => {
+                self.done_at = None;
+                self.refresh_if_due();
+                idle
+            }
+        };
+        self.now += 1;
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dram(policy: RowPolicy, t_refi: u32) -> Dram {
+        let config = DramConfig {
+            policy,
+            timing: DramTiming {
+                t_refi,
+                t_rfc: 10,
+                ..DramTiming::default()
+            },
+            ..DramConfig::default()
+        };
+        Dram::new(32, config).unwrap()
+    }
+
+    /// Idles for `gap` cycles, then reads `addr`; returns the cycles
+    /// `mem_wait` stayed high.
+    fn read(dram: &mut Dram, gap: u32, addr: u64) -> u32 {
+        for _ in 0..gap {
+            dram.cycle(&CacheOutputs::default());
+        }
+        let out = CacheOutputs {
+            mem_read: true,
+            mem_addr: Some(addr),
+            ..CacheOutputs::default()
+        };
+        let mut wait = 0;
+        while dram.cycle(&out).mem_wait {
+            wait += 1;
+        }
+        wait
+    }
+
+    /// `mem_wait` cycles of reads to rows 0, 1 and 2 of bank 0, with `gap`
+    /// idle cycles before each after the first.
+    fn waits(policy: RowPolicy, gap: u32) -> Vec<u32> {
+        let mut dram = dram(policy, 0);
+        let row = dram.config().row_bytes * dram.config().banks as u64;
+        let mut waits = vec![read(&mut dram, 0, 0)];
+        waits.extend([row, 2 * row].map(|addr| read(&mut dram, gap, addr)));
+        waits
+    }
+
+    #[test]
+    fn precharge_costs_t_rp() {
+        // tRCD 16 + tCAS 16 + burst 4 to an empty bank, plus tRP 16 to close
+        // another row first.
+        assert_eq!(waits(RowPolicy::Open, 0), [36, 52, 52]);
+        assert_eq!(waits(RowPolicy::Open, 40), [36, 52, 52]);
+        assert_eq!(waits(RowPolicy::Closed, 0), [36, 52, 52]);
+        // Idle cycles hide the closed-row precharge, tRP of them entirely.
+        assert_eq!(waits(RowPolicy::Closed, 6), [36, 46, 46]);
+        assert_eq!(waits(RowPolicy::Closed, 16), [36, 36, 36]);
+    }
+
+    #[test]
+    fn open_row_hits_cost_t_cas() {
+        let mut dram = dram(RowPolicy::Open, 0);
+        assert_eq!(read(&mut dram, 0, 0x000), 36);
+        // Same row: tCAS 16 + burst 4.
+        assert_eq!(read(&mut dram, 0, 0x020), 20);
+        // The next row is in bank 1, which is still empty.
+        assert_eq!(read(&mut dram, 0, 0x800), 36);
+        assert_eq!(read(&mut dram, 0, 0x040), 20);
+        let stats = dram.stats();
+        assert_eq!(
+            (stats.row_hits, stats.row_empty, stats.row_conflicts),
+            (2, 2, 0)
+        );
+        assert_eq!(stats.row_hit_ratio(), 0.5);
+        assert_eq!(dram.config().map(0x4000), (0, 1));
+    }
+
+    #[test]
+    fn closed_row_never_hits() {
+        let mut dram = dram(RowPolicy::Closed, 0);
+        assert_eq!(read(&mut dram, 0, 0x000), 36);
+        // The same row again waits out the precharge and activates anew.
+        assert_eq!(read(&mut dram, 0, 0x020), 52);
+        assert_eq!(read(&mut dram, 16, 0x040), 36);
+        let stats = dram.stats();
+        assert_eq!(
+            (stats.row_hits, stats.row_empty, stats.row_conflicts),
+            (0, 3, 0)
+        );
+        assert_eq!(stats.precharge_stall_cycles, 16);
+    }
+
+    #[test]
+    fn refresh_closes_rows() {
+        let mut dram = dram(RowPolicy::Open, 100);
+        assert_eq!(read(&mut dram, 0, 0x000), 36);
+        // Cycle 37 onwards: the refresh due at 100 starts while idle and
+        // the read arriving at 102 waits 8 cycles for it, then activates.
+        assert_eq!(read(&mut dram, 65, 0x020), 8 + 36);
+        let stats = dram.stats();
+        assert_eq!((stats.refreshes, stats.refresh_stall_cycles), (1, 8));
+        assert_eq!(stats.row_empty, 2);
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        let config = DramConfig::default();
+        assert!(config.validate(32).is_ok());
+        assert!(DramConfig { banks: 0, ..config }.validate(32).is_err());
+        assert!(config.validate(4096).is_err());
+        let timing = DramTiming {
+            t_refi: 100,
+            t_rfc: 100,
+            ..DramTiming::default()
+        };
+        assert!(DramConfig { timing, ..config }.validate(32).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! The controller state machine and port bundles shared by the cycle models.
+
+use std::fmt;
+
+/// `state_t` from the RTL, in declaration order so `encoding()` matches the
+/// value of `state_reg` in a waveform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum State {
+    Idle,
+    CompareTag,
+    Allocate,
+    WriteBack,
+    ReadFromMem,
+}
+
+impl State {
+    pub const ALL: [State; 5] = [
+        State::Idle,
+        State::CompareTag,
+        State::Allocate,
+        State::WriteBack,
+        State::ReadFromMem,
+    ];
+
+    /// The 3-bit value of `state_reg`.
+    pub fn encoding(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_encoding(value: u8) -> Option<State> {
+        State::ALL.get(value as usize).copied()
+    }
+
+    /// The enumerator name used in the SystemVerilog source.
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Idle => "S_IDLE",
+            State::CompareTag => "S_COMPARE_TAG",
+            State::Allocate => "S_ALLOCATE",
+            State::WriteBack => "S_WRITE_BACK",
+            State::ReadFromMem => "S_READ_FROM_MEM",
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The CPU-side inputs of the cache for one cycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuRequest {
+    pub cpu_addr: u64,
+    pub cpu_read: bool,
+    pub cpu_write: bool,
+    pub cpu_wdata: u64,
+}
+
+impl CpuRequest {
+    pub fn read(addr: u64) -> Self {
+        CpuRequest {
+            cpu_addr: addr,
+            cpu_read: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn write(addr: u64, data: u64) -> Self {
+        CpuRequest {
+            cpu_addr: addr,
+            cpu_write: true,
+            cpu_wdata: data,
+            ..Default::default()
+        }
+    }
+
+    /// No request: both `cpu_read` and `cpu_write` low.
+    pub fn idle() -> Self {
+        CpuRequest::default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.cpu_read || self.cpu_write
+    }
+}
+
+/// The combinational outputs of the cache for one cycle. Signals the RTL
+/// drives to `'x` are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheOutputs {
+    pub cpu_rdata: Option<u64>,
+    pub cpu_wait: bool,
+    pub mem_addr: Option<u64>,
+    pub mem_read: bool,
+    pub mem_write: bool,
+    pub mem_wdata: Option<Vec<u8>>,
+}
+
+/// A cycle-accurate cache controller.
+///
+/// `eval` is the `always_comb` block: it computes this cycle's outputs from
+/// the registered state and the CPU inputs. `clock` is the `always_ff` block:
+/// it applies the rising edge, given what memory drove on `mem_wait` and
+/// `mem_rdata` during the cycle.
+pub trait CycleModel {
+    fn eval(&self, cpu: &CpuRequest) -> CacheOutputs;
+    fn clock(&mut self, cpu: &CpuRequest, mem_wait: bool, mem_rdata: &[u8]);
+    /// Asserts `rst_n`: back to `S_IDLE` with every line invalid.
+    fn reset(&mut self);
+    fn state(&self) -> State;
+}
+
+/// Reads the word at `word_offset` from a little-endian block.
+pub(crate) fn read_word(block: &[u8], word_offset: usize, data_bytes: usize) -> u64 {
+    let start = word_offset * data_bytes;
+    block[start..start + data_bytes]
+        .iter()
+        .rev()
+        .fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+/// Replaces the word at `word_offset` in a little-endian block.
+pub(crate) fn write_word(block: &mut [u8], word_offset: usize, data_bytes: usize, data: u64) {
+    let start = word_offset * data_bytes;
+    for (i, byte) in block[start..start + data_bytes].iter_mut().enumerate() {
+        *byte = (data >> (8 * i)) as u8;
+    }
+}
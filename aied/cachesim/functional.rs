@@ -0,0 +1,412 @@
+//! A tag-only functional cache for trace-driven studies.
+//!
+//! This drops the data arrays and the FSM and keeps what decides hits and
+//! misses: tags, valid and dirty bits, invalid-way-first victim selection
+//! and a `ReplacementPolicy`. Hit/miss outcomes and write-backs match the
+//! cycle models for the same policy, at a fraction of the cost per access.
+//!
+//! It also runs the write policies the RTL lacks (see `write_policy`) and
+//! counts the memory-side traffic each one causes.
+
+use std::fmt;
+
+use super::params::{CacheParams, ParamError};
+use super::plru;
+use super::policy::{PolicyKind, ReplacementPolicy};
+use super::write_policy::{BufferedStore, WriteBuffer, WriteHit, WriteMiss, WritePolicy};
+
+/// Counters accumulated over a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub reads: u64,
+    pub writes: u64,
+    pub read_misses: u64,
+    pub write_misses: u64,
+    /// Dirty victims written back to memory.
+    pub write_backs: u64,
+    /// Blocks read from memory to fill a line.
+    pub fills: u64,
+    /// Write-through and write-around stores that reached memory; a drained
+    /// write-buffer entry counts once however many stores it merged.
+    pub mem_stores: u64,
+    pub mem_read_bytes: u64,
+    pub mem_write_bytes: u64,
+}
+
+impl Stats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.read_misses + self.write_misses
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.accesses() - self.misses()
+    }
+
+    pub fn miss_ratio(&self) -> f64 {
+        if self.accesses() == 0 {
+            0.0
+        } else {
+            self.misses() as f64 / self.accesses() as f64
+        }
+    }
+
+    /// Write transactions on the memory bus: write-backs plus stores.
+    pub fn mem_writes(&self) -> u64 {
+        self.write_backs + self.mem_stores
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} accesses, {} misses ({:.4}), {} write-backs, {} stores, {}B written",
+            self.accesses(),
+            self.misses(),
+            self.miss_ratio(),
+            self.write_backs,
+            self.mem_stores,
+            self.mem_write_bytes
+        )
+    }
+}
+
+/// A block displaced by a fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Eviction {
+    pub block_addr: u64,
+    pub dirty: bool,
+}
+
+/// What one access did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub hit: bool,
+    pub set: usize,
+    /// Whether the block is in the cache afterwards; false only for a
+    /// no-write-allocate write miss.
+    pub allocated: bool,
+    /// The way hit, or the way filled on a miss.
+    pub way: usize,
+    /// Whether a miss filled an invalid way rather than the policy's victim.
+    pub filled_invalid: bool,
+    pub evicted: Option<Eviction>,
+}
+
+pub struct FunctionalCache {
+    params: CacheParams,
+    tags: Vec<u64>,
+    valid: Vec<bool>,
+    dirty: Vec<bool>,
+    policy: Box<dyn ReplacementPolicy>,
+    write_policy: WritePolicy,
+    write_buffer: Option<WriteBuffer>,
+    stats: Stats,
+}
+
+impl FunctionalCache {
+    pub fn new(params: CacheParams, policy: PolicyKind) -> Result<Self, ParamError> {
+        let built = policy.build(params.num_sets() as usize, params.num_ways as usize);
+        Self::with_policy(params, built)
+    }
+
+    /// Whether a cache can be built for `params`: the RTL's own limits, and
+    /// no more ways than the PLRU tree holds.
+    pub fn check(params: &CacheParams) -> Result<(), ParamError> {
+        params.validate()?;
+        if params.num_ways > plru::MAX_WAYS {
+            return Err(ParamError::Ways(params.num_ways));
+        }
+        Ok(())
+    }
+
+    /// A cache that replaces with `policy`, which must have been built for
+    /// `params.num_sets()` sets of `params.num_ways` ways.
+    pub fn with_policy(
+        params: CacheParams,
+        policy: Box<dyn ReplacementPolicy>,
+    ) -> Result<Self, ParamError> {
+        Self::check(&params)?;
+        let blocks = params.num_blocks() as usize;
+        Ok(FunctionalCache {
+            params,
+            tags: vec![0; blocks],
+            valid: vec![false; blocks],
+            dirty: vec![false; blocks],
+            policy,
+            write_policy: WritePolicy::default(),
+            write_buffer: None,
+            stats: Stats::default(),
+        })
+    }
+
+    /// Replaces the RTL's write-back, write-allocate behavior.
+    pub fn with_write_policy(mut self, write_policy: WritePolicy) -> Self {
+        self.write_policy = write_policy;
+        self.write_buffer = write_policy
+            .write_buffer
+            .map(|entries| WriteBuffer::new(entries, self.params.words_per_block()));
+        self
+    }
+
+    pub fn write_policy(&self) -> WritePolicy {
+        self.write_policy
+    }
+
+    pub fn write_buffer(&self) -> Option<&WriteBuffer> {
+        self.write_buffer.as_ref()
+    }
+
+    pub fn params(&self) -> &CacheParams {
+        &self.params
+    }
+
+    pub fn policy(&self) -> &dyn ReplacementPolicy {
+        self.policy.as_ref()
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    fn ways(&self) -> usize {
+        self.params.num_ways as usize
+    }
+
+    /// Whether `addr` is cached, without touching any state.
+    pub fn probe(&self, addr: u64) -> bool {
+        let set = self.params.addr_index(addr);
+        self.lookup(set, self.params.addr_tag(addr)).is_some()
+    }
+
+    fn lookup(&self, set: usize, tag: u64) -> Option<usize> {
+        let base = set * self.ways();
+        (0..self.ways()).find(|&w| self.valid[base + w] && self.tags[base + w] == tag)
+    }
+
+    /// Sends a write-through or write-around store towards memory.
+    fn store_to_memory(&mut self, block_addr: u64, word: usize) {
+        match &mut self.write_buffer {
+            Some(buffer) => {
+                if let Some(drained) = buffer.store(block_addr, word) {
+                    self.count_drain(&drained);
+                }
+            }
+            None => {
+                self.stats.mem_stores += 1;
+                self.stats.mem_write_bytes += self.params.data_bytes() as u64;
+            }
+        }
+    }
+
+    fn count_drain(&mut self, drained: &BufferedStore) {
+        self.stats.mem_stores += 1;
+        self.stats.mem_write_bytes += drained.word_count() as u64 * self.params.data_bytes() as u64;
+    }
+
+    /// Writes out everything still in the write buffer, so that `stats`
+    /// includes it.
+    pub fn flush_write_buffer(&mut self) {
+        if let Some(buffer) = &mut self.write_buffer {
+            for drained in buffer.drain_all() {
+                self.count_drain(&drained);
+            }
+        }
+    }
+
+    /// One access under the configured write policy; with the default that
+    /// is write-back, write-allocate, as the RTL performs it.
+    pub fn access(&mut self, addr: u64, write: bool) -> Outcome {
+        let p = self.params;
+        let set = p.addr_index(addr);
+        let tag = p.addr_tag(addr);
+        let base = set * self.ways();
+        let block_addr = p.block_addr(tag, set);
+        let word = p.addr_word_offset(addr);
+        let write_through = self.write_policy.hit == WriteHit::WriteThrough;
+        self.policy.on_access(block_addr);
+        if write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+
+        if let Some(way) = self.lookup(set, tag) {
+            self.policy.on_hit(set, way);
+            if write && write_through {
+                self.store_to_memory(block_addr, word);
+            } else {
+                self.dirty[base + way] |= write;
+            }
+            return Outcome {
+                hit: true,
+                set,
+                allocated: true,
+                way,
+                filled_invalid: false,
+                evicted: None,
+            };
+        }
+
+        if write {
+            self.stats.write_misses += 1;
+        } else {
+            self.stats.read_misses += 1;
+        }
+        if write && self.write_policy.miss == WriteMiss::NoWriteAllocate {
+            self.store_to_memory(block_addr, word);
+            return Outcome {
+                hit: false,
+                set,
+                allocated: false,
+                way: 0,
+                filled_invalid: false,
+                evicted: None,
+            };
+        }
+
+        // A buffered store to the block must reach memory before the fill.
+        if let Some(pending) = self.write_buffer.as_mut().and_then(|b| b.take(block_addr)) {
+            self.count_drain(&pending);
+        }
+        let (way, filled_invalid) = match (0..self.ways()).find(|&w| !self.valid[base + w]) {
+            Some(way) => (way, true),
+            None => (self.policy.victim(set), false),
+        };
+        let line = base + way;
+        let evicted = self.valid[line].then(|| Eviction {
+            block_addr: p.block_addr(self.tags[line], set),
+            dirty: self.dirty[line],
+        });
+        if self.valid[line] && self.dirty[line] {
+            self.stats.write_backs += 1;
+            self.stats.mem_write_bytes += p.block_size_bytes as u64;
+        }
+        self.stats.fills += 1;
+        self.stats.mem_read_bytes += p.block_size_bytes as u64;
+        self.tags[line] = tag;
+        self.valid[line] = true;
+        self.dirty[line] = write && !write_through;
+        self.policy.on_fill(set, way);
+        if write && write_through {
+            self.store_to_memory(block_addr, word);
+        }
+        Outcome {
+            hit: false,
+            set,
+            allocated: true,
+            way,
+            filled_invalid,
+            evicted,
+        }
+    }
+
+    /// Runs `(addr, is_write)` pairs through the cache.
+    pub fn run<I: IntoIterator<Item = (u64, bool)>>(&mut self, accesses: I) -> Stats {
+        for (addr, write) in accesses {
+            self.access(addr, write);
+        }
+        self.stats
+    }
+}
+
+/// Runs the same access stream through one cache per policy and returns each
+/// policy's name and counters, in the order given.
+pub fn compare_policies(
+    params: CacheParams,
+    policies: &[PolicyKind],
+    accesses: &[(u64, bool)],
+) -> Result<Vec<(String, Stats)>, ParamError> {
+    policies
+        .iter()
+        .map(|&kind| {
+            let mut cache = FunctionalCache::new(params, kind)?;
+            let stats = cache.run(accesses.iter().copied());
+            Ok((kind.to_string(), stats))
+        })
+        .collect()
+}
+
+/// Runs the same access stream under each write policy, with the write
+/// buffer flushed at the end, and returns the counters for each.
+pub fn compare_write_policies(
+    params: CacheParams,
+    policy: PolicyKind,
+    write_policies: &[WritePolicy],
+    accesses: &[(u64, bool)],
+) -> Result<Vec<(WritePolicy, Stats)>, ParamError> {
+    write_policies
+        .iter()
+        .map(|&write_policy| {
+            let mut cache = FunctionalCache::new(params, policy)?.with_write_policy(write_policy);
+            cache.run(accesses.iter().copied());
+            cache.flush_write_buffer();
+            Ok((write_policy, cache.stats))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::memory::MainMemory;
+    use super::super::rng::Rng;
+    use super::super::set_associative::SetAssociativeCache;
+    use super::super::system::System;
+    use super::*;
+
+    #[test]
+    fn matches_the_cycle_model() {
+        // 1KB of 4 ways: 8 sets, so a 4KB footprint keeps evicting.
+        let params = CacheParams {
+            cache_size_kb: 1,
+            ..CacheParams::set_associative()
+        };
+        for kind in [PolicyKind::Plru, PolicyKind::Lru, PolicyKind::Fifo] {
+            let mut functional = FunctionalCache::new(params, kind).unwrap();
+            let policy = kind.build(params.num_sets() as usize, 4);
+            let mut system = System::new(
+                SetAssociativeCache::with_policy(params, policy).unwrap(),
+                MainMemory::new(32, 0),
+            );
+            let mut rng = Rng::new(5);
+            for _ in 0..2000 {
+                let addr = rng.below(4096) & !3;
+                let write = rng.chance(0.3);
+                let outcome = functional.access(addr, write);
+                let result = if write {
+                    system.write(addr, 0)
+                } else {
+                    system.read(addr)
+                };
+                assert_eq!(outcome.hit, result.hit, "{} at {:#x}", kind, addr);
+                assert_eq!(
+                    outcome.evicted.is_some_and(|e| e.dirty),
+                    result.write_back,
+                    "{} at {:#x}",
+                    kind,
+                    addr
+                );
+            }
+            assert!(functional.stats().write_backs > 0);
+        }
+    }
+
+    #[test]
+    fn counts_write_back_traffic() {
+        let params = CacheParams::default();
+        let mut cache = FunctionalCache::new(params, PolicyKind::Lru).unwrap();
+        // Write miss, read hit, then a conflicting read evicts it dirty.
+        let stats = cache.run([(0x40, true), (0x44, false), (0x1_0040, false)]);
+        assert_eq!((stats.writes, stats.reads), (1, 2));
+        assert_eq!((stats.write_misses, stats.read_misses), (1, 1));
+        assert_eq!(stats.fills, 2);
+        assert_eq!(stats.write_backs, 1);
+        assert_eq!((stats.mem_read_bytes, stats.mem_write_bytes), (64, 32));
+        assert!(cache.probe(0x1_0040));
+        assert!(!cache.probe(0x40));
+    }
+}
@@ -0,0 +1,236 @@
+//! Main-memory models that sit on the `mem_*` side of a cache.
+//!
+//! As usage.rs requires, a memory holds `mem_wait` high while it works on a
+//! `mem_read` or `mem_write` and drops it in the cycle the operation
+//! completes; the cache samples `mem_rdata` in that same cycle.
+//!
+//! How long `mem_wait` stays high is set by a `Timing`: a fixed latency,
+//! separate read and write latencies, or a single open row buffer that makes
+//! accesses to the most recently opened row cheaper than the rest.
+
+use std::collections::HashMap;
+
+use super::fsm::CacheOutputs;
+
+/// What memory drives back to the cache during one cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemResponse {
+    pub mem_wait: bool,
+    pub mem_rdata: Vec<u8>,
+}
+
+/// A memory attached to the cache's memory interface.
+pub trait Memory {
+    /// Called once per cycle with the cache's combinational outputs.
+    fn cycle(&mut self, out: &CacheOutputs) -> MemResponse;
+}
+
+/// The number of cycles `mem_wait` stays high for each operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Fixed(u32),
+    ReadWrite {
+        read: u32,
+        write: u32,
+    },
+    /// One row of `row_bytes` is held open after each operation; an
+    /// operation to the open row takes `hit` cycles, any other `miss`.
+    RowBuffer {
+        row_bytes: u64,
+        hit: u32,
+        miss: u32,
+    },
+}
+
+/// A sparse block-addressed memory. Bytes that were never written read as
+/// zero.
+#[derive(Debug, Clone)]
+pub struct MainMemory {
+    block_bytes: usize,
+    timing: Timing,
+    blocks: HashMap<u64, Vec<u8>>,
+    /// Wait cycles left for the operation in flight, if any.
+    remaining: Option<u32>,
+    /// The row a `Timing::RowBuffer` holds open.
+    open_row: Option<u64>,
+}
+
+impl MainMemory {
+    /// `latency` is the number of cycles `mem_wait` stays high per operation.
+    pub fn new(block_bytes: usize, latency: u32) -> Self {
+        Self::with_timing(block_bytes, Timing::Fixed(latency))
+    }
+
+    pub fn with_timing(block_bytes: usize, timing: Timing) -> Self {
+        MainMemory {
+            block_bytes,
+            timing,
+            blocks: HashMap::new(),
+            remaining: None,
+            open_row: None,
+        }
+    }
+
+    pub fn block_bytes(&self) -> usize {
+        self.block_bytes
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// The latency of an operation starting now, opening its row if the
+    /// timing has a row buffer.
+    fn latency(&mut self, addr: u64, write: bool) -> u32 {
+        match self.timing {
+            Timing::Fixed(latency) => latency,
+            Timing::ReadWrite { read, write: w } => {
+                if write {
+                    w
+                } else {
+                    read
+                }
+            }
+            Timing::RowBuffer {
+                row_bytes,
+                hit,
+                miss,
+            } => {
+                let row = addr / row_bytes.max(1);
+                if self.open_row.replace(row) == Some(row) {
+                    hit
+                } else {
+                    miss
+                }
+            }
+        }
+    }
+
+    pub fn read_block(&self, addr: u64) -> Vec<u8> {
+        self.blocks
+            .get(&self.align(addr))
+            .cloned()
+            .unwrap_or_else(|| vec![0; self.block_bytes])
+    }
+
+    pub fn write_block(&mut self, addr: u64, data: &[u8]) {
+        assert_eq!(data.len(), self.block_bytes, "block size mismatch");
+        let addr = self.align(addr);
+        self.blocks.insert(addr, data.to_vec());
+    }
+
+    fn align(&self, addr: u64) -> u64 {
+        addr & !(self.block_bytes as u64 - 1)
+    }
+}
+
+impl Memory for MainMemory {
+    fn cycle(&mut self, out: &CacheOutputs) -> MemResponse {
+        let idle = MemResponse {
+            mem_wait: false,
+            mem_rdata: vec![0; self.block_bytes],
+        };
+        let addr = match out.mem_addr {
+            Some(addr) if out.mem_read || out.mem_write => addr,
+            _ => {
+                self.remaining = None;
+                return idle;
+            }
+        };
+
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => self.latency(addr, out.mem_write),
+        };
+        if remaining > 0 {
+            self.remaining = Some(remaining - 1);
+            return MemResponse {
+                mem_wait: true,
+                ..idle
+            };
+        }
+        self.remaining = None;
+
+        if out.mem_write {
+            if let Some(data) = &out.mem_wdata {
+                self.write_block(addr, data);
+            }
+            idle
+        } else {
+            MemResponse {
+                mem_wait: false,
+                mem_rdata: self.read_block(addr),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::direct_mapped::DirectMappedCache;
+    use super::super::params::CacheParams;
+    use super::super::system::System;
+    use super::*;
+
+    fn system(timing: Timing) -> System<DirectMappedCache, MainMemory> {
+        System::new(
+            DirectMappedCache::new(CacheParams::default()).unwrap(),
+            MainMemory::with_timing(32, timing),
+        )
+    }
+
+    #[test]
+    fn wait_then_respond() {
+        let mut memory = MainMemory::new(4, 2);
+        memory.write_block(0x13, &[1, 2, 3, 4]);
+        let read = CacheOutputs {
+            mem_read: true,
+            mem_addr: Some(0x10),
+            ..Default::default()
+        };
+        assert!(memory.cycle(&read).mem_wait);
+        assert!(memory.cycle(&read).mem_wait);
+        assert_eq!(
+            memory.cycle(&read),
+            MemResponse {
+                mem_wait: false,
+                mem_rdata: vec![1, 2, 3, 4],
+            }
+        );
+        assert_eq!(memory.read_block(0x20), [0; 4]);
+        // Dropping the request abandons it.
+        assert!(memory.cycle(&read).mem_wait);
+        assert!(!memory.cycle(&CacheOutputs::default()).mem_wait);
+        assert!(memory.cycle(&read).mem_wait);
+    }
+
+    #[test]
+    fn fixed_latency() {
+        // A clean miss is five cycles plus the latency of the fill.
+        let mut system = system(Timing::Fixed(4));
+        assert_eq!(system.read(0x40).cycles, 9);
+    }
+
+    #[test]
+    fn read_and_write_latencies() {
+        let mut system = system(Timing::ReadWrite { read: 2, write: 5 });
+        assert_eq!(system.write(0x40, 1).cycles, 7);
+        // S_WRITE_BACK waits 5 and S_READ_FROM_MEM 2 on top of 6 cycles.
+        let result = system.read(0x1_0040);
+        assert!(result.write_back);
+        assert_eq!(result.cycles, 13);
+    }
+
+    #[test]
+    fn row_buffer() {
+        let mut system = system(Timing::RowBuffer {
+            row_bytes: 1024,
+            hit: 1,
+            miss: 4,
+        });
+        assert_eq!(system.read(0x000).cycles, 9);
+        assert_eq!(system.read(0x020).cycles, 6);
+        assert_eq!(system.read(0x400).cycles, 9);
+        assert_eq!(system.read(0x040).cycles, 9);
+    }
+}
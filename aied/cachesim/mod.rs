@@ -0,0 +1,67 @@
+//! Rust models of the caches in this directory.
+//!
+//! `direct_mapped` and `set_associative` are cycle-accurate golden references
+//! for `direct_mapped_cache` and `set_associative_cache`; `plru` holds the
+//! latter's replacement bits; `policy` and `rrip` hold pluggable alternatives.
+//! `functional` is a fast tag-only cache for comparing policies over long
+//! access streams, and it also runs the alternative policies in
+//! `write_policy`; `opt` gives the offline optimum to compare against and
+//! `classify` splits its misses into compulsory, capacity and conflict.
+//! `stack_distance` gives the miss ratio of every capacity in one pass, and
+//! `sweep` simulates a grid of parameters and policies across all cores,
+//! pricing each point with the storage, area and energy model in `cost`.
+//! `system` connects a model to a `memory` the way usage.rs describes,
+//! honoring `cpu_wait` on the CPU side and `mem_wait` on the memory side,
+//! and counts where the cycles go; the memory's `Timing` sets its latency,
+//! or `dram` stands in with banks, row buffers and refresh.
+//! `protocol` checks the handshakes on both sides, cycle by cycle, and
+//! `transaction` rebuilds CPU accesses from those cycles, whether they come
+//! from a `System` or from an RTL waveform read by `vcd`.
+//! `trace` reads recorded access streams and replays them through either
+//! kind of model, and `cosim` runs the Verilated RTL, with the `verilator`
+//! feature, in lockstep with the golden models. `coverage` scores a run's
+//! FSM, access, victim and `lru_bits` bins and reports the holes, and
+//! `stimulus` generates the seeded constrained-random traffic to fill them;
+//! `shrink` cuts a failing run down to a short regression test. `svheader`
+//! reads the parameters and ports of either module from its .sv source and
+//! builds its model, and `tbgen` writes self-checking SystemVerilog
+//! testbenches for those without Rust in the loop.
+
+pub mod classify;
+pub mod cosim;
+pub mod cost;
+pub mod coverage;
+pub mod direct_mapped;
+pub mod dram;
+pub mod fsm;
+pub mod functional;
+pub mod memory;
+pub mod opt;
+pub mod params;
+pub mod plru;
+pub mod policy;
+pub mod protocol;
+pub mod rng;
+pub mod rrip;
+pub mod set_associative;
+pub mod shrink;
+pub mod stack_distance;
+pub mod stimulus;
+pub mod svheader;
+pub mod sweep;
+pub mod system;
+pub mod tbgen;
+pub mod trace;
+pub mod transaction;
+pub mod vcd;
+pub mod write_policy;
+
+pub use direct_mapped::DirectMappedCache;
+pub use fsm::{CacheOutputs, CpuRequest, CycleModel, State};
+pub use functional::{FunctionalCache, Stats};
+pub use memory::{MainMemory, Memory, Timing};
+pub use params::CacheParams;
+pub use policy::{PolicyKind, ReplacementPolicy};
+pub use set_associative::SetAssociativeCache;
+pub use system::{System, TimingStats};
+pub use write_policy::WritePolicy;
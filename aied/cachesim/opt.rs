@@ -0,0 +1,203 @@
+//! Belady's offline optimal replacement (MIN), as an upper bound.
+//!
+//! OPT evicts the block whose next use lies furthest in the future, which no
+//! real policy can know. Here it is a `ReplacementPolicy` that is handed the
+//! whole access stream up front and follows along through `on_access`, so it
+//! runs in the same `FunctionalCache` as every other policy and its miss
+//! count is directly comparable.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::functional::{compare_policies, FunctionalCache, Stats};
+use super::params::{CacheParams, ParamError};
+use super::policy::{PolicyKind, ReplacementPolicy};
+
+/// A line that is never used again.
+const NEVER: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+pub struct Belady {
+    ways: usize,
+    blocks: Vec<u64>,
+    /// For each position in `blocks`, where that block is used next.
+    next_use: Vec<usize>,
+    /// Accesses seen so far; the current one is `pos - 1`.
+    pos: usize,
+    /// The next use of the block in each line.
+    line_next: Vec<usize>,
+}
+
+impl Belady {
+    /// `blocks` is the block-aligned address of every access, in order.
+    pub fn new(sets: usize, ways: usize, blocks: Vec<u64>) -> Self {
+        let mut next_use = vec![NEVER; blocks.len()];
+        let mut seen = HashMap::new();
+        for (i, &block) in blocks.iter().enumerate().rev() {
+            if let Some(next) = seen.insert(block, i) {
+                next_use[i] = next;
+            }
+        }
+        Belady {
+            ways,
+            blocks,
+            next_use,
+            pos: 0,
+            line_next: vec![NEVER; sets * ways],
+        }
+    }
+
+    /// OPT for a geometry and the `(addr, is_write)` stream it will see.
+    pub fn for_accesses(params: &CacheParams, accesses: &[(u64, bool)]) -> Self {
+        let blocks = accesses
+            .iter()
+            .map(|&(addr, _)| params.block_addr(params.addr_tag(addr), params.addr_index(addr)))
+            .collect();
+        Belady::new(params.num_sets() as usize, params.num_ways as usize, blocks)
+    }
+
+    fn current_next_use(&self) -> usize {
+        self.pos
+            .checked_sub(1)
+            .map_or(NEVER, |current| self.next_use[current])
+    }
+}
+
+impl ReplacementPolicy for Belady {
+    fn name(&self) -> &str {
+        "opt"
+    }
+
+    fn on_access(&mut self, block_addr: u64) {
+        assert_eq!(
+            self.blocks.get(self.pos),
+            Some(&block_addr),
+            "OPT was given a different access stream than the cache sees"
+        );
+        self.pos += 1;
+    }
+
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.line_next[set * self.ways + way] = self.current_next_use();
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.on_hit(set, way);
+    }
+
+    /// The line used furthest in the future; never-again lines first.
+    fn victim(&mut self, set: usize) -> usize {
+        let lines = &self.line_next[set * self.ways..(set + 1) * self.ways];
+        (0..self.ways)
+            .max_by_key(|&w| (lines[w], usize::MAX - w))
+            .unwrap_or(0)
+    }
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.line_next.fill(NEVER);
+    }
+}
+
+/// Runs OPT and then each of `policies` over the same stream, OPT first.
+pub fn compare_with_opt(
+    params: CacheParams,
+    policies: &[PolicyKind],
+    accesses: &[(u64, bool)],
+) -> Result<Vec<(String, Stats)>, ParamError> {
+    let opt = Box::new(Belady::for_accesses(&params, accesses));
+    let mut cache = FunctionalCache::with_policy(params, opt)?;
+    let mut rows = vec![("opt".to_string(), cache.run(accesses.iter().copied()))];
+    rows.extend(compare_policies(params, policies, accesses)?);
+    Ok(rows)
+}
+
+/// A plain-text table of OPT, PLRU and LRU misses for one geometry, with each
+/// policy's misses relative to OPT.
+pub fn report(params: CacheParams, accesses: &[(u64, bool)]) -> Result<String, ParamError> {
+    let rows = compare_with_opt(params, &[PolicyKind::Plru, PolicyKind::Lru], accesses)?;
+    let opt_misses = rows[0].1.misses().max(1) as f64;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{}KB, {}-way, {}B blocks, {} accesses",
+        params.cache_size_kb,
+        params.num_ways,
+        params.block_size_bytes,
+        accesses.len()
+    );
+    let _ = writeln!(
+        out,
+        "{:<10} {:>12} {:>10} {:>8}",
+        "policy", "misses", "ratio", "vs opt"
+    );
+    for (name, stats) in &rows {
+        let _ = writeln!(
+            out,
+            "{:<10} {:>12} {:>10.4} {:>7.2}x",
+            name,
+            stats.misses(),
+            stats.miss_ratio(),
+            stats.misses() as f64 / opt_misses
+        );
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rng::Rng;
+    use super::*;
+
+    #[test]
+    fn evicts_the_furthest_next_use() {
+        // One set of four 256-byte ways.
+        let params = CacheParams {
+            cache_size_kb: 1,
+            block_size_bytes: 256,
+            num_ways: 4,
+            ..CacheParams::default()
+        };
+        let accesses: Vec<(u64, bool)> = [1, 2, 3, 4, 1, 2, 5, 1, 2, 3, 4, 5]
+            .iter()
+            .map(|&b| (b * 256, false))
+            .collect();
+        let opt = Box::new(Belady::for_accesses(&params, &accesses));
+        let mut cache = FunctionalCache::with_policy(params, opt).unwrap();
+        let evicted: Vec<Option<u64>> = accesses
+            .iter()
+            .map(|&(addr, write)| cache.access(addr, write).evicted.map(|e| e.block_addr))
+            .collect();
+        // 5 displaces 4, used last; 4 then displaces 1, never used again.
+        assert_eq!(evicted[6], Some(4 * 256));
+        assert_eq!(evicted[10], Some(256));
+        assert_eq!(cache.stats().misses(), 6);
+        let rows = compare_with_opt(params, &[PolicyKind::Lru], &accesses).unwrap();
+        assert_eq!(rows[1].0, "lru");
+        assert_eq!(rows[1].1.misses(), 8);
+    }
+
+    #[test]
+    fn no_policy_beats_opt() {
+        let params = CacheParams {
+            cache_size_kb: 2,
+            ..CacheParams::set_associative()
+        };
+        let mut rng = Rng::new(11);
+        let accesses: Vec<(u64, bool)> = (0..5000)
+            .map(|_| (rng.below(16 * 1024), rng.chance(0.2)))
+            .collect();
+        let rows = compare_with_opt(params, &PolicyKind::ALL, &accesses).unwrap();
+        let opt = rows[0].1.misses();
+        for (name, stats) in &rows[1..] {
+            assert!(
+                opt <= stats.misses(),
+                "{}: {} < {}",
+                name,
+                stats.misses(),
+                opt
+            );
+        }
+        assert!(report(params, &accesses).unwrap().contains("opt"));
+    }
+}
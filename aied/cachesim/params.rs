@@ -0,0 +1,267 @@
+//! Cache parameters and the localparams the RTL derives from them.
+//!
+//! Every derived width is computed the same way as the `localparam` blocks at
+//! the top of `direct_mapped_64Kb.sv` and `set_associative_64kb.sv`, so
+//! address decomposition in the models matches `addr_tag`, `addr_index` and
+//! `addr_word_offset` bit for bit.
+
+use std::error::Error;
+use std::fmt;
+
+/// SystemVerilog `$clog2`: the number of bits needed to index `x` items.
+pub fn clog2(x: u64) -> u32 {
+    if x <= 1 {
+        0
+    } else {
+        64 - (x - 1).leading_zeros()
+    }
+}
+
+/// The module parameters of `direct_mapped_cache` and
+/// `set_associative_cache`. A direct-mapped cache has one way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheParams {
+    pub addr_width: u32,
+    /// CPU data bus width in bits.
+    pub data_width: u32,
+    pub cache_size_kb: u32,
+    pub block_size_bytes: u32,
+    pub num_ways: u32,
+}
+
+impl Default for CacheParams {
+    /// The defaults of `direct_mapped_cache`: 32-bit buses, 64KB, 32-byte
+    /// blocks.
+    fn default() -> Self {
+        CacheParams {
+            addr_width: 32,
+            data_width: 32,
+            cache_size_kb: 64,
+            block_size_bytes: 32,
+            num_ways: 1,
+        }
+    }
+}
+
+impl CacheParams {
+    /// The defaults of `set_associative_cache`: as `default()`, but 4-way.
+    pub fn set_associative() -> Self {
+        CacheParams {
+            num_ways: 4,
+            ..Default::default()
+        }
+    }
+
+    pub fn cache_size_bytes(&self) -> u64 {
+        self.cache_size_kb as u64 * 1024
+    }
+
+    pub fn block_size_bits(&self) -> u64 {
+        self.block_size_bytes as u64 * 8
+    }
+
+    pub fn num_blocks(&self) -> u64 {
+        self.cache_size_bytes() / self.block_size_bytes as u64
+    }
+
+    pub fn num_sets(&self) -> u64 {
+        self.num_blocks() / self.num_ways.max(1) as u64
+    }
+
+    pub fn data_bytes(&self) -> u32 {
+        self.data_width / 8
+    }
+
+    pub fn offset_bits(&self) -> u32 {
+        clog2(self.block_size_bytes as u64)
+    }
+
+    pub fn index_bits(&self) -> u32 {
+        clog2(self.num_sets())
+    }
+
+    /// `TAG_BITS`; negative when the address is too narrow for the geometry.
+    pub fn tag_bits(&self) -> i64 {
+        self.addr_width as i64 - self.index_bits() as i64 - self.offset_bits() as i64
+    }
+
+    pub fn word_offset_bits(&self) -> u32 {
+        clog2((self.block_size_bytes / self.data_bytes().max(1)) as u64)
+    }
+
+    pub fn words_per_block(&self) -> usize {
+        (self.block_size_bytes / self.data_bytes()) as usize
+    }
+
+    /// Runs the RTL's `$fatal` sanity checks plus the limits of the model
+    /// itself (addresses and CPU words must fit in a `u64`).
+    pub fn validate(&self) -> Result<(), ParamError> {
+        if self.addr_width == 0 || self.addr_width > 64 {
+            return Err(ParamError::AddrWidth(self.addr_width));
+        }
+        if self.data_width == 0 || !self.data_width.is_multiple_of(8) || self.data_width > 64 {
+            return Err(ParamError::DataWidth(self.data_width));
+        }
+        if !self.block_size_bytes.is_power_of_two() {
+            return Err(ParamError::NotPowerOfTwo(
+                "BLOCK_SIZE_BYTES",
+                self.block_size_bytes as u64,
+            ));
+        }
+        if self.cache_size_kb == 0 || !self.num_blocks().is_power_of_two() {
+            return Err(ParamError::NotPowerOfTwo("NUM_BLOCKS", self.num_blocks()));
+        }
+        if !self.num_ways.is_power_of_two() || self.num_ways as u64 > self.num_blocks() {
+            return Err(ParamError::NotPowerOfTwo("NUM_WAYS", self.num_ways as u64));
+        }
+        if self.tag_bits() <= 0 {
+            return Err(ParamError::AddrTooNarrow);
+        }
+        if self.block_size_bytes < self.data_bytes() {
+            return Err(ParamError::BlockSmallerThanWord);
+        }
+        Ok(())
+    }
+
+    /// `addr_tag = cpu_addr[ADDR_WIDTH-1 : ADDR_WIDTH-TAG_BITS]`
+    pub fn addr_tag(&self, addr: u64) -> u64 {
+        let tag_bits = self.tag_bits().max(0) as u32;
+        (addr >> (self.addr_width - tag_bits)) & mask(tag_bits)
+    }
+
+    /// `addr_index = cpu_addr[OFFSET_BITS+INDEX_BITS-1 : OFFSET_BITS]`
+    pub fn addr_index(&self, addr: u64) -> usize {
+        ((addr >> self.offset_bits()) & mask(self.index_bits())) as usize
+    }
+
+    /// `addr_word_offset = cpu_addr[OFFSET_BITS-1 : $clog2(DATA_BYTES)]`
+    pub fn addr_word_offset(&self, addr: u64) -> usize {
+        let low = clog2(self.data_bytes() as u64);
+        ((addr & mask(self.offset_bits())) >> low) as usize
+    }
+
+    /// `{tag, index, {OFFSET_BITS{1'b0}}}`, as driven on `mem_addr`.
+    pub fn block_addr(&self, tag: u64, index: usize) -> u64 {
+        let addr = (tag << (self.index_bits() + self.offset_bits()))
+            | ((index as u64) << self.offset_bits());
+        addr & mask(self.addr_width)
+    }
+}
+
+/// A mask of the low `bits` bits.
+pub fn mask(bits: u32) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << bits) - 1
+    }
+}
+
+/// A parameter combination the RTL (or the model) refuses to elaborate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    AddrWidth(u32),
+    DataWidth(u32),
+    NotPowerOfTwo(&'static str, u64),
+    AddrTooNarrow,
+    BlockSmallerThanWord,
+    /// The model requires a different associativity; holds the one given.
+    Ways(u32),
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::AddrWidth(w) => write!(f, "ADDR_WIDTH {} is outside 1..=64", w),
+            ParamError::DataWidth(w) => {
+                write!(
+                    f,
+                    "DATA_WIDTH {} must be a multiple of 8 no wider than 64",
+                    w
+                )
+            }
+            ParamError::NotPowerOfTwo(name, v) => {
+                write!(f, "{} = {} is not a power of two", name, v)
+            }
+            ParamError::AddrTooNarrow => {
+                write!(f, "Address width is too small for the cache configuration.")
+            }
+            ParamError::BlockSmallerThanWord => write!(f, "Block size must be >= CPU data width."),
+            ParamError::Ways(n) => write!(f, "NUM_WAYS = {} is not supported by this model", n),
+        }
+    }
+}
+
+impl Error for ParamError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clog2_matches_sv() {
+        assert_eq!(clog2(0), 0);
+        assert_eq!(clog2(1), 0);
+        assert_eq!(clog2(2), 1);
+        assert_eq!(clog2(5), 3);
+        assert_eq!(clog2(2048), 11);
+    }
+
+    #[test]
+    fn rtl_localparams() {
+        let dm = CacheParams::default();
+        assert_eq!(dm.num_blocks(), 2048);
+        assert_eq!(
+            (dm.offset_bits(), dm.index_bits(), dm.tag_bits()),
+            (5, 11, 16)
+        );
+        assert_eq!(dm.word_offset_bits(), 3);
+        let sa = CacheParams::set_associative();
+        assert_eq!(sa.num_sets(), 512);
+        assert_eq!(
+            (sa.offset_bits(), sa.index_bits(), sa.tag_bits()),
+            (5, 9, 18)
+        );
+    }
+
+    #[test]
+    fn address_fields() {
+        let p = CacheParams::default();
+        let addr = 0x1234_5678;
+        assert_eq!(p.addr_tag(addr), 0x1234);
+        assert_eq!(p.addr_index(addr), 0x2b3);
+        assert_eq!(p.addr_word_offset(addr), 6);
+        assert_eq!(p.block_addr(0x1234, 0x2b3), 0x1234_5660);
+    }
+
+    #[test]
+    fn rejects_what_the_rtl_rejects() {
+        let p = CacheParams::default();
+        let bad = |q: CacheParams| q.validate().unwrap_err();
+        assert_eq!(
+            bad(CacheParams {
+                data_width: 12,
+                ..p
+            }),
+            ParamError::DataWidth(12)
+        );
+        assert_eq!(
+            bad(CacheParams { num_ways: 3, ..p }),
+            ParamError::NotPowerOfTwo("NUM_WAYS", 3)
+        );
+        assert_eq!(
+            bad(CacheParams {
+                addr_width: 16,
+                ..p
+            }),
+            ParamError::AddrTooNarrow
+        );
+        assert_eq!(
+            bad(CacheParams {
+                block_size_bytes: 2,
+                ..p
+            }),
+            ParamError::BlockSmallerThanWord
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Pseudo-LRU replacement bits for the set-associative model.
+//!
+//! Two flavors share one bit layout: node `i` of a binary tree over the ways
+//! is bit `i` of `lru_bits`, with the children of node `i` at `2i+1` and
+//! `2i+2`. A 0 sends the victim walk left and a 1 sends it right, so bit 0
+//! picks a half, bits 1 and 2 pick a quarter, and so on. That is exactly how
+//! `set_associative_cache` reads its 3-bit `lru_bits`.
+//!
+//! - `Plru::Rtl` copies the RTL update bit for bit. It does not walk the
+//!   tree; it loads one of four constants selected by `hit_way`. The
+//!   constants do not always point away from the way just used (after a hit
+//!   on way 2 the tree still selects way 2), so this flavor only exists for
+//!   4 ways.
+//! - `Plru::Tree` is the textbook update for any power-of-two associativity:
+//!   every node on the path to the accessed way is set to point away from it.
+
+/// `LRU_BITS = NUM_WAYS - 1` for the 4-way RTL cache.
+pub const LRU_BITS: u32 = 3;
+
+/// The largest associativity the tree fits in a `u32` of `lru_bits`.
+pub const MAX_WAYS: u32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plru {
+    /// The 4-way encoding of `set_associative_cache`.
+    Rtl,
+    /// A generalized tree-PLRU for 1 to 32 ways.
+    Tree,
+}
+
+impl Plru {
+    /// The RTL encoding for 4 ways, the generalized tree otherwise.
+    pub fn for_ways(ways: usize) -> Plru {
+        if ways == 4 {
+            Plru::Rtl
+        } else {
+            Plru::Tree
+        }
+    }
+
+    /// The victim the tree selects when every way of the set is valid.
+    pub fn victim(self, lru_bits: u32, ways: usize) -> usize {
+        match self {
+            Plru::Rtl => victim_way(lru_bits as u8),
+            Plru::Tree => tree_victim(lru_bits, ways),
+        }
+    }
+
+    /// The bits after an access to `way`.
+    pub fn next(self, lru_bits: u32, way: usize, ways: usize) -> u32 {
+        match self {
+            Plru::Rtl => lru_bits_next(way) as u32,
+            Plru::Tree => tree_touch(lru_bits, way, ways),
+        }
+    }
+}
+
+/// The victim the RTL selects when every way of the set is valid.
+pub fn victim_way(lru_bits: u8) -> usize {
+    if lru_bits & 0b001 == 0 {
+        // Follow left branch (ways 0/1)
+        ((lru_bits >> 1) & 1) as usize
+    } else {
+        // Follow right branch (ways 2/3)
+        0b10 | ((lru_bits >> 2) & 1) as usize
+    }
+}
+
+/// `lru_bits_next` for a given `hit_way`.
+pub fn lru_bits_next(hit_way: usize) -> u8 {
+    match hit_way & 0b11 {
+        0 => 0b110, // Point away from way 0
+        1 => 0b100, // Point away from way 1
+        2 => 0b011, // Point away from way 2
+        _ => 0b001, // Point away from way 3
+    }
+}
+
+/// The RTL's `hit_way` priority encoder, widened to any number of ways: the
+/// lowest set bit of `hit_oh`, and the last way when nothing hits.
+pub fn hit_way(hit_oh: u32, ways: usize) -> usize {
+    if hit_oh == 0 {
+        ways - 1
+    } else {
+        hit_oh.trailing_zeros() as usize
+    }
+}
+
+/// Walks the tree from the root to a leaf.
+pub fn tree_victim(lru_bits: u32, ways: usize) -> usize {
+    let mut node = 0;
+    while node < ways - 1 {
+        node = 2 * node + 1 + ((lru_bits >> node) & 1) as usize;
+    }
+    node - (ways - 1)
+}
+
+/// Points every node on the path to `way` away from it.
+pub fn tree_touch(lru_bits: u32, way: usize, ways: usize) -> u32 {
+    let mut bits = lru_bits;
+    let mut node = way + ways - 1;
+    while node > 0 {
+        let parent = (node - 1) / 2;
+        if node == 2 * parent + 1 {
+            bits |= 1 << parent;
+        } else {
+            bits &= !(1 << parent);
+        }
+        node = parent;
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtl_constants() {
+        // The `unique case (hit_way)` in set_associative_64kb.sv.
+        assert_eq!(lru_bits_next(0), 0b110);
+        assert_eq!(lru_bits_next(1), 0b100);
+        assert_eq!(lru_bits_next(2), 0b011);
+        assert_eq!(lru_bits_next(3), 0b001);
+    }
+
+    #[test]
+    fn rtl_victim_walk() {
+        // Bit 0 picks the half, bit 1 or bit 2 the way within it.
+        let victims: Vec<usize> = (0..8).map(victim_way).collect();
+        assert_eq!(victims, [0, 2, 1, 2, 0, 3, 1, 3]);
+    }
+
+    #[test]
+    fn rtl_keeps_way_2_after_a_hit_on_it() {
+        assert_eq!(victim_way(lru_bits_next(0)), 1);
+        assert_eq!(victim_way(lru_bits_next(1)), 0);
+        assert_eq!(victim_way(lru_bits_next(2)), 2);
+        assert_eq!(victim_way(lru_bits_next(3)), 2);
+    }
+
+    #[test]
+    fn rtl_fill_loads_the_way_3_encoding() {
+        // Nothing hits during S_READ_FROM_MEM, so the priority encoder falls
+        // through to way 3 and the fill loads 3'b001 for one cycle.
+        assert_eq!(hit_way(0, 4), 3);
+        assert_eq!(lru_bits_next(hit_way(0, 4)), 0b001);
+        assert_eq!(hit_way(0b0110, 4), 1);
+    }
+
+    #[test]
+    fn tree_points_away() {
+        assert_eq!(tree_touch(0, 2, 4), 0b100);
+        assert_eq!(tree_victim(0b100, 4), 0);
+        assert_eq!(tree_touch(0b100, 0, 4), 0b111);
+        assert_eq!(tree_victim(0b111, 4), 3);
+        for way in 0..8 {
+            assert_ne!(tree_victim(tree_touch(0, way, 8), 8), way);
+        }
+        assert_eq!(Plru::Tree.next(0b011, 2, 4), 0b110);
+        assert_eq!(Plru::Rtl.next(0b111, 2, 4), 0b011);
+    }
+}
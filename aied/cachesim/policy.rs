@@ -0,0 +1,425 @@
+//! Replacement policies for the set-associative models.
+//!
+//! A cache calls `on_hit` when a lookup hits, `victim` when a miss finds no
+//! invalid way in the set (invalid ways are always filled first, lowest
+//! first, as in the RTL), and `on_fill` once the new block is in place. The
+//! RTL completes every miss with a second tag compare that hits; that is part
+//! of the same access and is not reported as another `on_hit`. Before any of
+//! these, `on_access` announces the block each access touches, for policies
+//! that need to know where they are in a trace.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::plru::Plru;
+use super::rng::Rng;
+use super::rrip::{self, Brrip, Drrip, Srrip};
+
+pub trait ReplacementPolicy {
+    fn name(&self) -> &str;
+    /// Called once per access, before the lookup, with the block-aligned
+    /// address.
+    fn on_access(&mut self, _block_addr: u64) {}
+    fn on_hit(&mut self, set: usize, way: usize);
+    fn on_fill(&mut self, set: usize, way: usize);
+    /// Picks the way to replace in a full set. Called exactly once per
+    /// replacement, so policies may update their state here.
+    fn victim(&mut self, set: usize) -> usize;
+    /// Returns to the state after `rst_n`.
+    fn reset(&mut self);
+    /// The per-set replacement state as a bit vector, where the policy has
+    /// one that fits (`lru_bits` for PLRU). Zero otherwise.
+    fn state_bits(&self, _set: usize) -> u64 {
+        0
+    }
+}
+
+/// True LRU with a per-set recency stack.
+#[derive(Debug, Clone)]
+pub struct Lru {
+    ways: usize,
+    /// Per set, ways ordered from most to least recently used.
+    stacks: Vec<Vec<usize>>,
+}
+
+impl Lru {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Lru {
+            ways,
+            stacks: vec![(0..ways).collect(); sets],
+        }
+    }
+
+    fn touch(&mut self, set: usize, way: usize) {
+        let stack = &mut self.stacks[set];
+        let pos = stack
+            .iter()
+            .position(|&w| w == way)
+            .unwrap_or(self.ways - 1);
+        stack[..=pos].rotate_right(1);
+        stack[0] = way;
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn name(&self) -> &str {
+        "lru"
+    }
+
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        self.stacks[set][self.ways - 1]
+    }
+
+    fn reset(&mut self) {
+        for stack in &mut self.stacks {
+            *stack = (0..self.ways).collect();
+        }
+    }
+}
+
+/// First in, first out: hits do not change the order.
+#[derive(Debug, Clone)]
+pub struct Fifo {
+    ways: usize,
+    next: Vec<usize>,
+}
+
+impl Fifo {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Fifo {
+            ways,
+            next: vec![0; sets],
+        }
+    }
+}
+
+impl ReplacementPolicy for Fifo {
+    fn name(&self) -> &str {
+        "fifo"
+    }
+
+    fn on_hit(&mut self, _set: usize, _way: usize) {}
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.next[set] = (way + 1) % self.ways;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        self.next[set]
+    }
+
+    fn reset(&mut self) {
+        self.next.fill(0);
+    }
+
+    fn state_bits(&self, set: usize) -> u64 {
+        self.next[set] as u64
+    }
+}
+
+/// Uniformly random victims from a seeded generator.
+#[derive(Debug, Clone)]
+pub struct Random {
+    ways: usize,
+    seed: u64,
+    rng: Rng,
+}
+
+impl Random {
+    pub fn new(ways: usize, seed: u64) -> Self {
+        Random {
+            ways,
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl ReplacementPolicy for Random {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn on_hit(&mut self, _set: usize, _way: usize) {}
+
+    fn on_fill(&mut self, _set: usize, _way: usize) {}
+
+    fn victim(&mut self, _set: usize) -> usize {
+        self.rng.below(self.ways as u64) as usize
+    }
+
+    fn reset(&mut self) {
+        self.rng = Rng::new(self.seed);
+    }
+}
+
+/// Least frequently used. Counts start at one on fill; ties go to the
+/// lowest way.
+#[derive(Debug, Clone)]
+pub struct Lfu {
+    ways: usize,
+    counts: Vec<u64>,
+}
+
+impl Lfu {
+    pub fn new(sets: usize, ways: usize) -> Self {
+        Lfu {
+            ways,
+            counts: vec![0; sets * ways],
+        }
+    }
+}
+
+impl ReplacementPolicy for Lfu {
+    fn name(&self) -> &str {
+        "lfu"
+    }
+
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.counts[set * self.ways + way] += 1;
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.counts[set * self.ways + way] = 1;
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let counts = &self.counts[set * self.ways..(set + 1) * self.ways];
+        (0..self.ways).min_by_key(|&w| counts[w]).unwrap_or(0)
+    }
+
+    fn reset(&mut self) {
+        self.counts.fill(0);
+    }
+}
+
+/// Pseudo-LRU over `lru_bits`, in either flavor from `plru`. A fill updates
+/// the bits as a hit on the filled way would.
+#[derive(Debug, Clone)]
+pub struct PseudoLru {
+    flavor: Plru,
+    ways: usize,
+    bits: Vec<u32>,
+}
+
+impl PseudoLru {
+    pub fn new(flavor: Plru, sets: usize, ways: usize) -> Self {
+        PseudoLru {
+            flavor,
+            ways,
+            bits: vec![0; sets],
+        }
+    }
+
+    pub fn flavor(&self) -> Plru {
+        self.flavor
+    }
+}
+
+impl ReplacementPolicy for PseudoLru {
+    fn name(&self) -> &str {
+        match self.flavor {
+            Plru::Rtl => "plru",
+            Plru::Tree => "tree-plru",
+        }
+    }
+
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.bits[set] = self.flavor.next(self.bits[set], way, self.ways);
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.on_hit(set, way);
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        self.flavor.victim(self.bits[set], self.ways)
+    }
+
+    fn reset(&mut self) {
+        self.bits.fill(0);
+    }
+
+    fn state_bits(&self, set: usize) -> u64 {
+        self.bits[set] as u64
+    }
+}
+
+/// A policy by name, for command lines and parameter sweeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PolicyKind {
+    Lru,
+    Fifo,
+    Random {
+        seed: u64,
+    },
+    Lfu,
+    /// The RTL's PLRU for 4 ways, the tree-PLRU for any other associativity.
+    Plru,
+    TreePlru,
+    /// The RRIP family with 2-bit RRPVs; see `rrip`.
+    Srrip,
+    Brrip {
+        seed: u64,
+    },
+    Drrip {
+        seed: u64,
+    },
+}
+
+impl PolicyKind {
+    pub const ALL: [PolicyKind; 9] = [
+        PolicyKind::Lru,
+        PolicyKind::Fifo,
+        PolicyKind::Random { seed: 1 },
+        PolicyKind::Lfu,
+        PolicyKind::Plru,
+        PolicyKind::TreePlru,
+        PolicyKind::Srrip,
+        PolicyKind::Brrip { seed: 1 },
+        PolicyKind::Drrip { seed: 1 },
+    ];
+
+    pub fn build(self, sets: usize, ways: usize) -> Box<dyn ReplacementPolicy> {
+        match self {
+            PolicyKind::Lru => Box::new(Lru::new(sets, ways)),
+            PolicyKind::Fifo => Box::new(Fifo::new(sets, ways)),
+            PolicyKind::Random { seed } => Box::new(Random::new(ways, seed)),
+            PolicyKind::Lfu => Box::new(Lfu::new(sets, ways)),
+            PolicyKind::Plru => Box::new(PseudoLru::new(Plru::for_ways(ways), sets, ways)),
+            PolicyKind::TreePlru => Box::new(PseudoLru::new(Plru::Tree, sets, ways)),
+            PolicyKind::Srrip => Box::new(Srrip::new(sets, ways, rrip::RRPV_BITS)),
+            PolicyKind::Brrip { seed } => Box::new(Brrip::new(sets, ways, rrip::RRPV_BITS, seed)),
+            PolicyKind::Drrip { seed } => Box::new(Drrip::new(sets, ways, rrip::RRPV_BITS, seed)),
+        }
+    }
+}
+
+impl fmt::Display for PolicyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyKind::Lru => f.write_str("lru"),
+            PolicyKind::Fifo => f.write_str("fifo"),
+            PolicyKind::Random { seed } => write!(f, "random:{}", seed),
+            PolicyKind::Lfu => f.write_str("lfu"),
+            PolicyKind::Plru => f.write_str("plru"),
+            PolicyKind::TreePlru => f.write_str("tree-plru"),
+            PolicyKind::Srrip => f.write_str("srrip"),
+            PolicyKind::Brrip { seed } => write!(f, "brrip:{}", seed),
+            PolicyKind::Drrip { seed } => write!(f, "drrip:{}", seed),
+        }
+    }
+}
+
+impl FromStr for PolicyKind {
+    type Err = String;
+
+    /// Accepts the names `Display` produces; a seeded policy named without
+    /// a seed gets 1.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let seed = || match arg {
+            Some(arg) => arg.parse().map_err(|_| format!("bad seed in {:?}", s)),
+            None => Ok(1),
+        };
+        let kind = match name.to_ascii_lowercase().as_str() {
+            "lru" => PolicyKind::Lru,
+            "fifo" => PolicyKind::Fifo,
+            "random" => return Ok(PolicyKind::Random { seed: seed()? }),
+            "lfu" => PolicyKind::Lfu,
+            "plru" => PolicyKind::Plru,
+            "tree-plru" => PolicyKind::TreePlru,
+            "srrip" => PolicyKind::Srrip,
+            "brrip" => return Ok(PolicyKind::Brrip { seed: seed()? }),
+            "drrip" => return Ok(PolicyKind::Drrip { seed: seed()? }),
+            _ => return Err(format!("unknown replacement policy {:?}", s)),
+        };
+        match arg {
+            Some(_) => Err(format!("{} takes no argument", name)),
+            None => Ok(kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills ways 0 to 3 of set 0 in order.
+    fn filled(kind: PolicyKind) -> Box<dyn ReplacementPolicy> {
+        let mut policy = kind.build(2, 4);
+        for way in 0..4 {
+            policy.on_fill(0, way);
+        }
+        policy
+    }
+
+    #[test]
+    fn lru_evicts_least_recent() {
+        let mut lru = filled(PolicyKind::Lru);
+        assert_eq!(lru.victim(0), 0);
+        lru.on_hit(0, 0);
+        lru.on_hit(0, 2);
+        assert_eq!(lru.victim(0), 1);
+        lru.on_fill(0, 1);
+        assert_eq!(lru.victim(0), 3);
+        // Set 1 is untouched.
+        assert_eq!(lru.victim(1), 3);
+    }
+
+    #[test]
+    fn fifo_ignores_hits() {
+        let mut fifo = filled(PolicyKind::Fifo);
+        fifo.on_hit(0, 0);
+        assert_eq!(fifo.victim(0), 0);
+        fifo.on_fill(0, 0);
+        assert_eq!(fifo.victim(0), 1);
+        assert_eq!(fifo.state_bits(0), 1);
+    }
+
+    #[test]
+    fn lfu_evicts_least_frequent() {
+        let mut lfu = filled(PolicyKind::Lfu);
+        lfu.on_hit(0, 0);
+        lfu.on_hit(0, 1);
+        lfu.on_hit(0, 1);
+        lfu.on_hit(0, 3);
+        assert_eq!(lfu.victim(0), 2);
+        lfu.on_fill(0, 2);
+        lfu.on_hit(0, 2);
+        // Ways 0, 2 and 3 tie at two; the lowest goes.
+        assert_eq!(lfu.victim(0), 0);
+    }
+
+    #[test]
+    fn random_replays_after_reset() {
+        let mut random = filled(PolicyKind::Random { seed: 9 });
+        let first: Vec<usize> = (0..32).map(|_| random.victim(0)).collect();
+        assert!(first.iter().all(|&w| w < 4));
+        random.reset();
+        let again: Vec<usize> = (0..32).map(|_| random.victim(0)).collect();
+        assert_eq!(first, again);
+    }
+
+    #[test]
+    fn kinds_round_trip() {
+        for kind in PolicyKind::ALL {
+            assert_eq!(kind.to_string().parse::<PolicyKind>(), Ok(kind));
+        }
+        assert_eq!("DRRIP".parse(), Ok(PolicyKind::Drrip { seed: 1 }));
+        assert!("lru:3".parse::<PolicyKind>().is_err());
+        assert!("random:x".parse::<PolicyKind>().is_err());
+        assert!("mru".parse::<PolicyKind>().is_err());
+    }
+}
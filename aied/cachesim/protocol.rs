@@ -0,0 +1,333 @@
+//! A monitor for the CPU and memory handshakes usage.rs lays down.
+//!
+//! The checker sees only port values, one sample per cycle, so it works the
+//! same on `System` cycle records, co-simulation and waveforms. The rules:
+//!
+//! - the CPU never asserts `cpu_read` and `cpu_write` together;
+//! - once the cache accepts a request, in the cycle it sees it from idle,
+//!   the CPU holds it until the cycle `cpu_wait` drops again: `cpu_addr`,
+//!   `cpu_read`, `cpu_write` and, for a write, `cpu_wdata` stay as they
+//!   were. `S_IDLE` accepts with `cpu_wait` low, and `S_COMPARE_TAG` samples
+//!   the request again the cycle after;
+//! - the cache never asserts `mem_read` and `mem_write` together, and drives
+//!   `mem_addr` (and `mem_wdata` for a write) whenever it asserts either;
+//! - while memory holds `mem_wait` the cache holds its request, `mem_wdata`
+//!   included, until the cycle memory drops `mem_wait`;
+//! - memory asserts `mem_wait` only against a request;
+//! - optionally, no access stalls for longer than a given number of cycles.
+
+use std::fmt;
+
+use super::fsm::{CacheOutputs, CpuRequest};
+use super::system::CycleRecord;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ViolationKind {
+    CpuReadAndWrite,
+    CpuRequestChanged,
+    CpuRequestWithdrawn,
+    MemReadAndWrite,
+    MemAddrUndriven,
+    MemWdataUndriven,
+    MemRequestChanged,
+    MemWdataChanged,
+    MemWaitWithoutRequest,
+    StallTimeout,
+}
+
+impl ViolationKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ViolationKind::CpuReadAndWrite => "cpu-read-and-write",
+            ViolationKind::CpuRequestChanged => "cpu-request-changed",
+            ViolationKind::CpuRequestWithdrawn => "cpu-request-withdrawn",
+            ViolationKind::MemReadAndWrite => "mem-read-and-write",
+            ViolationKind::MemAddrUndriven => "mem-addr-undriven",
+            ViolationKind::MemWdataUndriven => "mem-wdata-undriven",
+            ViolationKind::MemRequestChanged => "mem-request-changed",
+            ViolationKind::MemWdataChanged => "mem-wdata-changed",
+            ViolationKind::MemWaitWithoutRequest => "mem-wait-without-request",
+            ViolationKind::StallTimeout => "stall-timeout",
+        }
+    }
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub cycle: u64,
+    pub kind: ViolationKind,
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cycle {}: {}: {}", self.cycle, self.kind, self.detail)
+    }
+}
+
+/// The ports of one cycle, as far as the protocol is concerned.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortSample {
+    pub cpu: CpuRequest,
+    pub out: CacheOutputs,
+    pub mem_wait: bool,
+}
+
+impl From<&CycleRecord> for PortSample {
+    fn from(record: &CycleRecord) -> Self {
+        PortSample {
+            cpu: record.cpu,
+            out: record.out.clone(),
+            mem_wait: record.mem_wait,
+        }
+    }
+}
+
+fn describe(cpu: &CpuRequest) -> String {
+    match (cpu.cpu_read, cpu.cpu_write) {
+        (false, false) => "no request".to_string(),
+        (true, false) => format!("read {:#x}", cpu.cpu_addr),
+        (false, true) => format!("write {:#x} <- {:#x}", cpu.cpu_addr, cpu.cpu_wdata),
+        (true, true) => format!("read and write {:#x}", cpu.cpu_addr),
+    }
+}
+
+fn describe_mem(out: &CacheOutputs) -> String {
+    let addr = out
+        .mem_addr
+        .map_or_else(|| "x".to_string(), |a| format!("{:#x}", a));
+    match (out.mem_read, out.mem_write) {
+        (false, false) => "no request".to_string(),
+        (true, false) => format!("mem_read {}", addr),
+        (false, true) => format!("mem_write {}", addr),
+        (true, true) => format!("mem_read and mem_write {}", addr),
+    }
+}
+
+/// Checks a stream of per-cycle samples, collecting every violation.
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolChecker {
+    max_stall: Option<u64>,
+    previous: Option<PortSample>,
+    /// Consecutive cycles `cpu_wait` has been high with a request held.
+    stall: u64,
+    /// A request was accepted before this cycle and has not completed.
+    accepted: bool,
+    violations: Vec<Violation>,
+}
+
+impl ProtocolChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also flags any access stalled on `cpu_wait` for more than `cycles`
+    /// cycles in a row, once per stall.
+    pub fn with_max_stall(cycles: u64) -> Self {
+        ProtocolChecker {
+            max_stall: Some(cycles),
+            ..Self::default()
+        }
+    }
+
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Checks the ports of `cycle`, which follows the last cycle checked.
+    /// Returns how many violations it added.
+    pub fn check(&mut self, cycle: u64, sample: &PortSample) -> usize {
+        let before = self.violations.len();
+        let mut flag = |kind, detail| {
+            self.violations.push(Violation {
+                cycle,
+                kind,
+                detail,
+            })
+        };
+        let (cpu, out) = (&sample.cpu, &sample.out);
+
+        if cpu.cpu_read && cpu.cpu_write {
+            flag(
+                ViolationKind::CpuReadAndWrite,
+                format!("cpu_addr {:#x}", cpu.cpu_addr),
+            );
+        }
+        if out.mem_read && out.mem_write {
+            flag(ViolationKind::MemReadAndWrite, describe_mem(out));
+        }
+        if (out.mem_read || out.mem_write) && out.mem_addr.is_none() {
+            flag(ViolationKind::MemAddrUndriven, describe_mem(out));
+        }
+        if out.mem_write && out.mem_wdata.is_none() {
+            flag(ViolationKind::MemWdataUndriven, describe_mem(out));
+        }
+        if sample.mem_wait && !out.mem_read && !out.mem_write {
+            flag(
+                ViolationKind::MemWaitWithoutRequest,
+                "mem_wait high with no request".to_string(),
+            );
+        }
+
+        if let Some(prev) = &self.previous {
+            if self.accepted || (prev.cpu.is_active() && prev.out.cpu_wait) {
+                if !cpu.is_active() {
+                    flag(
+                        ViolationKind::CpuRequestWithdrawn,
+                        format!("{} dropped before it completed", describe(&prev.cpu)),
+                    );
+                } else if cpu.cpu_addr != prev.cpu.cpu_addr
+                    || cpu.cpu_read != prev.cpu.cpu_read
+                    || cpu.cpu_write != prev.cpu.cpu_write
+                    || (cpu.cpu_write && cpu.cpu_wdata != prev.cpu.cpu_wdata)
+                {
+                    flag(
+                        ViolationKind::CpuRequestChanged,
+                        format!("{} became {}", describe(&prev.cpu), describe(cpu)),
+                    );
+                }
+            }
+
+            let pending = (prev.out.mem_read || prev.out.mem_write) && prev.mem_wait;
+            if pending {
+                if out.mem_read != prev.out.mem_read
+                    || out.mem_write != prev.out.mem_write
+                    || out.mem_addr != prev.out.mem_addr
+                {
+                    flag(
+                        ViolationKind::MemRequestChanged,
+                        format!(
+                            "{} became {} under mem_wait",
+                            describe_mem(&prev.out),
+                            describe_mem(out)
+                        ),
+                    );
+                } else if out.mem_write && out.mem_wdata != prev.out.mem_wdata {
+                    flag(
+                        ViolationKind::MemWdataChanged,
+                        format!("{} under mem_wait", describe_mem(out)),
+                    );
+                }
+            }
+        }
+
+        if cpu.is_active() && out.cpu_wait {
+            self.stall += 1;
+            if self.max_stall.is_some_and(|max| self.stall == max + 1) {
+                flag(
+                    ViolationKind::StallTimeout,
+                    format!(
+                        "{} stalled for over {} cycles",
+                        describe(cpu),
+                        self.stall - 1
+                    ),
+                );
+            }
+        } else {
+            self.stall = 0;
+        }
+
+        // The cache accepts from idle, with `cpu_wait` low, and completes an
+        // access on the next cycle `cpu_wait` is low.
+        if self.accepted {
+            self.accepted = out.cpu_wait;
+        } else {
+            self.accepted = cpu.is_active();
+        }
+
+        self.previous = Some(sample.clone());
+        self.violations.len() - before
+    }
+
+    pub fn check_record(&mut self, record: &CycleRecord) -> usize {
+        self.check(record.cycle, &PortSample::from(record))
+    }
+
+    /// Checks every record in turn and returns the violations found so far.
+    pub fn check_records<'a, I>(&mut self, records: I) -> &[Violation]
+    where
+        I: IntoIterator<Item = &'a CycleRecord>,
+    {
+        for record in records {
+            self.check_record(record);
+        }
+        &self.violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(cpu: CpuRequest, cpu_wait: bool) -> PortSample {
+        PortSample {
+            cpu,
+            out: CacheOutputs {
+                cpu_wait,
+                ..CacheOutputs::default()
+            },
+            mem_wait: false,
+        }
+    }
+
+    fn kinds(samples: &[PortSample]) -> Vec<(u64, ViolationKind)> {
+        let mut checker = ProtocolChecker::new();
+        for (cycle, s) in samples.iter().enumerate() {
+            checker.check(cycle as u64, s);
+        }
+        checker
+            .violations()
+            .iter()
+            .map(|v| (v.cycle, v.kind))
+            .collect()
+    }
+
+    #[test]
+    fn request_changed_while_stalled() {
+        let samples = [
+            sample(CpuRequest::read(0x40), false),
+            sample(CpuRequest::read(0x40), true),
+            sample(CpuRequest::read(0x80), true),
+        ];
+        assert_eq!(kinds(&samples), [(2, ViolationKind::CpuRequestChanged)]);
+    }
+
+    #[test]
+    fn request_changed_between_accept_and_compare() {
+        // S_IDLE accepts with cpu_wait low; S_COMPARE_TAG samples again.
+        let samples = [
+            sample(CpuRequest::write(0x40, 1), false),
+            sample(CpuRequest::write(0x40, 2), false),
+        ];
+        assert_eq!(kinds(&samples), [(1, ViolationKind::CpuRequestChanged)]);
+        let samples = [
+            sample(CpuRequest::read(0x40), false),
+            sample(CpuRequest::idle(), false),
+        ];
+        assert_eq!(kinds(&samples), [(1, ViolationKind::CpuRequestWithdrawn)]);
+    }
+
+    #[test]
+    fn back_to_back_hits() {
+        // Accept, hit, then the next request is accepted from idle.
+        let samples = [
+            sample(CpuRequest::read(0x40), false),
+            sample(CpuRequest::read(0x40), false),
+            sample(CpuRequest::write(0x80, 3), false),
+            sample(CpuRequest::write(0x80, 3), true),
+            sample(CpuRequest::write(0x80, 3), false),
+            sample(CpuRequest::idle(), false),
+        ];
+        assert_eq!(kinds(&samples), []);
+    }
+}
@@ -0,0 +1,333 @@
+//! Re-reference interval prediction (Jaleel et al., ISCA 2010).
+//!
+//! Each line carries an M-bit re-reference prediction value (RRPV). A hit
+//! predicts a near re-reference and clears it; the victim is the first way
+//! predicted distant (RRPV at its maximum), after ageing the whole set until
+//! one is. The variants differ only in how a fill is predicted:
+//!
+//! - `Srrip` inserts at "long" (maximum - 1), so a block must hit once to
+//!   outlive a scan.
+//! - `Brrip` inserts at "distant" (maximum) except for one fill in
+//!   `BRRIP_EPSILON`, which protects thrashing working sets.
+//! - `Drrip` duels the two: a few leader sets always use one or the other, a
+//!   saturating PSEL counter tracks which leaders miss less, and the
+//!   remaining follower sets copy the winner.
+
+use super::policy::ReplacementPolicy;
+use super::rng::Rng;
+
+/// Default RRPV width, as in the paper.
+pub const RRPV_BITS: u32 = 2;
+/// BRRIP inserts at "long" once every this many fills, on average.
+pub const BRRIP_EPSILON: u64 = 32;
+/// Leader sets per policy under DRRIP, when there are enough sets.
+pub const LEADER_SETS: usize = 32;
+/// Width of the DRRIP policy selector.
+pub const PSEL_BITS: u32 = 10;
+
+/// The RRPVs of every line.
+#[derive(Debug, Clone)]
+struct RrpvTable {
+    ways: usize,
+    max: u8,
+    rrpv: Vec<u8>,
+}
+
+impl RrpvTable {
+    fn new(sets: usize, ways: usize, bits: u32) -> Self {
+        let max = ((1u32 << bits.clamp(1, 8)) - 1) as u8;
+        RrpvTable {
+            ways,
+            max,
+            rrpv: vec![max; sets * ways],
+        }
+    }
+
+    fn set(&mut self, set: usize) -> &mut [u8] {
+        &mut self.rrpv[set * self.ways..(set + 1) * self.ways]
+    }
+
+    fn hit(&mut self, set: usize, way: usize) {
+        self.set(set)[way] = 0;
+    }
+
+    fn insert(&mut self, set: usize, way: usize, distant: bool) {
+        let value = if distant { self.max } else { self.max - 1 };
+        self.set(set)[way] = value;
+    }
+
+    /// The lowest way at the maximum RRPV, after ageing the set just enough
+    /// for one to get there.
+    fn victim(&mut self, set: usize) -> usize {
+        let max = self.max;
+        let lines = self.set(set);
+        let oldest = lines.iter().copied().max().unwrap_or(max);
+        let age = max - oldest;
+        for rrpv in lines.iter_mut() {
+            *rrpv += age;
+        }
+        lines.iter().position(|&r| r == max).unwrap_or(0)
+    }
+
+    fn reset(&mut self) {
+        self.rrpv.fill(self.max);
+    }
+
+    /// The set's RRPVs packed way 0 first, as far as they fit in 64 bits.
+    fn packed(&self, set: usize) -> u64 {
+        let bits = 8 - self.max.leading_zeros();
+        self.rrpv[set * self.ways..(set + 1) * self.ways]
+            .iter()
+            .enumerate()
+            .take_while(|&(w, _)| (w as u32 + 1) * bits <= 64)
+            .fold(0, |acc, (w, &r)| acc | (r as u64) << (w as u32 * bits))
+    }
+}
+
+/// Static RRIP (the hit-priority variant).
+#[derive(Debug, Clone)]
+pub struct Srrip {
+    table: RrpvTable,
+}
+
+impl Srrip {
+    pub fn new(sets: usize, ways: usize, rrpv_bits: u32) -> Self {
+        Srrip {
+            table: RrpvTable::new(sets, ways, rrpv_bits),
+        }
+    }
+}
+
+impl ReplacementPolicy for Srrip {
+    fn name(&self) -> &str {
+        "srrip"
+    }
+
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.table.hit(set, way);
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        self.table.insert(set, way, false);
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        self.table.victim(set)
+    }
+
+    fn reset(&mut self) {
+        self.table.reset();
+    }
+
+    fn state_bits(&self, set: usize) -> u64 {
+        self.table.packed(set)
+    }
+}
+
+/// Bimodal RRIP.
+#[derive(Debug, Clone)]
+pub struct Brrip {
+    table: RrpvTable,
+    seed: u64,
+    rng: Rng,
+}
+
+impl Brrip {
+    pub fn new(sets: usize, ways: usize, rrpv_bits: u32, seed: u64) -> Self {
+        Brrip {
+            table: RrpvTable::new(sets, ways, rrpv_bits),
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl ReplacementPolicy for Brrip {
+    fn name(&self) -> &str {
+        "brrip"
+    }
+
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.table.hit(set, way);
+    }
+
+    fn on_fill(&mut self, set: usize, way: usize) {
+        let distant = self.rng.below(BRRIP_EPSILON) != 0;
+        self.table.insert(set, way, distant);
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        self.table.victim(set)
+    }
+
+    fn reset(&mut self) {
+        self.table.reset();
+        self.rng = Rng::new(self.seed);
+    }
+
+    fn state_bits(&self, set: usize) -> u64 {
+        self.table.packed(set)
+    }
+}
+
+/// Which insertion a DRRIP set uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetRole {
+    SrripLeader,
+    BrripLeader,
+    Follower,
+}
+
+/// Dynamic RRIP with set dueling.
+#[derive(Debug, Clone)]
+pub struct Drrip {
+    table: RrpvTable,
+    seed: u64,
+    rng: Rng,
+    /// Sets per constituency; each holds one leader of either kind.
+    constituency: usize,
+    psel: u32,
+    psel_max: u32,
+}
+
+impl Drrip {
+    pub fn new(sets: usize, ways: usize, rrpv_bits: u32, seed: u64) -> Self {
+        let leaders = LEADER_SETS.min(sets / 2);
+        let psel_max = (1 << PSEL_BITS) - 1;
+        Drrip {
+            table: RrpvTable::new(sets, ways, rrpv_bits),
+            seed,
+            rng: Rng::new(seed),
+            constituency: sets.checked_div(leaders).unwrap_or(0),
+            psel: psel_max / 2,
+            psel_max,
+        }
+    }
+
+    /// The first set of every constituency leads for SRRIP and the middle
+    /// one for BRRIP. With fewer than two sets there is nothing to duel and
+    /// every set follows.
+    pub fn role(&self, set: usize) -> SetRole {
+        match self.constituency {
+            0 => SetRole::Follower,
+            c if set.is_multiple_of(c) => SetRole::SrripLeader,
+            c if set % c == c / 2 => SetRole::BrripLeader,
+            _ => SetRole::Follower,
+        }
+    }
+
+    /// The policy selector: a miss in an SRRIP leader counts up, a miss in a
+    /// BRRIP leader counts down.
+    pub fn psel(&self) -> u32 {
+        self.psel
+    }
+
+    /// Whether followers currently insert as BRRIP, i.e. the SRRIP leaders
+    /// are missing more.
+    pub fn followers_use_brrip(&self) -> bool {
+        self.psel > self.psel_max / 2
+    }
+}
+
+impl ReplacementPolicy for Drrip {
+    fn name(&self) -> &str {
+        "drrip"
+    }
+
+    fn on_hit(&mut self, set: usize, way: usize) {
+        self.table.hit(set, way);
+    }
+
+    /// Every fill is a miss, so this is where the leaders train PSEL.
+    fn on_fill(&mut self, set: usize, way: usize) {
+        let brrip = match self.role(set) {
+            SetRole::SrripLeader => {
+                self.psel = (self.psel + 1).min(self.psel_max);
+                false
+            }
+            SetRole::BrripLeader => {
+                self.psel = self.psel.saturating_sub(1);
+                true
+            }
+            SetRole::Follower => self.followers_use_brrip(),
+        };
+        let distant = brrip && self.rng.below(BRRIP_EPSILON) != 0;
+        self.table.insert(set, way, distant);
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        self.table.victim(set)
+    }
+
+    fn reset(&mut self) {
+        self.table.reset();
+        self.rng = Rng::new(self.seed);
+        self.psel = self.psel_max / 2;
+    }
+
+    fn state_bits(&self, set: usize) -> u64 {
+        self.table.packed(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srrip_inserts_long_and_ages() {
+        let mut srrip = Srrip::new(1, 4, RRPV_BITS);
+        for way in 0..4 {
+            srrip.on_fill(0, way);
+        }
+        assert_eq!(srrip.state_bits(0), 0b10_10_10_10);
+        srrip.on_hit(0, 1);
+        // Nothing is at 3, so the set ages by one and way 0 goes first.
+        assert_eq!(srrip.victim(0), 0);
+        assert_eq!(srrip.state_bits(0), 0b11_11_01_11);
+        srrip.on_fill(0, 0);
+        assert_eq!(srrip.victim(0), 2);
+        assert_eq!(srrip.state_bits(0), 0b11_11_01_10);
+    }
+
+    #[test]
+    fn brrip_mostly_inserts_distant() {
+        let mut brrip = Brrip::new(1, 1, RRPV_BITS, 3);
+        let long = (0..3200)
+            .filter(|_| {
+                brrip.on_fill(0, 0);
+                brrip.state_bits(0) == 2
+            })
+            .count();
+        assert!((50..150).contains(&long), "{} long insertions", long);
+    }
+
+    #[test]
+    fn drrip_duels_on_psel() {
+        // 256 sets: 32 constituencies of 8.
+        let mut drrip = Drrip::new(256, 4, RRPV_BITS, 1);
+        assert_eq!(drrip.role(0), SetRole::SrripLeader);
+        assert_eq!(drrip.role(4), SetRole::BrripLeader);
+        assert_eq!(drrip.role(1), SetRole::Follower);
+        assert_eq!(drrip.psel(), 511);
+        assert!(!drrip.followers_use_brrip());
+        for _ in 0..3 {
+            drrip.on_fill(0, 0);
+        }
+        assert_eq!(drrip.psel(), 514);
+        assert!(drrip.followers_use_brrip());
+        for _ in 0..4 {
+            drrip.on_fill(4, 0);
+        }
+        assert_eq!(drrip.psel(), 510);
+        assert!(!drrip.followers_use_brrip());
+        drrip.on_fill(1, 0);
+        assert_eq!(drrip.state_bits(1) & 0b11, 2);
+        for _ in 0..2000 {
+            drrip.on_fill(8, 0);
+        }
+        assert_eq!(drrip.psel(), (1 << PSEL_BITS) - 1);
+        drrip.reset();
+        assert_eq!(drrip.psel(), 511);
+    }
+}
@@ -0,0 +1,410 @@
+//! Cycle-accurate model of `set_associative_cache` (set_associative_64kb.sv).
+//!
+//! The FSM is the same as in `direct_mapped`; what differs is the parallel
+//! tag compare across ways, the victim choice on a miss and the replacement
+//! update, which is delegated to a `ReplacementPolicy`. With 4 ways and the
+//! default `PseudoLru` these follow the RTL exactly (see `plru`), including
+//! invalid ways always being filled lowest first. One cycle is collapsed: the
+//! RTL's fill in S_READ_FROM_MEM loads `lru_bits_next` for `hit_way` 3 (the
+//! encoder's fall-through, since nothing hits yet) and the S_COMPARE_TAG hit
+//! that follows loads the encoding for the filled way. The model applies that
+//! final encoding at the fill, so the policy sees one `on_fill` per miss and
+//! `lru_bits` matches the RTL at the end of every access.
+//!
+//! Unlike the RTL, which stops at `$fatal` for anything but 4 ways, the model
+//! takes any power-of-two associativity from 1 to 32 and then defaults to the
+//! generalized tree-PLRU.
+//!
+//! The RTL recomputes `victim_way` combinationally every cycle; since nothing
+//! it depends on changes during a miss, the model asks the policy once, on
+//! the S_COMPARE_TAG miss, and holds the answer until the fill.
+//!
+//! Write data is merged by replacing exactly one `DATA_WIDTH` word. The RTL
+//! shifts an all-ones block mask, so its merge also clears every word above
+//! the one written; co-simulation against this model reports that.
+
+use std::fmt;
+
+use super::fsm::{read_word, write_word, CacheOutputs, CpuRequest, CycleModel, State};
+use super::params::{CacheParams, ParamError};
+use super::plru::{self, Plru};
+use super::policy::{PseudoLru, ReplacementPolicy};
+
+pub struct SetAssociativeCache {
+    params: CacheParams,
+    state_reg: State,
+    // Cache Memory Arrays, flattened as [set][way]
+    tag_array: Vec<u64>,
+    valid_array: Vec<bool>,
+    dirty_array: Vec<bool>,
+    data_array: Vec<u8>,
+    policy: Box<dyn ReplacementPolicy>,
+    victim_way: usize,
+    victim_was_invalid: bool,
+    /// Set by a fill, so the S_COMPARE_TAG that completes the miss is not
+    /// reported to the policy as a new hit.
+    filled: bool,
+}
+
+impl fmt::Debug for SetAssociativeCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SetAssociativeCache")
+            .field("params", &self.params)
+            .field("state_reg", &self.state_reg)
+            .field("policy", &self.policy.name())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SetAssociativeCache {
+    /// A cache with the PLRU flavor `Plru::for_ways` picks.
+    pub fn new(params: CacheParams) -> Result<Self, ParamError> {
+        Self::with_plru(params, Plru::for_ways(params.num_ways as usize))
+    }
+
+    pub fn with_plru(params: CacheParams, plru: Plru) -> Result<Self, ParamError> {
+        if plru == Plru::Rtl && params.num_ways != 4 {
+            // The RTL's `$fatal("This LRU implementation is for 4-way only.")`
+            return Err(ParamError::Ways(params.num_ways));
+        }
+        let policy = PseudoLru::new(plru, params.num_sets() as usize, params.num_ways as usize);
+        Self::with_policy(params, Box::new(policy))
+    }
+
+    /// A cache that replaces with `policy`, which must have been built for
+    /// `params.num_sets()` sets of `params.num_ways` ways.
+    pub fn with_policy(
+        params: CacheParams,
+        policy: Box<dyn ReplacementPolicy>,
+    ) -> Result<Self, ParamError> {
+        params.validate()?;
+        if params.num_ways > plru::MAX_WAYS {
+            return Err(ParamError::Ways(params.num_ways));
+        }
+        let blocks = params.num_blocks() as usize;
+        Ok(SetAssociativeCache {
+            params,
+            state_reg: State::Idle,
+            tag_array: vec![0; blocks],
+            valid_array: vec![false; blocks],
+            dirty_array: vec![false; blocks],
+            data_array: vec![0; blocks * params.block_size_bytes as usize],
+            policy,
+            victim_way: 0,
+            victim_was_invalid: false,
+            filled: false,
+        })
+    }
+
+    pub fn params(&self) -> &CacheParams {
+        &self.params
+    }
+
+    pub fn policy(&self) -> &dyn ReplacementPolicy {
+        self.policy.as_ref()
+    }
+
+    fn ways(&self) -> usize {
+        self.params.num_ways as usize
+    }
+
+    fn line(&self, set: usize, way: usize) -> usize {
+        set * self.ways() + way
+    }
+
+    pub fn is_valid(&self, set: usize, way: usize) -> bool {
+        self.valid_array[self.line(set, way)]
+    }
+
+    pub fn is_dirty(&self, set: usize, way: usize) -> bool {
+        self.dirty_array[self.line(set, way)]
+    }
+
+    pub fn tag(&self, set: usize, way: usize) -> u64 {
+        self.tag_array[self.line(set, way)]
+    }
+
+    /// `lru_bits[set]`, or whatever state the policy exposes instead.
+    pub fn lru_bits(&self, set: usize) -> u64 {
+        self.policy.state_bits(set)
+    }
+
+    pub fn block(&self, set: usize, way: usize) -> &[u8] {
+        let bytes = self.params.block_size_bytes as usize;
+        let line = self.line(set, way);
+        &self.data_array[line * bytes..(line + 1) * bytes]
+    }
+
+    fn block_mut(&mut self, set: usize, way: usize) -> &mut [u8] {
+        let bytes = self.params.block_size_bytes as usize;
+        let line = self.line(set, way);
+        &mut self.data_array[line * bytes..(line + 1) * bytes]
+    }
+
+    /// `hit_oh`: one bit per way whose valid tag matches `addr`.
+    pub fn hit_oh(&self, addr: u64) -> u32 {
+        let set = self.params.addr_index(addr);
+        let tag = self.params.addr_tag(addr);
+        (0..self.ways())
+            .filter(|&w| self.is_valid(set, w) && self.tag(set, w) == tag)
+            .fold(0, |oh, w| oh | 1 << w)
+    }
+
+    pub fn hit(&self, addr: u64) -> bool {
+        self.hit_oh(addr) != 0
+    }
+
+    pub fn hit_way(&self, addr: u64) -> usize {
+        plru::hit_way(self.hit_oh(addr), self.ways())
+    }
+
+    /// The way being replaced, from the S_COMPARE_TAG miss until the fill.
+    pub fn victim_way(&self) -> usize {
+        self.victim_way
+    }
+
+    /// Whether that victim was the lowest invalid way rather than the
+    /// policy's choice.
+    pub fn victim_was_invalid(&self) -> bool {
+        self.victim_was_invalid
+    }
+
+    /// The lowest invalid way if there is one, otherwise the policy's pick.
+    fn select_victim(&mut self, set: usize) -> (usize, bool) {
+        match (0..self.ways()).find(|&w| !self.is_valid(set, w)) {
+            Some(way) => (way, true),
+            None => (self.policy.victim(set), false),
+        }
+    }
+
+    fn next_state(&self, cpu: &CpuRequest, mem_wait: bool) -> State {
+        let set = self.params.addr_index(cpu.cpu_addr);
+        let victim = self.victim_way;
+        match self.state_reg {
+            State::Idle if cpu.is_active() => State::CompareTag,
+            State::Idle => State::Idle,
+            State::CompareTag if self.hit(cpu.cpu_addr) => State::Idle,
+            State::CompareTag => State::Allocate,
+            State::Allocate if self.is_valid(set, victim) && self.is_dirty(set, victim) => {
+                State::WriteBack
+            }
+            State::Allocate => State::ReadFromMem,
+            State::WriteBack if !mem_wait => State::ReadFromMem,
+            State::ReadFromMem if !mem_wait => State::CompareTag,
+            state => state,
+        }
+    }
+}
+
+impl CycleModel for SetAssociativeCache {
+    fn eval(&self, cpu: &CpuRequest) -> CacheOutputs {
+        let p = &self.params;
+        let set = p.addr_index(cpu.cpu_addr);
+        let mut out = CacheOutputs {
+            cpu_wait: true,
+            ..Default::default()
+        };
+
+        match self.state_reg {
+            State::Idle => out.cpu_wait = false,
+            State::CompareTag => {
+                if self.hit(cpu.cpu_addr) {
+                    out.cpu_wait = false;
+                    if cpu.cpu_read {
+                        let way = self.hit_way(cpu.cpu_addr);
+                        let word = p.addr_word_offset(cpu.cpu_addr);
+                        out.cpu_rdata = Some(read_word(
+                            self.block(set, way),
+                            word,
+                            p.data_bytes() as usize,
+                        ));
+                    }
+                }
+            }
+            State::Allocate => {}
+            State::WriteBack => {
+                let victim = self.victim_way;
+                out.mem_write = true;
+                out.mem_addr = Some(p.block_addr(self.tag(set, victim), set));
+                out.mem_wdata = Some(self.block(set, victim).to_vec());
+            }
+            State::ReadFromMem => {
+                out.mem_read = true;
+                out.mem_addr = Some(p.block_addr(p.addr_tag(cpu.cpu_addr), set));
+            }
+        }
+        out
+    }
+
+    fn clock(&mut self, cpu: &CpuRequest, mem_wait: bool, mem_rdata: &[u8]) {
+        let p = self.params;
+        let set = p.addr_index(cpu.cpu_addr);
+        let word = p.addr_word_offset(cpu.cpu_addr);
+        let data_bytes = p.data_bytes() as usize;
+        let hit = self.hit(cpu.cpu_addr);
+        let hit_way = self.hit_way(cpu.cpu_addr);
+        let state_next = self.next_state(cpu, mem_wait);
+        let victim = self.victim_way;
+
+        if self.state_reg == State::Idle && cpu.is_active() {
+            self.policy
+                .on_access(p.block_addr(p.addr_tag(cpu.cpu_addr), set));
+        }
+
+        // Case 1: HIT (Read or Write)
+        if hit && self.state_reg == State::CompareTag {
+            if !self.filled {
+                self.policy.on_hit(set, hit_way);
+            }
+            self.filled = false;
+            if cpu.cpu_write {
+                write_word(
+                    self.block_mut(set, hit_way),
+                    word,
+                    data_bytes,
+                    cpu.cpu_wdata,
+                );
+                let line = self.line(set, hit_way);
+                self.dirty_array[line] = true;
+            }
+        }
+
+        // MISS - choose the victim the rest of the miss works on
+        if !hit && self.state_reg == State::CompareTag {
+            (self.victim_way, self.victim_was_invalid) = self.select_victim(set);
+        }
+
+        // Case 2: MISS - data has been fetched from memory
+        if self.state_reg == State::ReadFromMem && !mem_wait {
+            let line = self.line(set, victim);
+            self.block_mut(set, victim).copy_from_slice(mem_rdata);
+            self.tag_array[line] = p.addr_tag(cpu.cpu_addr);
+            self.valid_array[line] = true;
+            self.dirty_array[line] = cpu.cpu_write;
+            self.policy.on_fill(set, victim);
+            self.filled = true;
+            if cpu.cpu_write {
+                write_word(self.block_mut(set, victim), word, data_bytes, cpu.cpu_wdata);
+            }
+        }
+
+        // Case 3: WRITE_BACK has completed
+        if self.state_reg == State::WriteBack && !mem_wait {
+            let line = self.line(set, victim);
+            self.dirty_array[line] = false;
+        }
+
+        self.state_reg = state_next;
+    }
+
+    fn reset(&mut self) {
+        self.state_reg = State::Idle;
+        self.filled = false;
+        self.policy.reset();
+        self.valid_array.fill(false);
+        self.dirty_array.fill(false);
+    }
+
+    fn state(&self) -> State {
+        self.state_reg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::memory::MainMemory;
+    use super::super::system::System;
+    use super::*;
+
+    const PARAMS: CacheParams = CacheParams {
+        addr_width: 32,
+        data_width: 32,
+        cache_size_kb: 64,
+        block_size_bytes: 32,
+        num_ways: 4,
+    };
+
+    fn system() -> System<SetAssociativeCache, MainMemory> {
+        System::new(
+            SetAssociativeCache::new(PARAMS).unwrap(),
+            MainMemory::new(32, 0),
+        )
+    }
+
+    /// Byte address of word 0 of `tag`'s block in set 0.
+    fn addr(tag: u64) -> u64 {
+        PARAMS.block_addr(tag, 0)
+    }
+
+    #[test]
+    fn lru_bits_follow_the_rtl() {
+        let mut system = system();
+        // Invalid ways fill lowest first; each fill ends on its way's code.
+        for (tag, bits) in [(1, 0b110), (2, 0b100), (3, 0b011), (4, 0b001)] {
+            assert!(!system.read(addr(tag)).hit);
+            assert_eq!(system.cache.lru_bits(0), bits);
+        }
+        assert!(system.read(addr(3)).hit);
+        assert_eq!(system.cache.lru_bits(0), 0b011);
+        // 3'b011 still selects way 2, so the block just hit is evicted.
+        assert!(!system.read(addr(5)).hit);
+        assert_eq!(system.cache.victim_way(), 2);
+        assert_eq!(system.cache.tag(0, 2), 5);
+        assert!(!system.read(addr(3)).hit);
+    }
+
+    #[test]
+    fn tree_plru_for_eight_ways() {
+        let params = CacheParams {
+            num_ways: 8,
+            ..PARAMS
+        };
+        let mut system = System::new(
+            SetAssociativeCache::new(params).unwrap(),
+            MainMemory::new(32, 0),
+        );
+        let addr = |tag| params.block_addr(tag, 0);
+        for tag in 1..=8 {
+            system.read(addr(tag));
+        }
+        assert_eq!(system.cache.tag(0, 7), 8);
+        // After touching ways 0 to 7 in order and then way 0, the root points
+        // right, its right child left (ways 4-5, away from 7), and that
+        // node at way 4 (away from 5).
+        assert!(system.read(addr(1)).hit);
+        assert!(!system.read(addr(9)).hit);
+        assert_eq!(system.cache.victim_way(), 4);
+        assert!(!system.cache.victim_was_invalid());
+        assert_eq!(system.cache.tag(0, 4), 9);
+    }
+
+    #[test]
+    fn associativity_limits() {
+        let ways = |num_ways| CacheParams { num_ways, ..PARAMS };
+        assert!(SetAssociativeCache::new(ways(1)).is_ok());
+        assert!(SetAssociativeCache::new(ways(32)).is_ok());
+        assert_eq!(
+            SetAssociativeCache::new(ways(64)).unwrap_err(),
+            ParamError::Ways(64)
+        );
+        assert_eq!(
+            SetAssociativeCache::with_plru(ways(8), Plru::Rtl).unwrap_err(),
+            ParamError::Ways(8)
+        );
+    }
+
+    #[test]
+    fn write_replaces_one_word() {
+        let mut system = system();
+        let block: Vec<u8> = (1..=32).collect();
+        system.memory.write_block(addr(1), &block);
+        system.read(addr(1));
+        system.write(addr(1) + 4, 0xAABB_CCDD);
+        // The RTL's mask also clears words 2 to 7 here; the model keeps
+        // them, and co-simulation reports the difference.
+        let mut expected = block.clone();
+        expected[4..8].copy_from_slice(&[0xDD, 0xCC, 0xBB, 0xAA]);
+        assert_eq!(system.cache.block(0, 0), &expected[..]);
+        assert!(system.cache.is_dirty(0, 0));
+    }
+}
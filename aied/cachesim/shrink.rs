@@ -0,0 +1,618 @@
+//! Delta debugging of a failing co-simulation down to a short reproducer.
+//!
+//! A `Case` is what a run depends on: the requests, idle cycles included,
+//! and how many `mem_wait` cycles memory held each operation of each request
+//! for. Keeping the waits with the request that caused them means dropping
+//! a request leaves every other operation's timing where it was. A
+//! `ScriptedMemory` plays the waits back exactly and records them in the
+//! first place, so `capture` can run a random stimulus and hand back the
+//! case the moment it diverges.
+//!
+//! `minimize` then alternates two passes until neither helps: `ddmin` over
+//! the requests, and over the operations that wait, lowering each survivor's
+//! wait as far as it will go. A candidate counts only if it fails on the
+//! same signal as the original, so the shrink does not wander onto a
+//! different bug. The result is 1-minimal: dropping any one request or wait
+//! makes the mismatch go away. `RegressionTest` writes it out as a test.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::cosim::{Cosim, Divergence, Dut, RtlModule};
+use super::fsm::{CacheOutputs, CpuRequest, CycleModel};
+use super::memory::{MainMemory, MemResponse, Memory};
+use super::params::CacheParams;
+
+/// A replayable run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Case {
+    pub requests: Vec<CpuRequest>,
+    /// `waits[i]` holds the `mem_wait` cycles of each memory operation
+    /// `requests[i]` makes, in order; operations not listed wait none.
+    pub waits: Vec<Vec<u32>>,
+}
+
+impl Case {
+    pub fn accesses(&self) -> usize {
+        self.requests.iter().filter(|r| r.is_active()).count()
+    }
+
+    pub fn wait_cycles(&self) -> u64 {
+        self.waits.iter().flatten().map(|&w| w as u64).sum()
+    }
+
+    /// `(request, operation)` of every operation that waits.
+    fn waiting(&self) -> Vec<(usize, usize)> {
+        let mut ops = Vec::new();
+        for (request, waits) in self.waits.iter().enumerate() {
+            ops.extend(
+                (0..waits.len())
+                    .filter(|&op| waits[op] > 0)
+                    .map(|op| (request, op)),
+            );
+        }
+        ops
+    }
+
+    /// The case with only the waits of `ops` kept.
+    fn keep_waits(&self, ops: &[(usize, usize)]) -> Case {
+        let mut waits: Vec<Vec<u32>> = self.waits.iter().map(|w| vec![0; w.len()]).collect();
+        for &(request, op) in ops {
+            waits[request][op] = self.waits[request][op];
+        }
+        Case {
+            requests: self.requests.clone(),
+            waits,
+        }
+    }
+
+    /// Drops zero waits from the ends of the lists.
+    fn trim(&mut self) {
+        for waits in &mut self.waits {
+            while waits.last() == Some(&0) {
+                waits.pop();
+            }
+        }
+        while self.waits.last().is_some_and(|w| w.is_empty()) {
+            self.waits.pop();
+        }
+    }
+}
+
+/// A memory whose `mem_wait` follows a script, one count per operation of
+/// the current request, before `inner` sees the operation. With `inner` at
+/// zero latency the script is the whole timing. It also records the wait
+/// every operation actually got, script and `inner` together.
+#[derive(Debug, Clone)]
+pub struct ScriptedMemory<M> {
+    pub inner: M,
+    block_bytes: usize,
+    script: Vec<u32>,
+    /// Script cycles left for the operation in flight, if one is.
+    extra: Option<u32>,
+    /// One list per request begun, as in `Case::waits`.
+    observed: Vec<Vec<u32>>,
+}
+
+impl<M: Memory> ScriptedMemory<M> {
+    /// Adds nothing to `inner` until a request `begin`s with a script.
+    pub fn new(inner: M, block_bytes: usize) -> Self {
+        ScriptedMemory {
+            inner,
+            block_bytes,
+            script: Vec::new(),
+            extra: None,
+            observed: Vec::new(),
+        }
+    }
+
+    /// Starts the next request, whose operations wait as `script` says.
+    pub fn begin(&mut self, script: &[u32]) {
+        self.script = script.to_vec();
+        self.observed.push(Vec::new());
+    }
+
+    /// The waits of the operations of every request begun so far.
+    pub fn observed(&self) -> &[Vec<u32>] {
+        &self.observed
+    }
+}
+
+impl<M: Memory> Memory for ScriptedMemory<M> {
+    fn cycle(&mut self, out: &CacheOutputs) -> MemResponse {
+        if !(out.mem_read || out.mem_write) || out.mem_addr.is_none() {
+            self.extra = None;
+            return self.inner.cycle(out);
+        }
+        if self.observed.is_empty() {
+            self.observed.push(Vec::new());
+        }
+        let ops = self.observed.last_mut().unwrap();
+        let extra = match self.extra {
+            Some(extra) => extra,
+            None => {
+                ops.push(0);
+                self.script.get(ops.len() - 1).copied().unwrap_or(0)
+            }
+        };
+        let resp = if extra > 0 {
+            MemResponse {
+                mem_wait: true,
+                mem_rdata: vec![0; self.block_bytes],
+            }
+        } else {
+            self.inner.cycle(out)
+        };
+        if resp.mem_wait {
+            self.extra = Some(extra.saturating_sub(1));
+            *ops.last_mut().unwrap() += 1;
+        } else {
+            self.extra = None;
+        }
+        resp
+    }
+}
+
+/// Runs `requests` through `cosim`, accesses to completion and idle
+/// requests for one cycle, as `stimulus::run_cosim` does, giving request
+/// `i` the waits in `waits[i]`. On a divergence, returns the index of the
+/// request it happened in.
+pub fn run<G, D, M>(
+    cosim: &mut Cosim<G, D, ScriptedMemory<M>>,
+    requests: &[CpuRequest],
+    waits: &[Vec<u32>],
+) -> Result<(), (usize, Divergence)>
+where
+    G: CycleModel,
+    D: Dut,
+    M: Memory,
+{
+    for (i, &cpu) in requests.iter().enumerate() {
+        cosim.memory.begin(waits.get(i).map_or(&[], |w| w));
+        let result = if cpu.is_active() {
+            cosim.access(cpu).map(|_| ())
+        } else {
+            cosim.step(&cpu).map(|_| ())
+        };
+        result.map_err(|d| (i, d))?;
+    }
+    Ok(())
+}
+
+/// Runs `requests`, such as a `Stimulus`, through `cosim`, whose memory
+/// should be a fresh `ScriptedMemory` with no script. On a divergence,
+/// returns the case up to the failing request with the waits the memory
+/// gave, which `replay` reproduces cycle for cycle; `None` if every
+/// request ran clean.
+pub fn capture<G, D, M, I>(
+    cosim: &mut Cosim<G, D, ScriptedMemory<M>>,
+    requests: I,
+) -> Option<(Case, Divergence)>
+where
+    G: CycleModel,
+    D: Dut,
+    M: Memory,
+    I: IntoIterator<Item = CpuRequest>,
+{
+    let mut case = Case::default();
+    for cpu in requests {
+        case.requests.push(cpu);
+        if let Err((_, d)) = run(cosim, &[cpu], &[]) {
+            case.waits = cosim.memory.observed().to_vec();
+            case.trim();
+            return Some((case, d));
+        }
+    }
+    None
+}
+
+/// Replays `case` on a fresh golden model and DUT, against a zero-latency
+/// memory following `case.waits`.
+pub fn replay<G: CycleModel, D: Dut>(
+    golden: G,
+    dut: D,
+    block_bytes: usize,
+    case: &Case,
+) -> Result<(), Divergence> {
+    let memory = ScriptedMemory::new(MainMemory::new(block_bytes, 0), block_bytes);
+    let mut cosim = Cosim::new(golden, dut, memory);
+    run(&mut cosim, &case.requests, &case.waits).map_err(|(_, d)| d)
+}
+
+/// The smallest subsequence of `items` found on which `fails` holds, by
+/// Zeller's ddmin. `fails(items)` should hold to begin with.
+pub fn ddmin<T: Clone>(items: &[T], mut fails: impl FnMut(&[T]) -> bool) -> Vec<T> {
+    let mut current = items.to_vec();
+    let mut n = 2;
+    while current.len() >= 2 {
+        let chunk = current.len().div_ceil(n);
+        let starts: Vec<usize> = (0..current.len()).step_by(chunk).collect();
+        let mut reduced = false;
+        for &start in &starts {
+            let subset = &current[start..(start + chunk).min(current.len())];
+            if fails(subset) {
+                current = subset.to_vec();
+                n = 2;
+                reduced = true;
+                break;
+            }
+        }
+        if !reduced && starts.len() > 2 {
+            for &start in &starts {
+                let end = (start + chunk).min(current.len());
+                let complement: Vec<T> = current[..start]
+                    .iter()
+                    .chain(&current[end..])
+                    .cloned()
+                    .collect();
+                if fails(&complement) {
+                    current = complement;
+                    n = (n - 1).max(2);
+                    reduced = true;
+                    break;
+                }
+            }
+        }
+        if !reduced {
+            if n >= current.len() {
+                break;
+            }
+            n = (2 * n).min(current.len());
+        }
+    }
+    current
+}
+
+/// A minimized case and what it took.
+#[derive(Debug, Clone)]
+pub struct Shrunk {
+    pub case: Case,
+    /// The mismatch the minimized case shows.
+    pub divergence: Divergence,
+    /// Times the checker ran.
+    pub runs: u64,
+}
+
+/// Shrinks `case`, which must fail `check`, to one that still fails on the
+/// same signal. `check` replays a case from reset, as `replay` does, and
+/// returns the divergence if there is one.
+pub fn minimize<F>(case: &Case, mut check: F) -> Result<Shrunk, String>
+where
+    F: FnMut(&Case) -> Result<(), Divergence>,
+{
+    let signal = match check(case) {
+        Err(d) => d.signal,
+        Ok(()) => return Err("the case to shrink does not fail".to_string()),
+    };
+    let mut runs = 1;
+    let mut fails = |candidate: &Case| {
+        runs += 1;
+        matches!(check(candidate), Err(d) if d.signal == signal)
+    };
+
+    let mut current = case.clone();
+    current.trim();
+    loop {
+        let before = current.clone();
+
+        // Each request goes with its waits.
+        let paired: Vec<(CpuRequest, Vec<u32>)> = current
+            .requests
+            .iter()
+            .enumerate()
+            .map(|(i, &r)| (r, current.waits.get(i).cloned().unwrap_or_default()))
+            .collect();
+        let kept = ddmin(&paired, |pairs| {
+            fails(&Case {
+                requests: pairs.iter().map(|p| p.0).collect(),
+                waits: pairs.iter().map(|p| p.1.clone()).collect(),
+            })
+        });
+        current = Case {
+            requests: kept.iter().map(|p| p.0).collect(),
+            waits: kept.into_iter().map(|p| p.1).collect(),
+        };
+        current.trim();
+
+        let waiting = current.waiting();
+        let ops = if waiting.is_empty() || fails(&current.keep_waits(&[])) {
+            Vec::new()
+        } else {
+            ddmin(&waiting, |ops| fails(&current.keep_waits(ops)))
+        };
+        current = current.keep_waits(&ops);
+        for (request, op) in ops {
+            // The lowest wait that still fails, assuming lower waits fail
+            // less often.
+            let (mut low, mut high) = (1, current.waits[request][op]);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                let mut candidate = current.clone();
+                candidate.waits[request][op] = mid;
+                if fails(&candidate) {
+                    high = mid;
+                } else {
+                    low = mid + 1;
+                }
+            }
+            current.waits[request][op] = high;
+        }
+        current.trim();
+
+        if current == before {
+            break;
+        }
+    }
+
+    let divergence = match check(&current) {
+        Err(d) => d,
+        Ok(()) => {
+            return Err("the shrunk case no longer fails; is the check deterministic?".to_string())
+        }
+    };
+    Ok(Shrunk {
+        case: current,
+        divergence,
+        runs: runs + 1,
+    })
+}
+
+/// A minimized case written out as a Rust test against the Verilated RTL.
+#[derive(Debug, Clone)]
+pub struct RegressionTest {
+    /// The test function's name, also the file's stem.
+    pub name: String,
+    /// The path the test reaches this module tree by, such as `cachesim`.
+    pub crate_path: String,
+    pub params: CacheParams,
+    pub shrunk: Shrunk,
+    /// Where the case came from, such as a seed, for the header comment.
+    pub origin: String,
+}
+
+impl RegressionTest {
+    /// Checks `name` and `crate_path`, which end up in Rust source and a
+    /// file name.
+    pub fn new(
+        name: &str,
+        crate_path: &str,
+        params: CacheParams,
+        shrunk: Shrunk,
+        origin: &str,
+    ) -> Result<Self, String> {
+        let test = RegressionTest {
+            name: name.to_string(),
+            crate_path: crate_path.to_string(),
+            params,
+            shrunk,
+            origin: origin.to_string(),
+        };
+        test.validate()?;
+        Ok(test)
+    }
+
+    /// `name` must be an identifier and `crate_path` a `::`-separated path
+    /// of them.
+    pub fn validate(&self) -> Result<(), String> {
+        if !is_ident(&self.name) {
+            return Err(format!("test name {:?} is not an identifier", self.name));
+        }
+        if !self.crate_path.split("::").all(is_ident) {
+            return Err(format!("crate path {:?} is not a path", self.crate_path));
+        }
+        Ok(())
+    }
+
+    /// The test's source. The file is compiled only with the `verilator`
+    /// feature, so a suite without the Verilated RTL skips it rather than
+    /// failing to link; with it, the test fails until the mismatch is fixed.
+    pub fn to_rust(&self) -> String {
+        let p = &self.params;
+        let c = &self.crate_path;
+        let (golden, golden_ty) = if p.num_ways == 1 {
+            ("direct_mapped", "DirectMappedCache")
+        } else {
+            ("set_associative", "SetAssociativeCache")
+        };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "//! Minimized from {} by shrink::minimize: {} accesses, {} wait cycles.\n\
+             //! It failed with: {}\n\n\
+             #![cfg(feature = \"verilator\")]\n",
+            self.origin,
+            self.shrunk.case.accesses(),
+            self.shrunk.case.wait_cycles(),
+            self.shrunk.divergence
+        );
+        let _ = writeln!(out, "use {}::cosim::VerilatedDut;", c);
+        let _ = writeln!(out, "use {}::{}::{};", c, golden, golden_ty);
+        let _ = writeln!(out, "use {}::fsm::CpuRequest;", c);
+        let _ = writeln!(out, "use {}::params::CacheParams;", c);
+        let _ = writeln!(out, "use {}::shrink::{{replay, Case}};\n", c);
+        let _ = writeln!(out, "#[test]\nfn {}() {{", self.name);
+        let _ = writeln!(
+            out,
+            "    let params = CacheParams {{\n        addr_width: {},\n        \
+             data_width: {},\n        cache_size_kb: {},\n        block_size_bytes: {},\n        \
+             num_ways: {},\n    }};",
+            p.addr_width, p.data_width, p.cache_size_kb, p.block_size_bytes, p.num_ways
+        );
+        out.push_str("    let case = Case {\n        requests: vec![\n");
+        for r in &self.shrunk.case.requests {
+            let request = if r.cpu_write {
+                format!("CpuRequest::write({:#x}, {:#x})", r.cpu_addr, r.cpu_wdata)
+            } else if r.cpu_read {
+                format!("CpuRequest::read({:#x})", r.cpu_addr)
+            } else {
+                "CpuRequest::idle()".to_string()
+            };
+            let _ = writeln!(out, "            {},", request);
+        }
+        out.push_str("        ],\n        waits: vec![\n");
+        for waits in &self.shrunk.case.waits {
+            let _ = writeln!(out, "            vec!{:?},", waits);
+        }
+        out.push_str("        ],\n    };\n");
+        let _ = writeln!(
+            out,
+            "    let dut = VerilatedDut::new(params)\n        \
+             .expect(\"the shim was built without {}\");",
+            RtlModule::for_params(p).name()
+        );
+        let _ = writeln!(
+            out,
+            "    let golden = {}::new(params).unwrap();\n    \
+             if let Err(d) = replay(golden, dut, params.block_size_bytes as usize, &case) {{\n        \
+             panic!(\"{{}}\", d);\n    }}\n}}",
+            golden_ty
+        );
+        out
+    }
+
+    /// Writes the test to `dir/<name>.rs` and returns the path.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> io::Result<PathBuf> {
+        self.validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join(format!("{}.rs", self.name));
+        fs::write(&path, self.to_rust())?;
+        Ok(path)
+    }
+}
+
+/// Whether `s` matches `[A-Za-z_][A-Za-z0-9_]*`.
+pub(crate) fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cosim::Signal;
+    use super::super::direct_mapped::DirectMappedCache;
+    use super::super::fsm::State;
+    use super::*;
+
+    /// The address whose reads `Broken` gets wrong.
+    const BAD: u64 = 0x5008;
+
+    /// The golden model, but with bit 0 of `cpu_rdata` flipped on reads of
+    /// `BAD`.
+    struct Broken(DirectMappedCache);
+
+    impl CycleModel for Broken {
+        fn eval(&self, cpu: &CpuRequest) -> CacheOutputs {
+            let mut out = CycleModel::eval(&self.0, cpu);
+            if cpu.cpu_read && cpu.cpu_addr == BAD {
+                out.cpu_rdata = out.cpu_rdata.map(|d| d ^ 1);
+            }
+            out
+        }
+
+        fn clock(&mut self, cpu: &CpuRequest, mem_wait: bool, mem_rdata: &[u8]) {
+            CycleModel::clock(&mut self.0, cpu, mem_wait, mem_rdata)
+        }
+
+        fn reset(&mut self) {
+            CycleModel::reset(&mut self.0)
+        }
+
+        fn state(&self) -> State {
+            CycleModel::state(&self.0)
+        }
+    }
+
+    fn golden() -> DirectMappedCache {
+        DirectMappedCache::new(CacheParams::default()).unwrap()
+    }
+
+    fn check(case: &Case) -> Result<(), Divergence> {
+        replay(golden(), Broken(golden()), 32, case)
+    }
+
+    /// Twenty requests around one read of `BAD`, every operation waiting.
+    fn case() -> Case {
+        let mut requests: Vec<CpuRequest> = (0..20u64)
+            .map(|i| match i % 3 {
+                0 => CpuRequest::write(0x1_0000 * (i % 4) + 0x5000, i),
+                1 => CpuRequest::read(0x40 * i),
+                _ => CpuRequest::idle(),
+            })
+            .collect();
+        requests.insert(13, CpuRequest::read(BAD));
+        let waits = requests
+            .iter()
+            .map(|r| if r.is_active() { vec![3, 2] } else { vec![] })
+            .collect();
+        Case { requests, waits }
+    }
+
+    #[test]
+    fn ddmin_finds_the_two_that_matter() {
+        let items: Vec<u32> = (0..20).collect();
+        let mut runs = 0;
+        let kept = ddmin(&items, |s| {
+            runs += 1;
+            s.contains(&3) && s.contains(&7)
+        });
+        assert_eq!(kept, [3, 7]);
+        assert!(runs < 100, "{} runs", runs);
+        assert_eq!(ddmin(&[1], |_| true), [1]);
+    }
+
+    #[test]
+    fn minimize_keeps_only_the_bad_read() {
+        let case = case();
+        let original = check(&case).unwrap_err();
+        assert_eq!(original.signal, Signal::CpuRdata);
+        let shrunk = minimize(&case, check).unwrap();
+        // The read misses and fills on its own; no wait is needed.
+        assert_eq!(shrunk.case.requests, [CpuRequest::read(BAD)]);
+        assert!(shrunk.case.waits.is_empty());
+        assert_eq!(shrunk.divergence.signal, original.signal);
+        assert_eq!(shrunk.divergence.cpu, CpuRequest::read(BAD));
+        assert!(minimize(&Case::default(), check).is_err());
+    }
+
+    #[test]
+    fn capture_records_the_waits_replay_needs() {
+        let memory = ScriptedMemory::new(MainMemory::new(32, 2), 32);
+        let mut cosim = Cosim::new(golden(), Broken(golden()), memory);
+        let (captured, divergence) = capture(&mut cosim, case().requests).unwrap();
+        // Everything up to and including the bad read, which was request 13.
+        assert_eq!(captured.requests.len(), 14);
+        // It diverges before reaching memory, so its waits are trimmed.
+        assert_eq!(captured.waits.len(), 13);
+        assert!(captured.waits.iter().flatten().all(|&w| w == 2));
+        assert_eq!(
+            replay(golden(), Broken(golden()), 32, &captured),
+            Err(divergence)
+        );
+    }
+
+    #[test]
+    fn regression_test_source() {
+        let shrunk = minimize(&case(), check).unwrap();
+        let params = CacheParams::default();
+        assert!(RegressionTest::new("1st", "cachesim", params, shrunk.clone(), "x").is_err());
+        assert!(RegressionTest::new("t", "a::b-c", params, shrunk.clone(), "x").is_err());
+        let test =
+            RegressionTest::new("bad_read", "crate::cachesim", params, shrunk, "seed 0x1").unwrap();
+        let source = test.to_rust();
+        assert!(source.starts_with(
+            "//! Minimized from seed 0x1 by shrink::minimize: 1 accesses, 0 wait cycles.\n"
+        ));
+        assert!(source.contains("#![cfg(feature = \"verilator\")]\n"));
+        assert!(source.contains("use crate::cachesim::direct_mapped::DirectMappedCache;\n"));
+        assert!(source.contains(
+            "        requests: vec![\n            CpuRequest::read(0x5008),\n        ],\n"
+        ));
+        assert!(source.contains("#[test]\nfn bad_read() {\n"));
+    }
+}
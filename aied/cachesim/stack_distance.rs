@@ -0,0 +1,307 @@
+//! Single-pass stack-distance analysis (Mattson et al., 1970).
+//!
+//! The stack distance of an access is the number of distinct blocks touched
+//! since the previous access to its block; first touches have none. An LRU
+//! cache of C blocks hits exactly the accesses with a distance below C, so
+//! one pass over a trace gives the fully associative LRU miss ratio of every
+//! capacity at once, which is what sizing `CACHE_SIZE_KB` needs.
+//!
+//! Each block keeps a marker at the time of its last access, in a Fenwick
+//! tree over time; the distance is the number of markers after the block's
+//! own, an O(log n) query. Times are renumbered when the tree fills, so it
+//! stays proportional to the number of distinct blocks rather than the
+//! length of the trace.
+//!
+//! The reuse distance (accesses, not distinct blocks, since the previous
+//! access to the block) is histogrammed alongside, in power-of-two buckets.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::params::clog2;
+
+/// The smallest Fenwick tree kept, in time slots.
+const MIN_SLOTS: usize = 1024;
+
+/// A binary indexed tree of counts.
+#[derive(Debug, Clone)]
+struct Fenwick {
+    tree: Vec<i64>,
+}
+
+impl Fenwick {
+    fn new(len: usize) -> Self {
+        Fenwick {
+            tree: vec![0; len + 1],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.tree.len() - 1
+    }
+
+    fn add(&mut self, pos: usize, delta: i64) {
+        let mut i = pos + 1;
+        while i < self.tree.len() {
+            self.tree[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// The sum over `0..pos`.
+    fn prefix(&self, pos: usize) -> i64 {
+        let mut i = pos;
+        let mut sum = 0;
+        while i > 0 {
+            sum += self.tree[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StackDistance {
+    offset_bits: u32,
+    /// Each block's time slot of last access.
+    last: HashMap<u64, usize>,
+    markers: Fenwick,
+    /// Next free time slot.
+    now: usize,
+    /// Each block's access count at its last access, for reuse distances.
+    last_access: HashMap<u64, u64>,
+    accesses: u64,
+    cold: u64,
+    /// `stack[d]`: accesses at stack distance `d`.
+    stack: Vec<u64>,
+    /// `reuse[b]`: accesses whose reuse distance has bit length `b`.
+    reuse: Vec<u64>,
+}
+
+impl StackDistance {
+    /// Analyses at the granularity of `block_size_bytes`, a power of two.
+    pub fn new(block_size_bytes: u32) -> Self {
+        StackDistance {
+            offset_bits: clog2(block_size_bytes as u64),
+            last: HashMap::new(),
+            markers: Fenwick::new(MIN_SLOTS),
+            now: 0,
+            last_access: HashMap::new(),
+            accesses: 0,
+            cold: 0,
+            stack: Vec::new(),
+            reuse: Vec::new(),
+        }
+    }
+
+    pub fn block_size_bytes(&self) -> u64 {
+        1 << self.offset_bits
+    }
+
+    pub fn accesses(&self) -> u64 {
+        self.accesses
+    }
+
+    /// First touches, which miss at every size.
+    pub fn cold_misses(&self) -> u64 {
+        self.cold
+    }
+
+    /// Distinct blocks seen so far.
+    pub fn distinct_blocks(&self) -> u64 {
+        self.last.len() as u64
+    }
+
+    /// `[d]` is the number of accesses at stack distance `d`.
+    pub fn stack_histogram(&self) -> &[u64] {
+        &self.stack
+    }
+
+    /// `[0]` counts reuse distance 0 and `[b]` distances in
+    /// `2^(b-1)..2^b`.
+    pub fn reuse_histogram(&self) -> &[u64] {
+        &self.reuse
+    }
+
+    /// Moves every marker to the front of a fresh tree, keeping their order.
+    fn compact(&mut self) {
+        let mut live: Vec<(usize, u64)> = self.last.iter().map(|(&b, &t)| (t, b)).collect();
+        live.sort_unstable();
+        let slots = (2 * live.len()).max(MIN_SLOTS);
+        self.markers = Fenwick::new(slots);
+        for (slot, &(_, block)) in live.iter().enumerate() {
+            self.last.insert(block, slot);
+            self.markers.add(slot, 1);
+        }
+        self.now = live.len();
+    }
+
+    /// Records an access; returns its stack distance, or `None` for a first
+    /// touch.
+    pub fn access(&mut self, addr: u64) -> Option<u64> {
+        let block = addr >> self.offset_bits;
+        if self.now == self.markers.len() {
+            self.compact();
+        }
+        let distance = self.last.insert(block, self.now).map(|previous| {
+            let after = self.markers.prefix(self.now) - self.markers.prefix(previous + 1);
+            self.markers.add(previous, -1);
+            after as u64
+        });
+        self.markers.add(self.now, 1);
+        self.now += 1;
+
+        match distance {
+            Some(d) => {
+                if self.stack.len() <= d as usize {
+                    self.stack.resize(d as usize + 1, 0);
+                }
+                self.stack[d as usize] += 1;
+            }
+            None => self.cold += 1,
+        }
+        if let Some(previous) = self.last_access.insert(block, self.accesses) {
+            let bucket = (64 - (self.accesses - previous - 1).leading_zeros()) as usize;
+            if self.reuse.len() <= bucket {
+                self.reuse.resize(bucket + 1, 0);
+            }
+            self.reuse[bucket] += 1;
+        }
+        self.accesses += 1;
+        distance
+    }
+
+    /// Records every address of a trace.
+    pub fn run<I: IntoIterator<Item = u64>>(&mut self, addrs: I) {
+        for addr in addrs {
+            self.access(addr);
+        }
+    }
+
+    /// Misses of a fully associative LRU cache of `blocks` blocks.
+    pub fn misses(&self, blocks: u64) -> u64 {
+        let far: u64 = self.stack.iter().skip(blocks as usize).sum();
+        self.cold + far
+    }
+
+    pub fn miss_ratio(&self, blocks: u64) -> f64 {
+        if self.accesses == 0 {
+            0.0
+        } else {
+            self.misses(blocks) as f64 / self.accesses as f64
+        }
+    }
+
+    /// The miss-ratio curve at every capacity from zero blocks up to the
+    /// footprint, as `(capacity in bytes, miss ratio)`. Beyond the footprint
+    /// only cold misses remain.
+    pub fn miss_ratio_curve(&self) -> Vec<(u64, f64)> {
+        let total = self.accesses.max(1) as f64;
+        let mut misses = self.cold + self.stack.iter().sum::<u64>();
+        let mut curve = Vec::with_capacity(self.stack.len() + 1);
+        curve.push((0, misses as f64 / total));
+        for (d, &count) in self.stack.iter().enumerate() {
+            misses -= count;
+            curve.push((
+                (d as u64 + 1) * self.block_size_bytes(),
+                misses as f64 / total,
+            ));
+        }
+        curve
+    }
+
+    /// The curve at power-of-two capacities, smallest to largest, up to the
+    /// first one that holds the whole footprint.
+    pub fn power_of_two_curve(&self) -> Vec<(u64, f64)> {
+        let mut blocks = 1u64;
+        let mut curve = Vec::new();
+        loop {
+            curve.push((blocks * self.block_size_bytes(), self.miss_ratio(blocks)));
+            if blocks >= self.distinct_blocks() {
+                return curve;
+            }
+            blocks *= 2;
+        }
+    }
+
+    /// A plain-text table of the power-of-two miss-ratio curve.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} accesses, {} distinct {}B blocks, {} cold misses",
+            self.accesses,
+            self.distinct_blocks(),
+            self.block_size_bytes(),
+            self.cold
+        );
+        let _ = writeln!(out, "{:>12} {:>12} {:>10}", "capacity", "misses", "ratio");
+        for (bytes, ratio) in self.power_of_two_curve() {
+            let blocks = bytes / self.block_size_bytes();
+            let capacity = if bytes >= 1024 {
+                format!("{}KB", bytes / 1024)
+            } else {
+                format!("{}B", bytes)
+            };
+            let _ = writeln!(
+                out,
+                "{:>12} {:>12} {:>10.4}",
+                capacity,
+                self.misses(blocks),
+                ratio
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::classify::FullyAssociativeLru;
+    use super::super::rng::Rng;
+    use super::*;
+
+    #[test]
+    fn distances_by_hand() {
+        let mut sd = StackDistance::new(32);
+        // Blocks A B C A B B D A, with 0x08 inside A's block.
+        let distances: Vec<Option<u64>> = [0x00, 0x20, 0x40, 0x08, 0x20, 0x20, 0x60, 0x00]
+            .into_iter()
+            .map(|addr| sd.access(addr))
+            .collect();
+        assert_eq!(
+            distances,
+            [None, None, None, Some(2), Some(2), Some(0), None, Some(2)]
+        );
+        assert_eq!(sd.cold_misses(), 4);
+        assert_eq!(sd.distinct_blocks(), 4);
+        assert_eq!(sd.stack_histogram(), [1, 0, 3]);
+        // Reuse distances 2, 2, 0 and 3.
+        assert_eq!(sd.reuse_histogram(), [1, 0, 3]);
+        assert_eq!(sd.misses(2), 7);
+        assert_eq!(sd.misses(3), 4);
+        assert_eq!(
+            sd.power_of_two_curve(),
+            [(32, 7.0 / 8.0), (64, 7.0 / 8.0), (128, 0.5)]
+        );
+    }
+
+    #[test]
+    fn matches_fully_associative_lru() {
+        // Long enough to compact the tree several times.
+        let mut rng = Rng::new(42);
+        let trace: Vec<u64> = (0..20_000)
+            .map(|_| {
+                let hot = rng.chance(0.7);
+                32 * if hot { rng.below(40) } else { rng.below(600) }
+            })
+            .collect();
+        let mut sd = StackDistance::new(32);
+        sd.run(trace.iter().copied());
+        for capacity in [1, 2, 8, 32, 40, 64, 200, 599, 600, 1000] {
+            let mut lru = FullyAssociativeLru::new(capacity);
+            let misses = trace.iter().filter(|&&a| !lru.access(a / 32)).count() as u64;
+            assert_eq!(sd.misses(capacity as u64), misses, "capacity {}", capacity);
+        }
+    }
+}
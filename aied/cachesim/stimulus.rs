@@ -0,0 +1,529 @@
+//! Constrained-random stimulus for the cycle models and the RTL.
+//!
+//! `Knobs` weight what the generator favours: accesses to a few conflict
+//! sets, each with more tags than the cache has ways so victims and
+//! write-backs keep coming; the share of writes; which word of the block an
+//! access lands on; idle cycles between requests; and extra `mem_wait`
+//! cycles, added by wrapping the memory in a `JitterMemory`.
+//!
+//! Everything random flows from one `u64` seed: the same params, knobs and
+//! seed give the same requests and the same `mem_wait` pattern, cycle for
+//! cycle. `seed_from_env` takes it from `CACHESIM_SEED` when set, so a
+//! failure's printed seed replays it exactly.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::cosim::{Cosim, Divergence, Dut};
+use super::fsm::{CacheOutputs, CpuRequest, CycleModel};
+use super::memory::{MemResponse, Memory};
+use super::params::{clog2, mask, CacheParams};
+use super::rng::Rng;
+
+/// The environment variable `seed_from_env` reads, in decimal or `0x` hex.
+pub const SEED_VAR: &str = "CACHESIM_SEED";
+
+/// Mixed into the seed for the `mem_wait` stream, so jitter does not shift
+/// when the requests change.
+const JITTER_STREAM: u64 = 0x6A09_E667_F3BC_C908;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Knobs {
+    /// Probability an access goes to a conflict set rather than anywhere in
+    /// `footprint`.
+    pub conflict: f64,
+    /// Sets targeted, chosen at random from the seed.
+    pub conflict_sets: usize,
+    /// Distinct tags per conflict set; more than the ways forces evictions.
+    pub conflict_tags: usize,
+    /// Bytes from address 0 that other accesses fall in.
+    pub footprint: u64,
+    pub write_ratio: f64,
+    /// Relative weight of each word offset in a block, one per word; empty
+    /// for uniform.
+    pub word_weights: Vec<u32>,
+    /// Probability of an idle cycle before a request.
+    pub idle: f64,
+    /// Probability a memory operation is held off by extra `mem_wait`
+    /// cycles, and the most it can be.
+    pub jitter: f64,
+    pub jitter_max: u32,
+}
+
+impl Default for Knobs {
+    fn default() -> Self {
+        Knobs {
+            conflict: 0.5,
+            conflict_sets: 4,
+            conflict_tags: 6,
+            footprint: 256 * 1024,
+            write_ratio: 0.3,
+            word_weights: Vec::new(),
+            idle: 0.05,
+            jitter: 0.25,
+            jitter_max: 4,
+        }
+    }
+}
+
+impl Knobs {
+    /// Checks the knobs make sense for a cache of `params`.
+    pub fn validate(&self, params: &CacheParams) -> Result<(), String> {
+        let probability = |name, p: f64| {
+            if (0.0..=1.0).contains(&p) {
+                Ok(())
+            } else {
+                Err(format!("{} must be in [0, 1], not {}", name, p))
+            }
+        };
+        probability("conflict", self.conflict)?;
+        probability("write_ratio", self.write_ratio)?;
+        probability("idle", self.idle)?;
+        probability("jitter", self.jitter)?;
+        if self.idle == 1.0 {
+            return Err("idle of 1 never issues a request".to_string());
+        }
+        if self.conflict > 0.0 {
+            if self.conflict_sets == 0 || self.conflict_sets as u64 > params.num_sets() {
+                return Err(format!(
+                    "conflict_sets must be in 1..={}, not {}",
+                    params.num_sets(),
+                    self.conflict_sets
+                ));
+            }
+            let tags = 1u64 << params.tag_bits().clamp(0, 63);
+            if self.conflict_tags == 0 || self.conflict_tags as u64 > tags {
+                return Err(format!(
+                    "conflict_tags must be in 1..={}, not {}",
+                    tags, self.conflict_tags
+                ));
+            }
+        }
+        if self.conflict < 1.0
+            && (self.footprint < params.block_size_bytes as u64
+                || self.footprint > mask(params.addr_width) + 1)
+        {
+            return Err(format!(
+                "footprint of {} bytes does not fit a {}-bit address space",
+                self.footprint, params.addr_width
+            ));
+        }
+        if !self.word_weights.is_empty() {
+            if self.word_weights.len() != params.words_per_block() {
+                return Err(format!(
+                    "word_weights needs one weight per word ({}), not {}",
+                    params.words_per_block(),
+                    self.word_weights.len()
+                ));
+            }
+            if self.word_weights.iter().all(|&w| w == 0) {
+                return Err("word_weights are all zero".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The seed in `CACHESIM_SEED`, or a fresh one from the clock.
+pub fn seed_from_env() -> Result<u64, String> {
+    match env::var(SEED_VAR) {
+        Ok(s) => {
+            let s = s.trim();
+            let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => s.parse(),
+            };
+            parsed.map_err(|_| format!("{}={:?} is not a seed", SEED_VAR, s))
+        }
+        Err(_) => {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64);
+            Ok(Rng::new(nanos ^ std::process::id() as u64).next_u64())
+        }
+    }
+}
+
+/// An endless stream of requests, idle cycles included, for one seed.
+#[derive(Debug, Clone)]
+pub struct Stimulus {
+    params: CacheParams,
+    knobs: Knobs,
+    seed: u64,
+    rng: Rng,
+    /// `(index, tags)` of each conflict set.
+    conflict: Vec<(usize, Vec<u64>)>,
+    /// Running sums of `word_weights`.
+    word_cdf: Vec<u64>,
+}
+
+impl Stimulus {
+    pub fn new(params: CacheParams, knobs: Knobs, seed: u64) -> Result<Self, String> {
+        params.validate().map_err(|e| e.to_string())?;
+        knobs.validate(&params)?;
+        let mut rng = Rng::new(seed);
+        let mut conflict = Vec::new();
+        if knobs.conflict > 0.0 {
+            let tag_space = 1u64 << params.tag_bits().clamp(0, 63);
+            for index in pick_distinct(&mut rng, params.num_sets(), knobs.conflict_sets) {
+                let tags = pick_distinct(&mut rng, tag_space, knobs.conflict_tags);
+                conflict.push((index as usize, tags));
+            }
+        }
+        let word_cdf = knobs
+            .word_weights
+            .iter()
+            .scan(0, |sum, &w| {
+                *sum += w as u64;
+                Some(*sum)
+            })
+            .collect();
+        Ok(Stimulus {
+            params,
+            knobs,
+            seed,
+            rng,
+            conflict,
+            word_cdf,
+        })
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn knobs(&self) -> &Knobs {
+        &self.knobs
+    }
+
+    /// The `(index, tags)` of each conflict set.
+    pub fn conflict_sets(&self) -> &[(usize, Vec<u64>)] {
+        &self.conflict
+    }
+
+    /// Wraps `inner` in the `mem_wait` jitter for this seed.
+    pub fn jitter<M: Memory>(&self, inner: M) -> JitterMemory<M> {
+        JitterMemory::new(
+            inner,
+            self.params.block_size_bytes as usize,
+            self.knobs.jitter,
+            self.knobs.jitter_max,
+            self.seed ^ JITTER_STREAM,
+        )
+    }
+
+    fn word_offset(&mut self) -> u64 {
+        match self.word_cdf.last() {
+            Some(&total) => {
+                let u = self.rng.below(total);
+                self.word_cdf.partition_point(|&c| c <= u) as u64
+            }
+            None => self.rng.below(self.params.words_per_block() as u64),
+        }
+    }
+
+    fn addr(&mut self) -> u64 {
+        let word = self.word_offset() << clog2(self.params.data_bytes() as u64);
+        if !self.conflict.is_empty() && self.rng.chance(self.knobs.conflict) {
+            let (index, tags) = &self.conflict[self.rng.below(self.conflict.len() as u64) as usize];
+            let tag = tags[self.rng.below(tags.len() as u64) as usize];
+            self.params.block_addr(tag, *index) | word
+        } else {
+            let block = self.params.block_size_bytes as u64;
+            (self.rng.below(self.knobs.footprint / block) * block) | word
+        }
+    }
+}
+
+impl Iterator for Stimulus {
+    type Item = CpuRequest;
+
+    /// The next cycle's request: idle, or an access to hold until
+    /// `cpu_wait` drops.
+    fn next(&mut self) -> Option<CpuRequest> {
+        if self.rng.chance(self.knobs.idle) {
+            return Some(CpuRequest::idle());
+        }
+        let addr = self.addr();
+        Some(if self.rng.chance(self.knobs.write_ratio) {
+            CpuRequest::write(addr, self.rng.next_u64() & mask(self.params.data_width))
+        } else {
+            CpuRequest::read(addr)
+        })
+    }
+}
+
+/// `count` distinct values below `n`, in the order drawn.
+fn pick_distinct(rng: &mut Rng, n: u64, count: usize) -> Vec<u64> {
+    let mut picked = Vec::with_capacity(count);
+    while picked.len() < count {
+        let v = rng.below(n);
+        if !picked.contains(&v) {
+            picked.push(v);
+        }
+    }
+    picked
+}
+
+/// A memory that holds some operations off for extra `mem_wait` cycles
+/// before `inner` sees them.
+#[derive(Debug, Clone)]
+pub struct JitterMemory<M> {
+    pub inner: M,
+    block_bytes: usize,
+    chance: f64,
+    max: u32,
+    rng: Rng,
+    /// Extra cycles left for the operation in flight, if one is.
+    extra: Option<u32>,
+}
+
+impl<M: Memory> JitterMemory<M> {
+    pub fn new(inner: M, block_bytes: usize, chance: f64, max: u32, seed: u64) -> Self {
+        JitterMemory {
+            inner,
+            block_bytes,
+            chance,
+            max,
+            rng: Rng::new(seed),
+            extra: None,
+        }
+    }
+}
+
+impl<M: Memory> Memory for JitterMemory<M> {
+    fn cycle(&mut self, out: &CacheOutputs) -> MemResponse {
+        if !(out.mem_read || out.mem_write) || out.mem_addr.is_none() {
+            self.extra = None;
+            return self.inner.cycle(out);
+        }
+        let extra = match self.extra {
+            Some(extra) => extra,
+            None if self.max > 0 && self.rng.chance(self.chance) => {
+                1 + self.rng.below(self.max as u64) as u32
+            }
+            None => 0,
+        };
+        if extra > 0 {
+            self.extra = Some(extra - 1);
+            return MemResponse {
+                mem_wait: true,
+                mem_rdata: vec![0; self.block_bytes],
+            };
+        }
+        let resp = self.inner.cycle(out);
+        // The next operation, even back to back, draws its own jitter.
+        self.extra = resp.mem_wait.then_some(0);
+        resp
+    }
+}
+
+/// A random run that diverged, with what it takes to replay it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub seed: u64,
+    /// Requests issued before the failing one, idle cycles included.
+    pub request: u64,
+    pub divergence: Divergence,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seed {:#x}, request {}: {} (replay with {}={:#x})",
+            self.seed, self.request, self.divergence, SEED_VAR, self.seed
+        )
+    }
+}
+
+impl Error for Failure {}
+
+/// Drives `count` requests from `stimulus` through `cosim`, which should be
+/// fresh and use `stimulus.jitter` around its memory for the run to replay.
+pub fn run_cosim<G, D, M>(
+    cosim: &mut Cosim<G, D, M>,
+    stimulus: &mut Stimulus,
+    count: u64,
+) -> Result<(), Failure>
+where
+    G: CycleModel,
+    D: Dut,
+    M: Memory,
+{
+    let seed = stimulus.seed();
+    for (request, cpu) in stimulus.take(count as usize).enumerate() {
+        let result = if cpu.is_active() {
+            cosim.access(cpu).map(|_| ())
+        } else {
+            cosim.step(&cpu).map(|_| ())
+        };
+        result.map_err(|divergence| Failure {
+            seed,
+            request: request as u64,
+            divergence,
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::direct_mapped::DirectMappedCache;
+    use super::super::memory::MainMemory;
+    use super::*;
+
+    fn set_associative() -> CacheParams {
+        CacheParams {
+            num_ways: 4,
+            ..CacheParams::default()
+        }
+    }
+
+    fn requests(seed: u64) -> Vec<CpuRequest> {
+        Stimulus::new(set_associative(), Knobs::default(), seed)
+            .unwrap()
+            .take(500)
+            .collect()
+    }
+
+    /// The `mem_wait` cycles of 100 back-to-back reads through `memory`.
+    fn waits<M: Memory>(mut memory: M) -> Vec<u32> {
+        let out = CacheOutputs {
+            mem_read: true,
+            mem_addr: Some(0x40),
+            ..CacheOutputs::default()
+        };
+        (0..100)
+            .map(|_| {
+                let mut wait = 0;
+                while memory.cycle(&out).mem_wait {
+                    wait += 1;
+                }
+                wait
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_requests() {
+        assert_eq!(requests(7), requests(7));
+        assert_ne!(requests(7), requests(8));
+        let idle = requests(7).iter().filter(|r| !r.is_active()).count();
+        assert!(idle > 0 && idle < 100, "{} idle cycles", idle);
+    }
+
+    #[test]
+    fn same_seed_same_jitter() {
+        let jitter = |seed| {
+            let stimulus = Stimulus::new(set_associative(), Knobs::default(), seed).unwrap();
+            waits(stimulus.jitter(MainMemory::new(32, 2)))
+        };
+        assert_eq!(jitter(7), jitter(7));
+        assert_ne!(jitter(7), jitter(8));
+        // Jitter only ever adds to the inner memory's two cycles, by at
+        // most jitter_max.
+        assert!(jitter(7).iter().all(|w| (2..=6).contains(w)));
+        assert!(jitter(7).iter().any(|&w| w > 2));
+        let none = JitterMemory::new(MainMemory::new(32, 2), 32, 0.0, 4, 7);
+        assert_eq!(waits(none), [2; 100]);
+    }
+
+    #[test]
+    fn conflict_accesses_hit_the_chosen_sets() {
+        let params = set_associative();
+        let knobs = Knobs {
+            conflict: 1.0,
+            conflict_sets: 2,
+            conflict_tags: 6,
+            idle: 0.0,
+            word_weights: vec![0, 0, 1, 0, 0, 0, 0, 0],
+            ..Knobs::default()
+        };
+        let mut stimulus = Stimulus::new(params, knobs, 11).unwrap();
+        let sets = stimulus.conflict_sets().to_vec();
+        assert_eq!(sets.len(), 2);
+        assert_ne!(sets[0].0, sets[1].0);
+        for cpu in stimulus.by_ref().take(300) {
+            let index = params.addr_index(cpu.cpu_addr);
+            let (_, tags) = sets.iter().find(|(i, _)| *i == index).unwrap();
+            assert!(tags.contains(&params.addr_tag(cpu.cpu_addr)));
+            assert_eq!(params.addr_word_offset(cpu.cpu_addr), 2);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_knobs() {
+        let params = set_associative();
+        let reject = |knobs: Knobs| knobs.validate(&params).unwrap_err();
+        assert_eq!(
+            reject(Knobs {
+                write_ratio: 1.5,
+                ..Knobs::default()
+            }),
+            "write_ratio must be in [0, 1], not 1.5"
+        );
+        assert_eq!(
+            reject(Knobs {
+                idle: 1.0,
+                ..Knobs::default()
+            }),
+            "idle of 1 never issues a request"
+        );
+        assert_eq!(
+            reject(Knobs {
+                conflict_sets: 513,
+                ..Knobs::default()
+            }),
+            "conflict_sets must be in 1..=512, not 513"
+        );
+        assert_eq!(
+            reject(Knobs {
+                conflict_tags: 0,
+                ..Knobs::default()
+            }),
+            "conflict_tags must be in 1..=262144, not 0"
+        );
+        assert_eq!(
+            reject(Knobs {
+                footprint: 16,
+                ..Knobs::default()
+            }),
+            "footprint of 16 bytes does not fit a 32-bit address space"
+        );
+        assert_eq!(
+            reject(Knobs {
+                word_weights: vec![1, 2],
+                ..Knobs::default()
+            }),
+            "word_weights needs one weight per word (8), not 2"
+        );
+        assert_eq!(
+            reject(Knobs {
+                word_weights: vec![0; 8],
+                ..Knobs::default()
+            }),
+            "word_weights are all zero"
+        );
+        // Without conflict accesses the conflict knobs go unchecked.
+        let no_conflict = Knobs {
+            conflict: 0.0,
+            conflict_sets: 0,
+            ..Knobs::default()
+        };
+        assert!(no_conflict.validate(&params).is_ok());
+    }
+
+    #[test]
+    fn golden_run_is_clean() {
+        let params = CacheParams::default();
+        let mut stimulus = Stimulus::new(params, Knobs::default(), 3).unwrap();
+        let golden = || DirectMappedCache::new(params).unwrap();
+        let memory = stimulus.jitter(MainMemory::new(32, 1));
+        let mut cosim = Cosim::new(golden(), golden(), memory);
+        assert_eq!(run_cosim(&mut cosim, &mut stimulus, 300), Ok(()));
+        assert!(cosim.protocol().is_clean());
+    }
+}
@@ -0,0 +1,1064 @@
+//! Module headers read straight from the SystemVerilog sources.
+//!
+//! Only as much SystemVerilog as the cache headers use is understood: the
+//! `module name #(parameter ...) (ports);` header, ANSI port declarations
+//! with packed ranges, and `localparam` declarations in the body. Parameter
+//! and range expressions are integers, names declared earlier, `+ - * / %`,
+//! shifts, parentheses and `$clog2`, evaluated as the elaborator would, so
+//! overriding a parameter recomputes every width and localparam after it.
+//!
+//! `SvModule::cache_params` turns the result into `CacheParams`, after
+//! checking that OFFSET_BITS, INDEX_BITS, TAG_BITS and WORD_OFFSET_BITS as
+//! the source derives them agree with `params`, and `SvModule::model`
+//! builds the golden model of whichever module was read.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::cosim::RtlModule;
+use super::coverage::CoverageModel;
+use super::direct_mapped::DirectMappedCache;
+use super::fsm::{CacheOutputs, CpuRequest, CycleModel, State};
+use super::params::{clog2, CacheParams, ParamError};
+use super::set_associative::SetAssociativeCache;
+
+#[derive(Debug)]
+pub enum SvError {
+    Io(io::Error),
+    /// Source this parser does not understand, at a 1-based line number.
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// A parameter or port the models need is missing or has the wrong
+    /// shape.
+    Interface(String),
+    Params(ParamError),
+}
+
+impl fmt::Display for SvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SvError::Io(e) => write!(f, "SystemVerilog I/O error: {}", e),
+            SvError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            SvError::Interface(message) => f.write_str(message),
+            SvError::Params(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for SvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SvError::Io(e) => Some(e),
+            SvError::Params(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SvError {
+    fn from(e: io::Error) -> Self {
+        SvError::Io(e)
+    }
+}
+
+impl From<ParamError> for SvError {
+    fn from(e: ParamError) -> Self {
+        SvError::Params(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Ident(String),
+    Num(i64),
+    Punct(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    tok: Tok,
+    line: usize,
+}
+
+const PUNCT: [&str; 18] = [
+    "<<", ">>", "#", "(", ")", "[", "]", ":", ";", ",", "=", "+", "-", "*", "/", "%", ".", "@",
+];
+
+fn parse_error(line: usize, message: impl Into<String>) -> SvError {
+    SvError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Splits `src` into tokens, dropping comments and strings. Operators
+/// outside the subset, which only the body uses, become `?` placeholders.
+fn tokenize(src: &str) -> Result<Vec<Token>, SvError> {
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    let (mut i, mut line) = (0, 1);
+    while i < bytes.len() {
+        let c = bytes[i];
+        let rest = &src[i..];
+        if c == b'\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_ascii_whitespace() {
+            i += 1;
+        } else if rest.starts_with("//") {
+            i += rest.find('\n').unwrap_or(rest.len());
+        } else if rest.starts_with("/*") {
+            let end = rest
+                .find("*/")
+                .ok_or_else(|| parse_error(line, "unterminated block comment"))?;
+            line += rest[..end].matches('\n').count();
+            i += end + 2;
+        } else if c == b'"' {
+            // Strings only appear in `$fatal` messages, which are skipped.
+            let end = rest[1..]
+                .find('"')
+                .ok_or_else(|| parse_error(line, "unterminated string"))?;
+            i += end + 2;
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' || c == b'`' {
+            let len = rest
+                .find(|ch: char| {
+                    !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '$' || ch == '`')
+                })
+                .unwrap_or(rest.len());
+            tokens.push(Token {
+                tok: Tok::Ident(rest[..len].to_string()),
+                line,
+            });
+            i += len;
+        } else if let Some((value, len)) = number(rest) {
+            tokens.push(Token {
+                tok: Tok::Num(value),
+                line,
+            });
+            i += len;
+        } else {
+            let punct = PUNCT.iter().find(|p| rest.starts_with(**p)).copied();
+            // Other operators only occur in the body, which is skimmed for
+            // `localparam`; keep a placeholder so statements still split.
+            let punct = punct.unwrap_or("?");
+            tokens.push(Token {
+                tok: Tok::Punct(punct),
+                line,
+            });
+            i += if punct == "?" {
+                rest.chars().next().map_or(1, char::len_utf8)
+            } else {
+                punct.len()
+            };
+        }
+    }
+    Ok(tokens)
+}
+
+/// A decimal or based literal at the start of `s`, such as `32`, `8'hff`,
+/// `'b1`; returns its value and length. `x` and `z` digits read as 0. Fill
+/// literals such as `'1` and casts such as `32'(x)` are not numbers here.
+fn number(s: &str) -> Option<(i64, usize)> {
+    let digits = |s: &str, radix: u32| {
+        s.find(|c: char| !(c.is_digit(radix) || c == '_' || "xXzZ?".contains(c)))
+            .unwrap_or(s.len())
+    };
+    let size_len = if s.starts_with(|c: char| c.is_ascii_digit()) {
+        s.find(|c: char| !(c.is_ascii_digit() || c == '_'))
+            .unwrap_or(s.len())
+    } else {
+        0
+    };
+    let after = &s[size_len..];
+    if let Some(based) = after.strip_prefix('\'') {
+        let based = based.strip_prefix(['s', 'S']).unwrap_or(based);
+        let skipped = s.len() - size_len - 1 - based.len();
+        let radix = match based.chars().next()?.to_ascii_lowercase() {
+            'd' => 10,
+            'h' => 16,
+            'b' => 2,
+            'o' => 8,
+            _ if size_len > 0 => {
+                let clean: String = s[..size_len].chars().filter(|&c| c != '_').collect();
+                return Some((clean.parse().ok()?, size_len));
+            }
+            _ => return None,
+        };
+        let body = &based[1..];
+        let len = digits(body, radix);
+        let clean: String = body[..len]
+            .chars()
+            .filter(|&c| c != '_')
+            .map(|c| if "xXzZ?".contains(c) { '0' } else { c })
+            .collect();
+        let value = i64::from_str_radix(&clean, radix).ok()?;
+        Some((value, size_len + 1 + skipped + 1 + len))
+    } else if size_len > 0 {
+        let clean: String = s[..size_len].chars().filter(|&c| c != '_').collect();
+        Some((clean.parse().ok()?, size_len))
+    } else {
+        None
+    }
+}
+
+/// Values of the parameters and localparams in scope.
+type Scope = Vec<(String, i64)>;
+
+fn lookup(scope: &Scope, name: &str) -> Option<i64> {
+    scope.iter().rev().find(|(n, _)| n == name).map(|&(_, v)| v)
+}
+
+/// Evaluates a constant expression.
+struct Eval<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    scope: &'a Scope,
+}
+
+impl Eval<'_> {
+    fn run(tokens: &[Token], scope: &Scope) -> Result<i64, SvError> {
+        let line = tokens.first().map_or(0, |t| t.line);
+        if tokens.is_empty() {
+            return Err(parse_error(line, "empty expression"));
+        }
+        let mut eval = Eval {
+            tokens,
+            pos: 0,
+            scope,
+        };
+        let value = eval.expr(0)?;
+        match tokens.get(eval.pos) {
+            None => Ok(value),
+            Some(t) => Err(parse_error(
+                t.line,
+                format!("unexpected {:?} in expression", t.tok),
+            )),
+        }
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(0, |t| t.line)
+    }
+
+    fn peek_punct(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos).map(|t| &t.tok) {
+            Some(Tok::Punct(p)) => Some(p),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), SvError> {
+        if self.peek_punct() == Some(punct) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(parse_error(self.line(), format!("expected `{}`", punct)))
+        }
+    }
+
+    /// Precedence climbing over shifts, additive and multiplicative
+    /// operators.
+    fn expr(&mut self, min_prec: u8) -> Result<i64, SvError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let (op, prec) = match self.peek_punct() {
+                Some(op @ ("<<" | ">>")) => (op, 1),
+                Some(op @ ("+" | "-")) => (op, 2),
+                Some(op @ ("*" | "/" | "%")) => (op, 3),
+                _ => break,
+            };
+            if prec < min_prec {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(prec + 1)?;
+            let line = self.line();
+            let div_zero = || parse_error(line, "division by zero");
+            lhs = match op {
+                "<<" => lhs.checked_shl(rhs as u32).unwrap_or(0),
+                ">>" => lhs.checked_shr(rhs as u32).unwrap_or(0),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" => lhs.checked_div(rhs).ok_or_else(div_zero)?,
+                _ => lhs.checked_rem(rhs).ok_or_else(div_zero)?,
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, SvError> {
+        let line = self.line();
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| parse_error(line, "expression ends early"))?;
+        self.pos += 1;
+        match &token.tok {
+            Tok::Num(v) => Ok(*v),
+            Tok::Punct("-") => Ok(-self.unary()?),
+            Tok::Punct("+") => self.unary(),
+            Tok::Punct("(") => {
+                let v = self.expr(0)?;
+                self.expect(")")?;
+                Ok(v)
+            }
+            Tok::Ident(name) if name == "$clog2" => {
+                self.expect("(")?;
+                let v = self.expr(0)?;
+                self.expect(")")?;
+                Ok(clog2(v.max(0) as u64) as i64)
+            }
+            Tok::Ident(name) => lookup(self.scope, name)
+                .ok_or_else(|| parse_error(token.line, format!("unknown name `{}`", name))),
+            Tok::Punct(p) => Err(parse_error(token.line, format!("unexpected `{}`", p))),
+        }
+    }
+}
+
+/// A `parameter` or `localparam`, with its value under the current
+/// overrides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SvParam {
+    pub name: String,
+    /// The declared expression, tokens separated by spaces.
+    pub expr: String,
+    pub value: i64,
+    /// Where it was declared.
+    pub line: usize,
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Input,
+    Output,
+    Inout,
+}
+
+impl Direction {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            Direction::Input => "input",
+            Direction::Output => "output",
+            Direction::Inout => "inout",
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.keyword())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SvPort {
+    pub name: String,
+    pub direction: Direction,
+    /// Bits, from the packed range; 1 without one.
+    pub width: u32,
+    /// The range as declared, such as `[ADDR_WIDTH-1:0]`.
+    pub range: Option<String>,
+    pub line: usize,
+    range_tokens: Option<(Vec<Token>, Vec<Token>)>,
+}
+
+/// A module header and its localparams.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SvModule {
+    pub name: String,
+    pub params: Vec<SvParam>,
+    pub ports: Vec<SvPort>,
+    pub localparams: Vec<SvParam>,
+}
+
+fn join(tokens: &[Token]) -> String {
+    let parts: Vec<String> = tokens
+        .iter()
+        .map(|t| match &t.tok {
+            Tok::Ident(s) => s.clone(),
+            Tok::Num(v) => v.to_string(),
+            Tok::Punct(p) => p.to_string(),
+        })
+        .collect();
+    parts.join(" ")
+}
+
+fn is_ident(token: Option<&Token>, word: &str) -> bool {
+    matches!(token, Some(Token { tok: Tok::Ident(s), .. }) if s == word)
+}
+
+fn is_punct(token: Option<&Token>, punct: &str) -> bool {
+    matches!(token, Some(Token { tok: Tok::Punct(p), .. }) if *p == punct)
+}
+
+/// Splits the tokens between the brackets opening at `open` on top-level
+/// commas; returns the items and the index past the closing bracket.
+fn bracketed(tokens: &[Token], open: usize) -> Result<(Vec<&[Token]>, usize), SvError> {
+    let mut depth = 0;
+    let mut items = Vec::new();
+    let mut start = open + 1;
+    for (i, t) in tokens.iter().enumerate().skip(open) {
+        match t.tok {
+            Tok::Punct("(" | "[") => depth += 1,
+            Tok::Punct(")" | "]") => {
+                depth -= 1;
+                if depth == 0 {
+                    if i > start {
+                        items.push(&tokens[start..i]);
+                    }
+                    return Ok((items, i + 1));
+                }
+            }
+            Tok::Punct(",") if depth == 1 => {
+                items.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    Err(parse_error(tokens[open].line, "unbalanced brackets"))
+}
+
+/// Data types and qualifiers that may precede a declared name.
+const TYPE_WORDS: [&str; 13] = [
+    "parameter",
+    "localparam",
+    "int",
+    "integer",
+    "logic",
+    "bit",
+    "reg",
+    "wire",
+    "var",
+    "signed",
+    "unsigned",
+    "longint",
+    "type",
+];
+
+/// Parses `[type] [range] NAME = expr` into a parameter.
+fn declaration(item: &[Token], scope: &Scope) -> Result<SvParam, SvError> {
+    let line = item.first().map_or(0, |t| t.line);
+    let eq = item
+        .iter()
+        .position(|t| is_punct(Some(t), "="))
+        .ok_or_else(|| parse_error(line, "parameter without a value"))?;
+    let name = match item[..eq].last().map(|t| &t.tok) {
+        Some(Tok::Ident(name)) if !TYPE_WORDS.contains(&name.as_str()) => name.clone(),
+        _ => return Err(parse_error(line, "expected a parameter name")),
+    };
+    let tokens = item[eq + 1..].to_vec();
+    Ok(SvParam {
+        name,
+        expr: join(&tokens),
+        value: Eval::run(&tokens, scope)?,
+        line,
+        tokens,
+    })
+}
+
+fn port(
+    item: &[Token],
+    direction: &mut Option<Direction>,
+    scope: &Scope,
+) -> Result<SvPort, SvError> {
+    let line = item.first().map_or(0, |t| t.line);
+    let keyword = match item.first().map(|t| &t.tok) {
+        Some(Tok::Ident(w)) => match w.as_str() {
+            "input" => Some(Direction::Input),
+            "output" => Some(Direction::Output),
+            "inout" => Some(Direction::Inout),
+            _ => None,
+        },
+        _ => None,
+    };
+    // ANSI ports without a direction keep the previous port's.
+    let mut i = 0;
+    if keyword.is_some() {
+        *direction = keyword;
+        i = 1;
+    }
+    let direction = direction.ok_or_else(|| parse_error(line, "port without a direction"))?;
+    while matches!(item.get(i).map(|t| &t.tok), Some(Tok::Ident(w)) if TYPE_WORDS.contains(&w.as_str()))
+    {
+        i += 1;
+    }
+    let mut range_tokens = None;
+    if is_punct(item.get(i), "[") {
+        let (_, end) = bracketed(item, i)?;
+        let inner = &item[i + 1..end - 1];
+        let colon = inner
+            .iter()
+            .position(|t| is_punct(Some(t), ":"))
+            .ok_or_else(|| parse_error(line, "packed range without `:`"))?;
+        range_tokens = Some((inner[..colon].to_vec(), inner[colon + 1..].to_vec()));
+        i = end;
+    }
+    let name = match item.get(i).map(|t| &t.tok) {
+        Some(Tok::Ident(name)) if i + 1 == item.len() => name.clone(),
+        _ => return Err(parse_error(line, "expected a port name")),
+    };
+    let mut port = SvPort {
+        name,
+        direction,
+        width: 1,
+        range: range_tokens
+            .as_ref()
+            .map(|(msb, lsb)| format!("[{}:{}]", join(msb), join(lsb))),
+        line,
+        range_tokens,
+    };
+    port.width = port_width(&port, scope)?;
+    Ok(port)
+}
+
+fn port_width(port: &SvPort, scope: &Scope) -> Result<u32, SvError> {
+    match &port.range_tokens {
+        None => Ok(1),
+        Some((msb, lsb)) => {
+            let (msb, lsb) = (Eval::run(msb, scope)?, Eval::run(lsb, scope)?);
+            Ok((msb - lsb).unsigned_abs() as u32 + 1)
+        }
+    }
+}
+
+impl SvModule {
+    /// The first module in `src`.
+    pub fn parse(src: &str) -> Result<Self, SvError> {
+        Self::parse_all(src)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| parse_error(1, "no module"))
+    }
+
+    /// Every module in `src`, in order.
+    pub fn parse_all(src: &str) -> Result<Vec<Self>, SvError> {
+        let tokens = tokenize(src)?;
+        let mut modules = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            if is_ident(tokens.get(i), "module") {
+                let (module, end) = Self::parse_at(&tokens, i + 1)?;
+                modules.push(module);
+                i = end;
+            } else {
+                i += 1;
+            }
+        }
+        Ok(modules)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SvError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Reads `module` from its source file in `dir`, the directory that
+    /// holds the .sv files.
+    pub fn load_rtl<P: AsRef<Path>>(dir: P, module: RtlModule) -> Result<Self, SvError> {
+        let src = fs::read_to_string(dir.as_ref().join(module.source()))?;
+        Self::parse_all(&src)?
+            .into_iter()
+            .find(|m| m.name == module.name())
+            .ok_or_else(|| {
+                SvError::Interface(format!(
+                    "{} has no module {}",
+                    module.source(),
+                    module.name()
+                ))
+            })
+    }
+
+    /// Parses from the module name at `i`; returns the module and the index
+    /// past `endmodule`.
+    fn parse_at(tokens: &[Token], mut i: usize) -> Result<(Self, usize), SvError> {
+        let line = tokens.get(i).map_or(0, |t| t.line);
+        let name = match tokens.get(i).map(|t| &t.tok) {
+            Some(Tok::Ident(name)) => name.clone(),
+            _ => return Err(parse_error(line, "expected a module name")),
+        };
+        i += 1;
+        let mut scope = Scope::new();
+        let mut params = Vec::new();
+        if is_punct(tokens.get(i), "#") {
+            if !is_punct(tokens.get(i + 1), "(") {
+                return Err(parse_error(line, "expected `(` after `#`"));
+            }
+            let (items, end) = bracketed(tokens, i + 1)?;
+            for item in items {
+                let param = declaration(item, &scope)?;
+                scope.push((param.name.clone(), param.value));
+                params.push(param);
+            }
+            i = end;
+        }
+        let mut ports = Vec::new();
+        if is_punct(tokens.get(i), "(") {
+            let (items, end) = bracketed(tokens, i)?;
+            let mut direction = None;
+            for item in items {
+                ports.push(port(item, &mut direction, &scope)?);
+            }
+            i = end;
+        }
+        if !is_punct(tokens.get(i), ";") {
+            return Err(parse_error(line, "expected `;` after the module header"));
+        }
+
+        let mut localparams = Vec::new();
+        while i < tokens.len() && !is_ident(tokens.get(i), "endmodule") {
+            if is_ident(tokens.get(i), "localparam") {
+                let end = (i..tokens.len())
+                    .find(|&j| is_punct(tokens.get(j), ";"))
+                    .ok_or_else(|| parse_error(tokens[i].line, "localparam without `;`"))?;
+                // `localparam A = 1, B = A + 1;` declares both.
+                let mut start = i + 1;
+                let mut depth = 0;
+                for j in i + 1..=end {
+                    match tokens[j].tok {
+                        Tok::Punct("(" | "[") => depth += 1,
+                        Tok::Punct(")" | "]") => depth -= 1,
+                        Tok::Punct("," | ";") if depth == 0 => {
+                            let param = declaration(&tokens[start..j], &scope)?;
+                            scope.push((param.name.clone(), param.value));
+                            localparams.push(param);
+                            start = j + 1;
+                        }
+                        _ => {}
+                    }
+                }
+                i = end;
+            }
+            i += 1;
+        }
+        let module = SvModule {
+            name,
+            params,
+            ports,
+            localparams,
+        };
+        Ok((module, i + 1))
+    }
+
+    /// The value of a parameter or localparam.
+    pub fn value(&self, name: &str) -> Option<i64> {
+        self.params
+            .iter()
+            .chain(&self.localparams)
+            .find(|p| p.name == name)
+            .map(|p| p.value)
+    }
+
+    pub fn port(&self, name: &str) -> Option<&SvPort> {
+        self.ports.iter().find(|p| p.name == name)
+    }
+
+    /// The module elaborated with `overrides` in place of the parameter
+    /// defaults, as `#(.NAME(value))` would.
+    pub fn with_overrides(&self, overrides: &[(&str, i64)]) -> Result<Self, SvError> {
+        for (name, _) in overrides {
+            if !self.params.iter().any(|p| p.name == *name) {
+                return Err(SvError::Interface(format!(
+                    "{} has no parameter {}",
+                    self.name, name
+                )));
+            }
+        }
+        let mut module = self.clone();
+        let mut scope = Scope::new();
+        for param in module.params.iter_mut().chain(&mut module.localparams) {
+            param.value = match overrides.iter().find(|(n, _)| *n == param.name) {
+                Some(&(_, value)) => value,
+                None => Eval::run(&param.tokens, &scope)?,
+            };
+            scope.push((param.name.clone(), param.value));
+        }
+        for port in &mut module.ports {
+            port.width = port_width(port, &scope)?;
+        }
+        Ok(module)
+    }
+
+    /// The module elaborated for `params`.
+    pub fn for_params(&self, params: &CacheParams) -> Result<Self, SvError> {
+        let mut overrides = vec![
+            ("ADDR_WIDTH", params.addr_width as i64),
+            ("DATA_WIDTH", params.data_width as i64),
+            ("CACHE_SIZE_KB", params.cache_size_kb as i64),
+            ("BLOCK_SIZE_BYTES", params.block_size_bytes as i64),
+        ];
+        if self.params.iter().any(|p| p.name == "NUM_WAYS") {
+            overrides.push(("NUM_WAYS", params.num_ways as i64));
+        } else if params.num_ways != 1 {
+            return Err(SvError::Params(ParamError::Ways(params.num_ways)));
+        }
+        self.with_overrides(&overrides)
+    }
+
+    fn required(&self, name: &str) -> Result<u32, SvError> {
+        let value = self.value(name).ok_or_else(|| {
+            SvError::Interface(format!("{} has no parameter {}", self.name, name))
+        })?;
+        u32::try_from(value)
+            .map_err(|_| SvError::Interface(format!("{} = {} is out of range", name, value)))
+    }
+
+    /// The cache parameters, NUM_WAYS being 1 if the module has none. The
+    /// derived localparams the source declares must match `CacheParams`'s.
+    pub fn cache_params(&self) -> Result<CacheParams, SvError> {
+        let params = CacheParams {
+            addr_width: self.required("ADDR_WIDTH")?,
+            data_width: self.required("DATA_WIDTH")?,
+            cache_size_kb: self.required("CACHE_SIZE_KB")?,
+            block_size_bytes: self.required("BLOCK_SIZE_BYTES")?,
+            num_ways: match self.value("NUM_WAYS") {
+                Some(_) => self.required("NUM_WAYS")?,
+                None => 1,
+            },
+        };
+        params.validate()?;
+        if let Some((name, rtl, model)) = self.derived_mismatches(&params).first() {
+            return Err(SvError::Interface(format!(
+                "{} derives {} = {}, the model {}",
+                self.name, name, rtl, model
+            )));
+        }
+        Ok(params)
+    }
+
+    /// The derived localparams the source declares that differ from
+    /// `params`'s, as `(name, source value, model value)`.
+    pub fn derived_mismatches(&self, params: &CacheParams) -> Vec<(&'static str, i64, i64)> {
+        let derived = [
+            ("OFFSET_BITS", params.offset_bits() as i64),
+            ("INDEX_BITS", params.index_bits() as i64),
+            ("TAG_BITS", params.tag_bits()),
+            ("WORD_OFFSET_BITS", params.word_offset_bits() as i64),
+        ];
+        derived
+            .into_iter()
+            .filter_map(|(name, model)| match self.value(name) {
+                Some(rtl) if rtl != model => Some((name, rtl, model)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Checks every port the models drive or sample is declared with the
+    /// direction and width `params` implies.
+    pub fn check_ports(&self, params: &CacheParams) -> Result<(), SvError> {
+        let block_bits = params.block_size_bits() as u32;
+        let expected = [
+            ("clk", Direction::Input, 1),
+            ("rst_n", Direction::Input, 1),
+            ("cpu_addr", Direction::Input, params.addr_width),
+            ("cpu_read", Direction::Input, 1),
+            ("cpu_write", Direction::Input, 1),
+            ("cpu_wdata", Direction::Input, params.data_width),
+            ("cpu_rdata", Direction::Output, params.data_width),
+            ("cpu_wait", Direction::Output, 1),
+            ("mem_addr", Direction::Output, params.addr_width),
+            ("mem_read", Direction::Output, 1),
+            ("mem_write", Direction::Output, 1),
+            ("mem_rdata", Direction::Input, block_bits),
+            ("mem_wdata", Direction::Output, block_bits),
+            ("mem_wait", Direction::Input, 1),
+        ];
+        for (name, direction, width) in expected {
+            let port = self
+                .port(name)
+                .ok_or_else(|| SvError::Interface(format!("{} has no port {}", self.name, name)))?;
+            if port.direction != direction || port.width != width {
+                return Err(SvError::Interface(format!(
+                    "{} declares {} {} of {} bits, expected {} of {}",
+                    self.name, port.direction, name, port.width, direction, width
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Which cache this is, by module name.
+    pub fn rtl_module(&self) -> Option<RtlModule> {
+        [RtlModule::DirectMapped, RtlModule::SetAssociative]
+            .into_iter()
+            .find(|m| m.name() == self.name)
+    }
+
+    /// The golden model of this module with its parameters, after checking
+    /// the ports.
+    pub fn model(&self) -> Result<Model, SvError> {
+        let params = self.cache_params()?;
+        self.check_ports(&params)?;
+        match self.rtl_module() {
+            Some(RtlModule::DirectMapped) => {
+                Ok(Model::DirectMapped(DirectMappedCache::new(params)?))
+            }
+            Some(RtlModule::SetAssociative) => {
+                Ok(Model::SetAssociative(SetAssociativeCache::new(params)?))
+            }
+            None => Err(SvError::Interface(format!(
+                "no golden model for module {}",
+                self.name
+            ))),
+        }
+    }
+}
+
+/// The golden model of either cache, chosen at run time.
+#[derive(Debug)]
+pub enum Model {
+    DirectMapped(DirectMappedCache),
+    SetAssociative(SetAssociativeCache),
+}
+
+impl Model {
+    /// The model of the module `RtlModule::for_params` picks.
+    pub fn for_params(params: CacheParams) -> Result<Self, ParamError> {
+        Ok(match RtlModule::for_params(&params) {
+            RtlModule::DirectMapped => Model::DirectMapped(DirectMappedCache::new(params)?),
+            RtlModule::SetAssociative => Model::SetAssociative(SetAssociativeCache::new(params)?),
+        })
+    }
+}
+
+impl CycleModel for Model {
+    fn eval(&self, cpu: &CpuRequest) -> CacheOutputs {
+        match self {
+            Model::DirectMapped(c) => c.eval(cpu),
+            Model::SetAssociative(c) => c.eval(cpu),
+        }
+    }
+
+    fn clock(&mut self, cpu: &CpuRequest, mem_wait: bool, mem_rdata: &[u8]) {
+        match self {
+            Model::DirectMapped(c) => c.clock(cpu, mem_wait, mem_rdata),
+            Model::SetAssociative(c) => c.clock(cpu, mem_wait, mem_rdata),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Model::DirectMapped(c) => c.reset(),
+            Model::SetAssociative(c) => c.reset(),
+        }
+    }
+
+    fn state(&self) -> State {
+        match self {
+            Model::DirectMapped(c) => c.state(),
+            Model::SetAssociative(c) => c.state(),
+        }
+    }
+}
+
+impl CoverageModel for Model {
+    fn params(&self) -> &CacheParams {
+        match self {
+            Model::DirectMapped(c) => c.params(),
+            Model::SetAssociative(c) => c.params(),
+        }
+    }
+
+    fn lru_bits(&self, set: usize) -> Option<u64> {
+        match self {
+            Model::DirectMapped(c) => CoverageModel::lru_bits(c, set),
+            Model::SetAssociative(c) => CoverageModel::lru_bits(c, set),
+        }
+    }
+
+    fn last_victim(&self) -> Option<(usize, bool)> {
+        match self {
+            Model::DirectMapped(c) => c.last_victim(),
+            Model::SetAssociative(c) => c.last_victim(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIRECT_MAPPED: &str = include_str!("../direct_mapped_64Kb.sv");
+    const SET_ASSOCIATIVE: &str = include_str!("../set_associative_64kb.sv");
+
+    fn values(params: &[SvParam]) -> Vec<(&str, i64)> {
+        params.iter().map(|p| (p.name.as_str(), p.value)).collect()
+    }
+
+    #[test]
+    fn reads_the_direct_mapped_header() {
+        let module = SvModule::parse(DIRECT_MAPPED).unwrap();
+        assert_eq!(module.name, "direct_mapped_cache");
+        assert_eq!(module.rtl_module(), Some(RtlModule::DirectMapped));
+        assert_eq!(module.cache_params().unwrap(), CacheParams::default());
+        assert_eq!(
+            values(&module.localparams),
+            [
+                ("CACHE_SIZE_BYTES", 65536),
+                ("BLOCK_SIZE_BITS", 256),
+                ("NUM_BLOCKS", 2048),
+                ("DATA_BYTES", 4),
+                ("OFFSET_BITS", 5),
+                ("INDEX_BITS", 11),
+                ("TAG_BITS", 16),
+                ("WORD_OFFSET_BITS", 3),
+            ]
+        );
+        assert_eq!(module.ports.len(), 14);
+        let rdata = module.port("mem_rdata").unwrap();
+        assert_eq!(rdata.direction, Direction::Input);
+        assert_eq!(rdata.width, 256);
+        assert_eq!(rdata.range.as_deref(), Some("[BLOCK_SIZE_BYTES * 8 - 1:0]"));
+        assert_eq!(rdata.line, 33);
+        assert!(module.check_ports(&CacheParams::default()).is_ok());
+        assert!(matches!(module.model(), Ok(Model::DirectMapped(_))));
+    }
+
+    #[test]
+    fn reads_the_set_associative_header() {
+        let module = SvModule::parse(SET_ASSOCIATIVE).unwrap();
+        assert_eq!(module.name, "set_associative_cache");
+        let params = module.cache_params().unwrap();
+        assert_eq!(
+            params,
+            CacheParams {
+                num_ways: 4,
+                ..CacheParams::default()
+            }
+        );
+        assert_eq!(module.value("NUM_SETS"), Some(512));
+        assert_eq!(module.value("INDEX_BITS"), Some(9));
+        assert_eq!(module.value("TAG_BITS"), Some(18));
+        assert_eq!(module.value("LRU_BITS"), Some(3));
+        assert!(module.derived_mismatches(&params).is_empty());
+        assert!(matches!(module.model(), Ok(Model::SetAssociative(_))));
+    }
+
+    #[test]
+    fn overrides_recompute_what_follows() {
+        let module = SvModule::parse(DIRECT_MAPPED).unwrap();
+        let small = module
+            .with_overrides(&[("CACHE_SIZE_KB", 16), ("BLOCK_SIZE_BYTES", 64)])
+            .unwrap();
+        // 16KB of 64-byte blocks: 256 lines, 6 offset bits, 8 index bits.
+        assert_eq!(small.value("NUM_BLOCKS"), Some(256));
+        assert_eq!(small.value("OFFSET_BITS"), Some(6));
+        assert_eq!(small.value("INDEX_BITS"), Some(8));
+        assert_eq!(small.value("TAG_BITS"), Some(18));
+        assert_eq!(small.value("WORD_OFFSET_BITS"), Some(4));
+        assert_eq!(small.port("mem_wdata").unwrap().width, 512);
+        let params = small.cache_params().unwrap();
+        assert_eq!((params.cache_size_kb, params.block_size_bytes), (16, 64));
+        assert!(small.check_ports(&params).is_ok());
+        // The declared expressions stay as written.
+        assert_eq!(small.localparams, {
+            let mut expected = module.localparams.clone();
+            for (param, value) in expected.iter_mut().zip([16384, 512, 256, 4, 6, 8, 18, 4]) {
+                param.value = value;
+            }
+            expected
+        });
+
+        let sa = SvModule::parse(SET_ASSOCIATIVE).unwrap();
+        let two_way = CacheParams {
+            num_ways: 2,
+            ..CacheParams::default()
+        };
+        assert_eq!(
+            sa.for_params(&two_way).unwrap().value("NUM_SETS"),
+            Some(1024)
+        );
+        assert!(matches!(
+            module.for_params(&two_way),
+            Err(SvError::Params(ParamError::Ways(2)))
+        ));
+        let err = module.with_overrides(&[("NUM_WAYS", 2)]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "direct_mapped_cache has no parameter NUM_WAYS"
+        );
+    }
+
+    #[test]
+    fn based_literals_and_clog2() {
+        let src = "
+            module m #(
+                parameter int A = 8'hff,
+                parameter B = 'b1,
+                parameter C = 4'd10 + 3'o7 * 16'h_1_0
+            ) (input logic [A-1:0] x, output z);
+                localparam D = $clog2(A + B), E = D << 2;
+                localparam F = $clog2(1) + (E - 1) % 5;
+            endmodule";
+        let module = SvModule::parse(src).unwrap();
+        assert_eq!(values(&module.params), [("A", 255), ("B", 1), ("C", 122)]);
+        // 256 needs 8 bits; 1 needs none.
+        assert_eq!(values(&module.localparams), [("D", 8), ("E", 32), ("F", 1)]);
+        let widths: Vec<_> = module
+            .ports
+            .iter()
+            .map(|p| (p.name.as_str(), p.direction, p.width))
+            .collect();
+        assert_eq!(
+            widths,
+            [("x", Direction::Input, 255), ("z", Direction::Output, 1),]
+        );
+        assert_eq!(module.params[2].expr, "10 + 7 * 16");
+    }
+
+    #[test]
+    fn unknown_names_are_parse_errors() {
+        let src = "module m #(\n    parameter A = 1,\n    parameter B = A + C\n);\nendmodule";
+        let err = SvModule::parse(src).unwrap_err();
+        assert!(matches!(err, SvError::Parse { line: 3, .. }), "{:?}", err);
+        assert_eq!(err.to_string(), "line 3: unknown name `C`");
+        assert!(SvModule::parse("// nothing here").is_err());
+    }
+
+    #[test]
+    fn derived_values_must_match_the_model() {
+        let src = DIRECT_MAPPED.replace(
+            "ADDR_WIDTH - INDEX_BITS - OFFSET_BITS",
+            "ADDR_WIDTH - INDEX_BITS",
+        );
+        let module = SvModule::parse(&src).unwrap();
+        let params = CacheParams::default();
+        assert_eq!(module.derived_mismatches(&params), [("TAG_BITS", 21, 16)]);
+        let err = module.cache_params().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "direct_mapped_cache derives TAG_BITS = 21, the model 16"
+        );
+        assert!(module.model().is_err());
+    }
+
+    #[test]
+    fn ports_must_have_the_implied_width() {
+        let src = DIRECT_MAPPED.replace(
+            "output logic [DATA_WIDTH-1:0] cpu_rdata",
+            "output logic [DATA_WIDTH-2:0] cpu_rdata",
+        );
+        let module = SvModule::parse(&src).unwrap();
+        let err = module.check_ports(&CacheParams::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "direct_mapped_cache declares output cpu_rdata of 31 bits, expected output of 32"
+        );
+        let module = SvModule::parse(DIRECT_MAPPED).unwrap();
+        let wide = CacheParams {
+            data_width: 64,
+            ..CacheParams::default()
+        };
+        assert!(module.check_ports(&wide).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+//! Design-space sweeps over the RTL parameters and replacement policies.
+//!
+//! A `SweepSpace` lists candidate values for each module parameter
+//! (CACHE_SIZE_KB, BLOCK_SIZE_BYTES, NUM_WAYS, ADDR_WIDTH, DATA_WIDTH) and
+//! the policies to try; `run` simulates every combination the RTL would
+//! elaborate on a functional cache, spread over all cores. Each worker
+//! replays the trace from its own iterator, so the trace can be re-read from
+//! disk rather than held in memory.
+//!
+//! Results carry the storage, area and energy `cost` estimates for the
+//! configuration; the points no other point beats on both miss ratio and
+//! storage are marked Pareto-optimal.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use super::cost::{CostEstimate, TechParams};
+use super::functional::{FunctionalCache, Stats};
+use super::params::CacheParams;
+use super::policy::PolicyKind;
+use super::trace::TraceError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepSpace {
+    pub cache_size_kb: Vec<u32>,
+    pub block_size_bytes: Vec<u32>,
+    pub num_ways: Vec<u32>,
+    pub addr_width: Vec<u32>,
+    pub data_width: Vec<u32>,
+    pub policies: Vec<PolicyKind>,
+    /// Technology figures for each point's cost estimate.
+    pub tech: TechParams,
+}
+
+impl Default for SweepSpace {
+    /// Sizes and shapes around the RTL defaults, 32-bit buses, true LRU
+    /// against the RTL's PLRU.
+    fn default() -> Self {
+        SweepSpace {
+            cache_size_kb: vec![8, 16, 32, 64, 128],
+            block_size_bytes: vec![16, 32, 64],
+            num_ways: vec![1, 2, 4, 8],
+            addr_width: vec![32],
+            data_width: vec![32],
+            policies: vec![PolicyKind::Lru, PolicyKind::Plru],
+            tech: TechParams::default(),
+        }
+    }
+}
+
+impl SweepSpace {
+    /// Every combination that passes `FunctionalCache::check`, in a fixed
+    /// order. A direct-mapped geometry has nothing to replace, so it is run
+    /// once, under the first policy.
+    pub fn points(&self) -> Vec<(CacheParams, PolicyKind)> {
+        let mut points = Vec::new();
+        for &cache_size_kb in &self.cache_size_kb {
+            for &block_size_bytes in &self.block_size_bytes {
+                for &num_ways in &self.num_ways {
+                    for &addr_width in &self.addr_width {
+                        for &data_width in &self.data_width {
+                            let params = CacheParams {
+                                addr_width,
+                                data_width,
+                                cache_size_kb,
+                                block_size_bytes,
+                                num_ways,
+                            };
+                            if FunctionalCache::check(&params).is_err() {
+                                continue;
+                            }
+                            let policies = if num_ways == 1 {
+                                &self.policies[..self.policies.len().min(1)]
+                            } else {
+                                &self.policies[..]
+                            };
+                            points.extend(policies.iter().map(|&policy| (params, policy)));
+                        }
+                    }
+                }
+            }
+        }
+        points
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepResult {
+    pub params: CacheParams,
+    pub policy: PolicyKind,
+    pub stats: Stats,
+    /// Total bits over every storage array.
+    pub storage_bits: u64,
+    pub cost: CostEstimate,
+    /// No other result has both a lower-or-equal miss ratio and
+    /// lower-or-equal storage, with one of them strictly lower.
+    pub pareto: bool,
+}
+
+/// Marks the Pareto-optimal results by miss ratio against storage.
+pub fn mark_pareto(results: &mut [SweepResult]) {
+    let mut order: Vec<usize> = (0..results.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (ra, rb) = (&results[a], &results[b]);
+        ra.storage_bits
+            .cmp(&rb.storage_bits)
+            .then(ra.stats.miss_ratio().total_cmp(&rb.stats.miss_ratio()))
+    });
+    // Walking up in storage, a point is optimal when it beats the best
+    // miss ratio of everything cheaper (ties on both are all kept).
+    let mut best = f64::INFINITY;
+    let mut best_storage = 0;
+    for i in order {
+        let r = &mut results[i];
+        let ratio = r.stats.miss_ratio();
+        r.pareto = ratio < best || (ratio == best && r.storage_bits == best_storage);
+        if ratio < best {
+            best = ratio;
+            best_storage = r.storage_bits;
+        }
+    }
+}
+
+/// Runs every point of `space` over the trace `accesses` yields, on
+/// `threads` workers (all cores if `None`). Results come back in the order
+/// of `space.points()`, with the Pareto front marked.
+pub fn run<F, I>(
+    space: &SweepSpace,
+    accesses: F,
+    threads: Option<usize>,
+) -> Result<Vec<SweepResult>, TraceError>
+where
+    F: Fn() -> I + Sync,
+    I: IntoIterator<Item = Result<(u64, bool), TraceError>>,
+{
+    let points = space.points();
+    let threads = threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, points.len().max(1));
+    let next = AtomicUsize::new(0);
+    let slots: Mutex<Vec<Option<Result<Stats, TraceError>>>> =
+        Mutex::new((0..points.len()).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(&(params, policy)) = points.get(i) else {
+                    break;
+                };
+                let result = simulate(params, policy, accesses());
+                slots.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(result);
+            });
+        }
+    });
+
+    let mut results = Vec::with_capacity(points.len());
+    let slots = slots.into_inner().unwrap_or_else(|e| e.into_inner());
+    for ((params, policy), slot) in points.into_iter().zip(slots) {
+        let stats = slot.expect("every point is simulated")?;
+        let cost = CostEstimate::new(&params, &space.tech);
+        results.push(SweepResult {
+            params,
+            policy,
+            stats,
+            storage_bits: cost.bits.total(),
+            cost,
+            pareto: false,
+        });
+    }
+    mark_pareto(&mut results);
+    Ok(results)
+}
+
+fn simulate<I>(params: CacheParams, policy: PolicyKind, accesses: I) -> Result<Stats, TraceError>
+where
+    I: IntoIterator<Item = Result<(u64, bool), TraceError>>,
+{
+    let mut cache = FunctionalCache::new(params, policy).expect("sweep points are validated");
+    for access in accesses {
+        let (addr, write) = access?;
+        cache.access(addr, write);
+    }
+    Ok(*cache.stats())
+}
+
+const CSV_HEADER: &str = "cache_size_kb,block_size_bytes,num_ways,addr_width,data_width,policy,\
+accesses,misses,miss_ratio,write_backs,storage_bits,area_mm2,leakage_mw,energy_nj,pareto";
+
+/// The results as CSV, one row per point, with a header.
+pub fn to_csv(results: &[SweepResult]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", CSV_HEADER);
+    for r in results {
+        let p = &r.params;
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{:.6},{},{},{:.6},{:.6},{:.3},{}",
+            p.cache_size_kb,
+            p.block_size_bytes,
+            p.num_ways,
+            p.addr_width,
+            p.data_width,
+            r.policy,
+            r.stats.accesses(),
+            r.stats.misses(),
+            r.stats.miss_ratio(),
+            r.stats.write_backs,
+            r.storage_bits,
+            r.cost.area_mm2,
+            r.cost.leakage_mw,
+            r.cost.dynamic_energy_nj(&r.stats),
+            r.pareto
+        );
+    }
+    out
+}
+
+/// The results as a JSON array of objects with the CSV's columns.
+pub fn to_json(results: &[SweepResult]) -> String {
+    let mut out = String::from("[\n");
+    for (i, r) in results.iter().enumerate() {
+        let p = &r.params;
+        let _ = write!(
+            out,
+            "  {{\"cache_size_kb\": {}, \"block_size_bytes\": {}, \"num_ways\": {}, \
+             \"addr_width\": {}, \"data_width\": {}, \"policy\": \"{}\", \"accesses\": {}, \
+             \"misses\": {}, \"miss_ratio\": {:.6}, \"write_backs\": {}, \
+             \"storage_bits\": {}, \"area_mm2\": {:.6}, \"leakage_mw\": {:.6}, \
+             \"energy_nj\": {:.3}, \"pareto\": {}}}",
+            p.cache_size_kb,
+            p.block_size_bytes,
+            p.num_ways,
+            p.addr_width,
+            p.data_width,
+            r.policy,
+            r.stats.accesses(),
+            r.stats.misses(),
+            r.stats.miss_ratio(),
+            r.stats.write_backs,
+            r.storage_bits,
+            r.cost.area_mm2,
+            r.cost.leakage_mw,
+            r.cost.dynamic_energy_nj(&r.stats),
+            r.pareto
+        );
+        out.push_str(if i + 1 < results.len() { ",\n" } else { "\n" });
+    }
+    out.push(']');
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space(num_ways: Vec<u32>) -> SweepSpace {
+        SweepSpace {
+            cache_size_kb: vec![1],
+            block_size_bytes: vec![32],
+            num_ways,
+            addr_width: vec![32],
+            data_width: vec![32],
+            policies: vec![PolicyKind::Lru, PolicyKind::Plru],
+            tech: TechParams::default(),
+        }
+    }
+
+    /// A result for the default 64KB direct-mapped geometry with `misses`
+    /// of ten reads, claiming `storage_bits`.
+    fn result(storage_bits: u64, misses: u64) -> SweepResult {
+        let params = CacheParams::default();
+        SweepResult {
+            params,
+            policy: PolicyKind::Lru,
+            stats: Stats {
+                reads: 10,
+                read_misses: misses,
+                ..Stats::default()
+            },
+            storage_bits,
+            cost: CostEstimate::new(&params, &TechParams::default()),
+            pareto: false,
+        }
+    }
+
+    #[test]
+    fn points_skip_what_a_cache_rejects() {
+        // 64 ways is more than the PLRU tree holds, and 3 is not a power
+        // of two; direct mapped runs under the first policy only.
+        let points = space(vec![1, 2, 3, 64]).points();
+        let shapes: Vec<(u32, PolicyKind)> = points
+            .iter()
+            .map(|(p, policy)| (p.num_ways, *policy))
+            .collect();
+        assert_eq!(
+            shapes,
+            [
+                (1, PolicyKind::Lru),
+                (2, PolicyKind::Lru),
+                (2, PolicyKind::Plru)
+            ]
+        );
+    }
+
+    #[test]
+    fn run_keeps_point_order() {
+        // Blocks 0 and 32 share set 0 of the direct-mapped cache but fit
+        // the two ways of a set of the 2-way one.
+        let trace = [0x000, 0x400, 0x000, 0x400, 0x800];
+        let results = run(
+            &space(vec![1, 2, 64]),
+            || trace.iter().map(|&addr| Ok((addr, false))),
+            Some(2),
+        )
+        .unwrap();
+        let misses: Vec<(u32, u64)> = results
+            .iter()
+            .map(|r| (r.params.num_ways, r.stats.misses()))
+            .collect();
+        assert_eq!(misses, [(1, 5), (2, 3), (2, 3)]);
+        assert!(results[0].storage_bits < results[1].storage_bits);
+        assert!(results.iter().all(|r| r.pareto));
+
+        let failing = run(
+            &space(vec![1]),
+            || {
+                [Err(TraceError::Parse {
+                    line: 1,
+                    message: "bad".into(),
+                })]
+            },
+            None,
+        );
+        assert!(failing.is_err());
+    }
+
+    #[test]
+    fn pareto_front() {
+        let mut results = vec![
+            result(100, 5),
+            result(200, 3),
+            result(200, 4),
+            result(300, 3),
+            result(100, 5),
+            result(400, 1),
+        ];
+        mark_pareto(&mut results);
+        let front: Vec<bool> = results.iter().map(|r| r.pareto).collect();
+        // Equal points are both kept; a larger point only as good is not.
+        assert_eq!(front, [true, true, false, false, true, true]);
+    }
+
+    #[test]
+    fn csv_and_json() {
+        let mut results = vec![result(561_152, 5), result(600_000, 6)];
+        mark_pareto(&mut results);
+        let csv = to_csv(&results);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+        assert!(
+            lines[1].starts_with("64,32,1,32,32,lru,10,5,0.500000,0,561152,"),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[1].ends_with(",true"));
+        assert!(lines[2].ends_with(",false"));
+
+        let json = to_json(&results);
+        assert!(json.starts_with(
+            "[\n  {\"cache_size_kb\": 64, \"block_size_bytes\": 32, \"num_ways\": 1, "
+        ));
+        assert!(json.contains("\"policy\": \"lru\", \"accesses\": 10, \"misses\": 5, "));
+        assert!(json.contains("\"pareto\": true},\n  {"));
+        assert!(json.ends_with("\"pareto\": false}\n]\n"));
+        assert_eq!(to_json(&[]), "[\n]\n");
+    }
+}
@@ -0,0 +1,333 @@
+//! A CPU driver that wires a cycle model to a memory, as usage.rs describes.
+//!
+//! The CPU presents a request while the cache sits in S_IDLE and holds it,
+//! unchanged, for as long as `cpu_wait` is high. The access completes in the
+//! first later cycle with `cpu_wait` low, which is the S_COMPARE_TAG hit that
+//! also returns `cpu_rdata`.
+//!
+//! Every cycle the system steps is counted in `TimingStats`: by FSM state,
+//! by whether it stalled the CPU on `cpu_wait` or the cache on `mem_wait`,
+//! and, for each completed access, towards the average memory access time.
+
+use std::fmt::{self, Write};
+
+use super::fsm::{CacheOutputs, CpuRequest, CycleModel, State};
+use super::memory::Memory;
+
+/// The signals of one clock cycle, as seen on the cache's ports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleRecord {
+    pub cycle: u64,
+    pub state: State,
+    pub cpu: CpuRequest,
+    pub out: CacheOutputs,
+    pub mem_wait: bool,
+}
+
+/// The result of one CPU access.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessResult {
+    /// `cpu_rdata` in the completing cycle; `None` for writes.
+    pub rdata: Option<u64>,
+    pub hit: bool,
+    /// Whether the miss wrote a dirty victim back first.
+    pub write_back: bool,
+    /// Cycles from the request being presented to its completion, inclusive.
+    pub cycles: u64,
+}
+
+/// Cycle counts accumulated by a `System`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimingStats {
+    /// Indexed by `State::encoding`.
+    pub state_cycles: [u64; State::ALL.len()],
+    /// Cycles with a request held and `cpu_wait` high.
+    pub stall_cycles: u64,
+    /// Cycles with `mem_wait` high.
+    pub mem_wait_cycles: u64,
+    pub accesses: u64,
+    pub hits: u64,
+    /// Cycles of every completed access, from `AccessResult::cycles`.
+    pub access_cycles: u64,
+    pub hit_cycles: u64,
+}
+
+impl TimingStats {
+    pub fn cycles_in(&self, state: State) -> u64 {
+        self.state_cycles[state.encoding() as usize]
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.state_cycles.iter().sum()
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.accesses - self.hits
+    }
+
+    /// Average memory access time in cycles, as measured.
+    pub fn amat(&self) -> f64 {
+        ratio(self.access_cycles, self.accesses)
+    }
+
+    /// Average cycles of a hit.
+    pub fn hit_time(&self) -> f64 {
+        ratio(self.hit_cycles, self.hits)
+    }
+
+    /// Average extra cycles a miss takes over a hit; AMAT is the hit time
+    /// plus the miss ratio times this.
+    pub fn miss_penalty(&self) -> f64 {
+        if self.misses() == 0 {
+            return 0.0;
+        }
+        ratio(self.access_cycles - self.hit_cycles, self.misses()) - self.hit_time()
+    }
+
+    pub fn miss_ratio(&self) -> f64 {
+        ratio(self.misses(), self.accesses)
+    }
+
+    /// The counts accumulated since `earlier`, a snapshot of the same
+    /// system.
+    pub fn since(&self, earlier: &TimingStats) -> TimingStats {
+        let mut state_cycles = self.state_cycles;
+        for (now, then) in state_cycles.iter_mut().zip(earlier.state_cycles) {
+            *now -= then;
+        }
+        TimingStats {
+            state_cycles,
+            stall_cycles: self.stall_cycles - earlier.stall_cycles,
+            mem_wait_cycles: self.mem_wait_cycles - earlier.mem_wait_cycles,
+            accesses: self.accesses - earlier.accesses,
+            hits: self.hits - earlier.hits,
+            access_cycles: self.access_cycles - earlier.access_cycles,
+            hit_cycles: self.hit_cycles - earlier.hit_cycles,
+        }
+    }
+
+    /// A plain-text report: AMAT and its breakdown, stalls, and the cycles
+    /// in each state.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} accesses, AMAT {:.3} cycles (hit time {:.3} + miss ratio {:.4} x miss penalty {:.3})",
+            self.accesses,
+            self.amat(),
+            self.hit_time(),
+            self.miss_ratio(),
+            self.miss_penalty()
+        );
+        let _ = writeln!(
+            out,
+            "{} cycles, {} stalled on cpu_wait, {} on mem_wait",
+            self.total_cycles(),
+            self.stall_cycles,
+            self.mem_wait_cycles
+        );
+        for state in State::ALL {
+            let cycles = self.cycles_in(state);
+            let _ = writeln!(
+                out,
+                "{:<17} {:>12} {:>7.2}%",
+                state.name(),
+                cycles,
+                100.0 * ratio(cycles, self.total_cycles())
+            );
+        }
+        out
+    }
+}
+
+impl fmt::Display for TimingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.report())
+    }
+}
+
+fn ratio(num: u64, den: u64) -> f64 {
+    if den == 0 {
+        0.0
+    } else {
+        num as f64 / den as f64
+    }
+}
+
+pub struct System<C, M> {
+    pub cache: C,
+    pub memory: M,
+    cycle: u64,
+    trace: Option<Vec<CycleRecord>>,
+    timing: TimingStats,
+}
+
+impl<C: CycleModel, M: Memory> System<C, M> {
+    pub fn new(cache: C, memory: M) -> Self {
+        System {
+            cache,
+            memory,
+            cycle: 0,
+            trace: None,
+            timing: TimingStats::default(),
+        }
+    }
+
+    /// Keeps a per-cycle record of every port from now on.
+    pub fn record_cycles(&mut self) {
+        self.trace.get_or_insert_with(Vec::new);
+    }
+
+    pub fn take_cycle_records(&mut self) -> Vec<CycleRecord> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle
+    }
+
+    pub fn timing(&self) -> &TimingStats {
+        &self.timing
+    }
+
+    pub fn reset_timing(&mut self) {
+        self.timing = TimingStats::default();
+    }
+
+    /// Advances one clock with `cpu` on the CPU inputs.
+    pub fn step(&mut self, cpu: &CpuRequest) -> CacheOutputs {
+        let state = self.cache.state();
+        let out = self.cache.eval(cpu);
+        let resp = self.memory.cycle(&out);
+        self.cache.clock(cpu, resp.mem_wait, &resp.mem_rdata);
+        self.timing.state_cycles[state.encoding() as usize] += 1;
+        self.timing.stall_cycles += (cpu.is_active() && out.cpu_wait) as u64;
+        self.timing.mem_wait_cycles += resp.mem_wait as u64;
+        if let Some(trace) = &mut self.trace {
+            trace.push(CycleRecord {
+                cycle: self.cycle,
+                state,
+                cpu: *cpu,
+                out: out.clone(),
+                mem_wait: resp.mem_wait,
+            });
+        }
+        self.cycle += 1;
+        out
+    }
+
+    /// Runs one access to completion, holding the request while `cpu_wait`
+    /// is high.
+    pub fn access(&mut self, cpu: CpuRequest) -> AccessResult {
+        assert!(cpu.is_active(), "an access needs cpu_read or cpu_write");
+        while self.cache.state() != State::Idle {
+            self.step(&CpuRequest::idle());
+        }
+
+        let start = self.cycle;
+        let mut hit = true;
+        let mut write_back = false;
+        self.step(&cpu);
+        loop {
+            match self.cache.state() {
+                State::Allocate => hit = false,
+                State::WriteBack => write_back = true,
+                _ => {}
+            }
+            let out = self.step(&cpu);
+            if !out.cpu_wait {
+                let cycles = self.cycle - start;
+                self.timing.accesses += 1;
+                self.timing.access_cycles += cycles;
+                if hit {
+                    self.timing.hits += 1;
+                    self.timing.hit_cycles += cycles;
+                }
+                return AccessResult {
+                    rdata: out.cpu_rdata,
+                    hit,
+                    write_back,
+                    cycles,
+                };
+            }
+        }
+    }
+
+    pub fn read(&mut self, addr: u64) -> AccessResult {
+        self.access(CpuRequest::read(addr))
+    }
+
+    pub fn write(&mut self, addr: u64, data: u64) -> AccessResult {
+        self.access(CpuRequest::write(addr, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::direct_mapped::DirectMappedCache;
+    use super::super::memory::MainMemory;
+    use super::super::params::CacheParams;
+    use super::*;
+
+    /// A write miss, a read hit and a read miss that writes the first line
+    /// back, with memory waiting two cycles an operation.
+    fn run() -> (System<DirectMappedCache, MainMemory>, TimingStats) {
+        let mut system = System::new(
+            DirectMappedCache::new(CacheParams::default()).unwrap(),
+            MainMemory::new(32, 2),
+        );
+        assert_eq!(system.write(0x40, 7).cycles, 7);
+        let first = *system.timing();
+        assert_eq!(system.read(0x44).cycles, 2);
+        assert_eq!(system.read(0x1_0040).cycles, 10);
+        (system, first)
+    }
+
+    #[test]
+    fn counts_cycles_by_state() {
+        let (system, _) = run();
+        let timing = system.timing();
+        let by_state: Vec<u64> = State::ALL.iter().map(|&s| timing.cycles_in(s)).collect();
+        assert_eq!(by_state, [3, 5, 2, 3, 6]);
+        assert_eq!(timing.total_cycles(), 19);
+        assert_eq!(timing.total_cycles(), system.cycle_count());
+        // Each fill and write-back holds mem_wait for two cycles.
+        assert_eq!(timing.mem_wait_cycles, 6);
+        // Every cycle but S_IDLE and the completing one of each access.
+        assert_eq!(timing.stall_cycles, 13);
+    }
+
+    #[test]
+    fn amat_breakdown() {
+        let (system, _) = run();
+        let timing = system.timing();
+        assert_eq!((timing.accesses, timing.hits, timing.misses()), (3, 1, 2));
+        assert_eq!(timing.amat(), 19.0 / 3.0);
+        assert_eq!(timing.hit_time(), 2.0);
+        assert_eq!(timing.miss_ratio(), 2.0 / 3.0);
+        // Misses average 8.5 cycles, 6.5 more than a hit.
+        assert_eq!(timing.miss_penalty(), 6.5);
+        let rebuilt = timing.hit_time() + timing.miss_ratio() * timing.miss_penalty();
+        assert!((rebuilt - timing.amat()).abs() < 1e-12);
+        assert_eq!(TimingStats::default().amat(), 0.0);
+        assert_eq!(TimingStats::default().miss_penalty(), 0.0);
+    }
+
+    #[test]
+    fn since_and_report() {
+        let (mut system, first) = run();
+        let later = system.timing().since(&first);
+        assert_eq!((later.accesses, later.hits), (2, 1));
+        assert_eq!(later.access_cycles, 12);
+        assert_eq!(later.cycles_in(State::Allocate), 1);
+
+        let report = system.timing().report();
+        assert!(report.starts_with(
+            "3 accesses, AMAT 6.333 cycles (hit time 2.000 + miss ratio 0.6667 x miss penalty 6.500)\n\
+             19 cycles, 13 stalled on cpu_wait, 6 on mem_wait\n"
+        ));
+        assert!(report.contains("S_READ_FROM_MEM              6   31.58%\n"));
+        system.reset_timing();
+        assert_eq!(*system.timing(), TimingStats::default());
+    }
+}
@@ -0,0 +1,473 @@
+//! Self-checking SystemVerilog testbenches from Rust scenarios.
+//!
+//! A `Scenario` is a list of requests, idle cycles included, and the memory
+//! `Timing` to run them against. `Testbench::generate` runs it through the
+//! golden model, recording each access's `cpu_rdata` and cycle count and the
+//! `mem_wait` cycles of every memory operation, and writes a testbench that
+//! drives `direct_mapped_cache` or `set_associative_cache` the same way and
+//! checks both. Its memory replays the recorded waits operation by
+//! operation rather than reimplementing `Timing`, so the RTL sees the same
+//! `mem_wait` pattern cycle for cycle.
+//!
+//! The testbench keeps to what Icarus Verilog 12 accepts with `-g2012`:
+//! no classes, queues or associative arrays. The blocks the memory may be
+//! asked for are listed up front, all zero as `MainMemory` starts.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::cosim::RtlModule;
+use super::fsm::CpuRequest;
+use super::memory::{MainMemory, Timing};
+use super::params::CacheParams;
+use super::shrink::{is_ident, ScriptedMemory};
+use super::svheader::Model;
+use super::system::System;
+
+/// What a testbench is generated from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scenario {
+    /// Names the testbench module, `tb_<name>`, and its file.
+    pub name: String,
+    pub params: CacheParams,
+    /// Accesses run to completion; idle requests take one cycle.
+    pub requests: Vec<CpuRequest>,
+    pub timing: Timing,
+}
+
+/// What the golden model did with one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expected {
+    pub cpu: CpuRequest,
+    /// `cpu_rdata` when the access completed; `None` for writes, idle
+    /// cycles and undriven data, which are not checked.
+    pub rdata: Option<u64>,
+    pub hit: bool,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Testbench {
+    pub scenario: Scenario,
+    pub expected: Vec<Expected>,
+    /// `mem_wait` cycles of each memory operation, in order.
+    pub waits: Vec<u32>,
+    /// Block addresses the cache reads or writes, sorted.
+    pub blocks: Vec<u64>,
+}
+
+impl Testbench {
+    /// Runs `scenario` through the golden model from reset. The name must
+    /// be an identifier, since it names both the module and the file.
+    pub fn generate(scenario: Scenario) -> Result<Self, String> {
+        if !is_ident(&scenario.name) {
+            return Err(format!(
+                "scenario name {:?} is not an identifier",
+                scenario.name
+            ));
+        }
+        let params = scenario.params;
+        let block_bytes = params.block_size_bytes as usize;
+        let memory = ScriptedMemory::new(
+            MainMemory::with_timing(block_bytes, scenario.timing),
+            block_bytes,
+        );
+        let mut system = System::new(
+            Model::for_params(params).map_err(|e| e.to_string())?,
+            memory,
+        );
+        system.record_cycles();
+        let mut expected = Vec::with_capacity(scenario.requests.len());
+        for &cpu in &scenario.requests {
+            system.memory.begin(&[]);
+            if cpu.is_active() {
+                let result = system.access(cpu);
+                expected.push(Expected {
+                    cpu,
+                    rdata: result.rdata.filter(|_| cpu.cpu_read),
+                    hit: result.hit,
+                    cycles: result.cycles,
+                });
+            } else {
+                system.step(&cpu);
+                expected.push(Expected {
+                    cpu,
+                    rdata: None,
+                    hit: false,
+                    cycles: 1,
+                });
+            }
+        }
+        let waits = system.memory.observed().concat();
+        let mut blocks: Vec<u64> = system
+            .take_cycle_records()
+            .iter()
+            .filter(|r| r.out.mem_read || r.out.mem_write)
+            .filter_map(|r| r.out.mem_addr)
+            .collect();
+        blocks.sort_unstable();
+        blocks.dedup();
+        Ok(Testbench {
+            scenario,
+            expected,
+            waits,
+            blocks,
+        })
+    }
+
+    pub fn module_name(&self) -> String {
+        format!("tb_{}", self.scenario.name)
+    }
+
+    /// The testbench source.
+    pub fn to_sv(&self) -> String {
+        let p = &self.scenario.params;
+        let rtl = RtlModule::for_params(p);
+        let tb = self.module_name();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "// Generated by tbgen from scenario {}: {} requests, {} memory operations.\n\
+             // Expected values come from the Rust golden model.\n\
+             //\n\
+             //   iverilog -g2012 -o {tb} {tb}.sv {}\n\
+             //   vvp {tb}\n",
+            self.scenario.name,
+            self.expected.len(),
+            self.waits.len(),
+            rtl.source(),
+            tb = tb
+        );
+        let _ = writeln!(out, "`timescale 1ns/1ps\n\nmodule {};", tb);
+        let _ = writeln!(
+            out,
+            "    localparam ADDR_WIDTH       = {};\n    \
+             localparam DATA_WIDTH       = {};\n    \
+             localparam CACHE_SIZE_KB    = {};\n    \
+             localparam BLOCK_SIZE_BYTES = {};",
+            p.addr_width, p.data_width, p.cache_size_kb, p.block_size_bytes
+        );
+        if rtl == RtlModule::SetAssociative {
+            let _ = writeln!(out, "    localparam NUM_WAYS         = {};", p.num_ways);
+        }
+        let _ = writeln!(
+            out,
+            "    localparam BLOCK_BITS       = BLOCK_SIZE_BYTES * 8;\n    \
+             localparam NUM_BLOCKS       = {};\n    \
+             localparam NUM_OPS          = {};\n",
+            self.blocks.len().max(1),
+            self.waits.len().max(1)
+        );
+        out.push_str(
+            "    logic clk = 1'b0;\n    \
+             logic rst_n = 1'b0;\n    \
+             logic [ADDR_WIDTH-1:0] cpu_addr = '0;\n    \
+             logic                  cpu_read = 1'b0;\n    \
+             logic                  cpu_write = 1'b0;\n    \
+             logic [DATA_WIDTH-1:0] cpu_wdata = '0;\n    \
+             logic [DATA_WIDTH-1:0] cpu_rdata;\n    \
+             logic                  cpu_wait;\n    \
+             logic [ADDR_WIDTH-1:0] mem_addr;\n    \
+             logic                  mem_read;\n    \
+             logic                  mem_write;\n    \
+             logic [BLOCK_BITS-1:0] mem_rdata;\n    \
+             logic [BLOCK_BITS-1:0] mem_wdata;\n    \
+             logic                  mem_wait;\n\n",
+        );
+        let _ = writeln!(out, "    {} #(", rtl.name());
+        out.push_str(
+            "        .ADDR_WIDTH(ADDR_WIDTH),\n        \
+             .DATA_WIDTH(DATA_WIDTH),\n        \
+             .CACHE_SIZE_KB(CACHE_SIZE_KB),\n        \
+             .BLOCK_SIZE_BYTES(BLOCK_SIZE_BYTES)",
+        );
+        if rtl == RtlModule::SetAssociative {
+            out.push_str(",\n        .NUM_WAYS(NUM_WAYS)");
+        }
+        out.push_str(
+            "\n    ) dut (\n        \
+             .clk(clk), .rst_n(rst_n),\n        \
+             .cpu_addr(cpu_addr), .cpu_read(cpu_read), .cpu_write(cpu_write),\n        \
+             .cpu_wdata(cpu_wdata), .cpu_rdata(cpu_rdata), .cpu_wait(cpu_wait),\n        \
+             .mem_addr(mem_addr), .mem_read(mem_read), .mem_write(mem_write),\n        \
+             .mem_rdata(mem_rdata), .mem_wdata(mem_wdata), .mem_wait(mem_wait)\n    \
+             );\n\n    \
+             always #5 clk = ~clk;\n\n",
+        );
+
+        out.push_str(
+            "    // --- Memory: the blocks the golden model touched, and the mem_wait\n    \
+             // cycles of each operation in the order it made them ---\n    \
+             logic [ADDR_WIDTH-1:0] blk_addr [0:NUM_BLOCKS-1];\n    \
+             logic [BLOCK_BITS-1:0] blk_data [0:NUM_BLOCKS-1];\n    \
+             integer op_wait [0:NUM_OPS-1];\n    \
+             integer op = 0;          // operations completed\n    \
+             logic   busy = 1'b0;     // an operation has started waiting\n    \
+             integer left = 0;        // its wait cycles still to come\n    \
+             integer errors = 0;\n\n    \
+             initial begin\n",
+        );
+        for (i, addr) in self.blocks.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "        blk_addr[{}] = {}'h{:x}; blk_data[{}] = '0;",
+                i, p.addr_width, addr, i
+            );
+        }
+        if self.blocks.is_empty() {
+            out.push_str("        blk_addr[0] = '0; blk_data[0] = '0;\n");
+        }
+        for (i, wait) in self.waits.iter().enumerate() {
+            let _ = writeln!(out, "        op_wait[{}] = {};", i, wait);
+        }
+        if self.waits.is_empty() {
+            out.push_str("        op_wait[0] = 0;\n");
+        }
+        out.push_str(
+            "    end\n\n    \
+             function integer find_block(input logic [ADDR_WIDTH-1:0] addr);\n        \
+             integer i;\n        \
+             begin\n            \
+             find_block = -1;\n            \
+             for (i = 0; i < NUM_BLOCKS; i = i + 1)\n                \
+             if (blk_addr[i] == addr) find_block = i;\n        \
+             end\n    \
+             endfunction\n\n    \
+             // Wait cycles left this cycle, counting this one, and the block\n    \
+             // addressed. Kept out of functions so @* sees what they read.\n    \
+             integer cur_wait;\n    \
+             integer blk;\n    \
+             always @* begin\n        \
+             if (busy) cur_wait = left;\n        \
+             else if (op < NUM_OPS) cur_wait = op_wait[op];\n        \
+             else cur_wait = 0;\n        \
+             blk = find_block(mem_addr);\n        \
+             mem_wait = (mem_read || mem_write) && cur_wait != 0;\n        \
+             mem_rdata = (mem_read && blk >= 0) ? blk_data[blk] : '0;\n    \
+             end\n\n    \
+             always @(posedge clk) begin\n        \
+             if (mem_read || mem_write) begin\n            \
+             if (cur_wait != 0) begin\n                \
+             busy <= 1'b1;\n                \
+             left <= cur_wait - 1;\n            \
+             end else begin\n                \
+             busy <= 1'b0;\n                \
+             op <= op + 1;\n                \
+             if (mem_write) begin\n                    \
+             if (blk < 0) begin\n                        \
+             $display(\"ERROR: write to unexpected block %h\", mem_addr);\n                        \
+             errors = errors + 1;\n                    \
+             end else blk_data[blk] <= mem_wdata;\n                \
+             end\n            \
+             end\n        \
+             end else begin\n            \
+             busy <= 1'b0;\n        \
+             end\n    \
+             end\n\n",
+        );
+
+        out.push_str(
+            "    // --- CPU: inputs change at the falling edge and outputs are\n    \
+             // sampled just after it, so each rising edge sees one request ---\n    \
+             task automatic access(input integer n, input logic rd, input logic wr,\n                          \
+             input logic [ADDR_WIDTH-1:0] addr, input logic [DATA_WIDTH-1:0] wdata,\n                          \
+             input logic check, input logic [DATA_WIDTH-1:0] rdata,\n                          \
+             input integer cycles);\n        \
+             integer taken;\n        \
+             begin\n            \
+             @(negedge clk);\n            \
+             cpu_addr = addr; cpu_read = rd; cpu_write = wr; cpu_wdata = wdata;\n            \
+             taken = 1;\n            \
+             if (rd || wr) begin\n                \
+             @(negedge clk); #1;\n                \
+             taken = 2;\n                \
+             while (cpu_wait) begin\n                    \
+             @(negedge clk); #1;\n                    \
+             taken = taken + 1;\n                \
+             end\n                \
+             if (check && cpu_rdata !== rdata) begin\n                    \
+             $display(\"ERROR: request %0d: cpu_rdata %h, expected %h\", n, cpu_rdata, rdata);\n                    \
+             errors = errors + 1;\n                \
+             end\n                \
+             if (taken != cycles) begin\n                    \
+             $display(\"ERROR: request %0d took %0d cycles, expected %0d\", n, taken, cycles);\n                    \
+             errors = errors + 1;\n                \
+             end\n            \
+             end\n        \
+             end\n    \
+             endtask\n\n    \
+             initial begin\n        \
+             repeat (2) @(negedge clk);\n        \
+             rst_n = 1'b1;\n",
+        );
+        let hex = |v: u64, bits: u32| format!("{}'h{:x}", bits, v);
+        for (n, e) in self.expected.iter().enumerate() {
+            let cpu = &e.cpu;
+            let _ = writeln!(
+                out,
+                "        access({}, 1'b{}, 1'b{}, {}, {}, 1'b{}, {}, {});{}",
+                n,
+                cpu.cpu_read as u8,
+                cpu.cpu_write as u8,
+                hex(cpu.cpu_addr, p.addr_width),
+                hex(cpu.cpu_wdata, p.data_width),
+                e.rdata.is_some() as u8,
+                hex(e.rdata.unwrap_or(0), p.data_width),
+                e.cycles,
+                if !cpu.is_active() {
+                    " // idle"
+                } else if e.hit {
+                    " // hit"
+                } else {
+                    " // miss"
+                }
+            );
+        }
+        let _ = writeln!(
+            out,
+            "        @(negedge clk);\n        \
+             cpu_read = 1'b0; cpu_write = 1'b0;\n        \
+             @(negedge clk);\n        \
+             if (op != {ops}) begin\n            \
+             $display(\"ERROR: %0d memory operations, expected {ops}\", op);\n            \
+             errors = errors + 1;\n        \
+             end\n        \
+             if (errors == 0) $display(\"PASS {tb}: {n} requests\");\n        \
+             else $display(\"FAIL {tb}: %0d errors\", errors);\n        \
+             $finish;\n    \
+             end\n\n    \
+             initial begin\n        \
+             #{timeout};\n        \
+             $display(\"FAIL {tb}: timed out\");\n        \
+             $finish;\n    \
+             end\n\
+             endmodule",
+            ops = self.waits.len(),
+            tb = tb,
+            n = self.expected.len(),
+            timeout = 10 * (self.expected.iter().map(|e| e.cycles).sum::<u64>() + 10) * 2
+        );
+        out
+    }
+
+    /// Writes the testbench to `dir/tb_<name>.sv` and returns the path.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> io::Result<PathBuf> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join(format!("{}.sv", self.module_name()));
+        fs::write(&path, self.to_sv())?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clean miss, a hit, an idle cycle, a miss that writes back 0x40's
+    /// dirty line, and a miss that reads it back.
+    fn scenario() -> Scenario {
+        Scenario {
+            name: "evict".to_string(),
+            params: CacheParams::default(),
+            requests: vec![
+                CpuRequest::write(0x40, 0x1234),
+                CpuRequest::read(0x40),
+                CpuRequest::idle(),
+                CpuRequest::read(0x1_0040),
+                CpuRequest::read(0x40),
+            ],
+            timing: Timing::ReadWrite { read: 2, write: 3 },
+        }
+    }
+
+    #[test]
+    fn records_results_and_waits() {
+        let tb = Testbench::generate(scenario()).unwrap();
+        let results: Vec<_> = tb
+            .expected
+            .iter()
+            .map(|e| (e.rdata, e.hit, e.cycles))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                // A clean miss takes 5 + 2 cycles.
+                (None, false, 7),
+                (Some(0x1234), true, 2),
+                (None, false, 1),
+                // Write-back then fill: 4 + (3 + 1) + (2 + 1).
+                (Some(0), false, 11),
+                (Some(0x1234), false, 7),
+            ]
+        );
+        assert_eq!(tb.waits, [2, 3, 2, 2]);
+        assert_eq!(tb.blocks, [0x40, 0x1_0040]);
+        assert_eq!(tb.module_name(), "tb_evict");
+    }
+
+    #[test]
+    fn testbench_source() {
+        let sv = Testbench::generate(scenario()).unwrap().to_sv();
+        assert!(sv.starts_with(
+            "// Generated by tbgen from scenario evict: 5 requests, 4 memory operations.\n\
+             // Expected values come from the Rust golden model.\n\
+             //\n\
+             //   iverilog -g2012 -o tb_evict tb_evict.sv direct_mapped_64Kb.sv\n\
+             //   vvp tb_evict\n\
+             \n\
+             `timescale 1ns/1ps\n\
+             \n\
+             module tb_evict;\n"
+        ));
+        assert!(sv.contains(
+            "        blk_addr[0] = 32'h40; blk_data[0] = '0;\n        \
+             blk_addr[1] = 32'h10040; blk_data[1] = '0;\n        \
+             op_wait[0] = 2;\n        \
+             op_wait[1] = 3;\n        \
+             op_wait[2] = 2;\n        \
+             op_wait[3] = 2;\n    \
+             end\n"
+        ));
+        assert!(sv.contains(
+            "        rst_n = 1'b1;\n        \
+             access(0, 1'b0, 1'b1, 32'h40, 32'h1234, 1'b0, 32'h0, 7); // miss\n        \
+             access(1, 1'b1, 1'b0, 32'h40, 32'h0, 1'b1, 32'h1234, 2); // hit\n        \
+             access(2, 1'b0, 1'b0, 32'h0, 32'h0, 1'b0, 32'h0, 1); // idle\n        \
+             access(3, 1'b1, 1'b0, 32'h10040, 32'h0, 1'b1, 32'h0, 11); // miss\n        \
+             access(4, 1'b1, 1'b0, 32'h40, 32'h0, 1'b1, 32'h1234, 7); // miss\n"
+        ));
+        assert!(sv.contains("        if (op != 4) begin\n"));
+        assert!(!sv.contains("NUM_WAYS"));
+        assert!(sv.ends_with("endmodule\n"));
+    }
+
+    #[test]
+    fn set_associative_scenarios_pass_the_ways() {
+        let mut scenario = scenario();
+        scenario.params.num_ways = 4;
+        let sv = Testbench::generate(scenario).unwrap().to_sv();
+        assert!(sv.contains("    localparam NUM_WAYS         = 4;\n"));
+        assert!(sv.contains("    set_associative_cache #(\n"));
+        assert!(sv.contains(",\n        .NUM_WAYS(NUM_WAYS)\n    ) dut (\n"));
+    }
+
+    #[test]
+    fn rejects_bad_names_and_params() {
+        for name in ["", "1st", "a b", "../x", "tb-1"] {
+            let mut scenario = scenario();
+            scenario.name = name.to_string();
+            assert_eq!(
+                Testbench::generate(scenario).unwrap_err(),
+                format!("scenario name {:?} is not an identifier", name)
+            );
+        }
+        let mut scenario = scenario();
+        scenario.params.num_ways = 3;
+        assert_eq!(
+            Testbench::generate(scenario).unwrap_err(),
+            "NUM_WAYS = 3 is not a power of two"
+        );
+    }
+}
@@ -0,0 +1,420 @@
+//! A compact binary trace format for long runs.
+//!
+//! The stream opens with an 8-byte header:
+//!
+//! | offset | field                                   |
+//! |--------|-----------------------------------------|
+//! | 0      | magic, `b"CTRC"`                        |
+//! | 4      | version, u8 (currently 1)               |
+//! | 5      | `ADDR_WIDTH`, u8                        |
+//! | 6      | `DATA_WIDTH`, u16 little-endian         |
+//!
+//! Each record that follows is a flags byte, an optional size and an address
+//! delta:
+//!
+//! - flags bits 1:0 are the kind (0 read, 1 write, 2 instruction fetch);
+//!   bits 5:2 are log2 of the size, or 15 when a LEB128 size follows;
+//!   bits 7:6 are zero.
+//! - the address is the previous record's address (0 before the first) plus
+//!   a zigzag LEB128 delta, wrapping at 64 bits.
+//!
+//! A sequential word-sized stream costs two bytes an access. There is no
+//! record count, so a trace can be written as it is generated and read
+//! until the stream ends. Addresses must fit in `ADDR_WIDTH`; the writer
+//! rejects any that do not rather than truncate them.
+
+use std::io::{self, BufRead, Write};
+
+use super::super::params::{mask, CacheParams};
+use super::{Access, AccessKind, TraceError, TraceFormat, TraceReader};
+
+pub const MAGIC: [u8; 4] = *b"CTRC";
+pub const VERSION: u8 = 1;
+pub const HEADER_BYTES: usize = 8;
+/// The size code meaning "an explicit size follows".
+const EXPLICIT_SIZE: u8 = 15;
+/// A u64 takes at most this many LEB128 bytes.
+const MAX_VARINT_BYTES: usize = 10;
+
+/// The RTL parameters a trace was recorded for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinaryHeader {
+    pub addr_width: u32,
+    pub data_width: u32,
+}
+
+impl BinaryHeader {
+    pub fn from_params(params: &CacheParams) -> Self {
+        BinaryHeader {
+            addr_width: params.addr_width,
+            data_width: params.data_width,
+        }
+    }
+
+    /// Whether the trace was recorded for buses as wide as `params`'.
+    pub fn matches(&self, params: &CacheParams) -> bool {
+        self.addr_width == params.addr_width && self.data_width == params.data_width
+    }
+
+    fn encode(&self) -> [u8; HEADER_BYTES] {
+        let mut bytes = [0u8; HEADER_BYTES];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5] = self.addr_width as u8;
+        bytes[6..].copy_from_slice(&(self.data_width as u16).to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8; HEADER_BYTES]) -> Result<Self, String> {
+        if bytes[..4] != MAGIC {
+            return Err("not a binary trace".into());
+        }
+        if bytes[4] != VERSION {
+            return Err(format!("unsupported version {}", bytes[4]));
+        }
+        let header = BinaryHeader {
+            addr_width: bytes[5] as u32,
+            data_width: u16::from_le_bytes([bytes[6], bytes[7]]) as u32,
+        };
+        if !(1..=64).contains(&header.addr_width) {
+            return Err(format!("bad ADDR_WIDTH {}", header.addr_width));
+        }
+        if header.data_width == 0 {
+            return Err("bad DATA_WIDTH 0".into());
+        }
+        Ok(header)
+    }
+}
+
+fn zigzag(delta: i64) -> u64 {
+    ((delta << 1) ^ (delta >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Writes accesses in the binary format.
+pub struct BinaryWriter<W: Write> {
+    inner: W,
+    header: BinaryHeader,
+    prev_addr: u64,
+    records: u64,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    /// Writes the header; fails if the widths do not fit it.
+    pub fn new(mut inner: W, header: BinaryHeader) -> io::Result<Self> {
+        if !(1..=64).contains(&header.addr_width) || !(1..=0xffff).contains(&header.data_width) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "ADDR_WIDTH {} / DATA_WIDTH {} do not fit the header",
+                    header.addr_width, header.data_width
+                ),
+            ));
+        }
+        inner.write_all(&header.encode())?;
+        Ok(BinaryWriter {
+            inner,
+            header,
+            prev_addr: 0,
+            records: 0,
+            buf: Vec::with_capacity(1 + 2 * MAX_VARINT_BYTES),
+        })
+    }
+
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    pub fn write(&mut self, access: &Access) -> io::Result<()> {
+        if access.addr & !mask(self.header.addr_width) != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "address {:#x} is wider than ADDR_WIDTH {}",
+                    access.addr, self.header.addr_width
+                ),
+            ));
+        }
+        let kind = match access.kind {
+            AccessKind::Read => 0,
+            AccessKind::Write => 1,
+            AccessKind::InstrFetch => 2,
+        };
+        let size_code = if access.size.is_power_of_two()
+            && access.size.trailing_zeros() < EXPLICIT_SIZE as u32
+        {
+            access.size.trailing_zeros() as u8
+        } else {
+            EXPLICIT_SIZE
+        };
+        self.buf.clear();
+        self.buf.push(kind | size_code << 2);
+        if size_code == EXPLICIT_SIZE {
+            put_varint(&mut self.buf, access.size as u64);
+        }
+        let delta = access.addr.wrapping_sub(self.prev_addr) as i64;
+        put_varint(&mut self.buf, zigzag(delta));
+        self.inner.write_all(&self.buf)?;
+        self.prev_addr = access.addr;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads a binary trace as a stream.
+pub struct BinaryReader<R> {
+    inner: R,
+    header: BinaryHeader,
+    prev_addr: u64,
+    /// Byte offset of the next unread byte.
+    offset: u64,
+    done: bool,
+}
+
+impl<R: BufRead> BinaryReader<R> {
+    /// Reads and checks the header.
+    pub fn new(mut inner: R) -> Result<Self, TraceError> {
+        let mut bytes = [0u8; HEADER_BYTES];
+        inner.read_exact(&mut bytes).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => TraceError::Corrupt {
+                offset: 0,
+                message: "truncated header".into(),
+            },
+            _ => TraceError::Io(e),
+        })?;
+        let header = BinaryHeader::decode(&bytes)
+            .map_err(|message| TraceError::Corrupt { offset: 0, message })?;
+        Ok(BinaryReader {
+            inner,
+            header,
+            prev_addr: 0,
+            offset: HEADER_BYTES as u64,
+            done: false,
+        })
+    }
+
+    pub fn header(&self) -> BinaryHeader {
+        self.header
+    }
+
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.inner.fill_buf()?.first().copied();
+        if byte.is_some() {
+            self.inner.consume(1);
+            self.offset += 1;
+        }
+        Ok(byte)
+    }
+
+    fn varint(&mut self, start: u64) -> Result<u64, TraceError> {
+        let mut value = 0u64;
+        for i in 0..MAX_VARINT_BYTES {
+            let byte = self.byte()?.ok_or_else(|| TraceError::Corrupt {
+                offset: start,
+                message: "truncated record".into(),
+            })?;
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(TraceError::Corrupt {
+            offset: start,
+            message: "varint longer than 10 bytes".into(),
+        })
+    }
+
+    fn record(&mut self) -> Result<Option<Access>, TraceError> {
+        let start = self.offset;
+        let Some(flags) = self.byte()? else {
+            return Ok(None);
+        };
+        let corrupt = |message: String| TraceError::Corrupt {
+            offset: start,
+            message,
+        };
+        let kind = match flags & 0x3 {
+            0 => AccessKind::Read,
+            1 => AccessKind::Write,
+            2 => AccessKind::InstrFetch,
+            _ => return Err(corrupt("unknown access kind 3".into())),
+        };
+        if flags >> 6 != 0 {
+            return Err(corrupt(format!("reserved flag bits set in {:#04x}", flags)));
+        }
+        let size_code = (flags >> 2) & 0xf;
+        let size = if size_code == EXPLICIT_SIZE {
+            u32::try_from(self.varint(start)?)
+                .ok()
+                .filter(|&s| s > 0)
+                .ok_or_else(|| corrupt("bad explicit size".into()))?
+        } else {
+            1 << size_code
+        };
+        let addr = self
+            .prev_addr
+            .wrapping_add(unzigzag(self.varint(start)?) as u64);
+        if addr & !mask(self.header.addr_width) != 0 {
+            return Err(corrupt(format!(
+                "address {:#x} is wider than ADDR_WIDTH {}",
+                addr, self.header.addr_width
+            )));
+        }
+        self.prev_addr = addr;
+        Ok(Some(Access { kind, addr, size }))
+    }
+}
+
+impl<R: BufRead> Iterator for BinaryReader<R> {
+    type Item = Result<Access, TraceError>;
+
+    /// A corrupt record ends the stream: without its delta, no later
+    /// address can be recovered.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let record = self.record().transpose();
+        if !matches!(record, Some(Ok(_))) {
+            self.done = true;
+        }
+        record
+    }
+}
+
+impl<R: BufRead> TraceReader for BinaryReader<R> {
+    fn format(&self) -> TraceFormat {
+        TraceFormat::Binary
+    }
+}
+
+/// Writes every access of `trace` to `out` and returns the record count.
+pub fn convert<I, W>(trace: I, out: W, header: BinaryHeader) -> Result<u64, TraceError>
+where
+    I: IntoIterator<Item = Result<Access, TraceError>>,
+    W: Write,
+{
+    let mut writer = BinaryWriter::new(out, header)?;
+    for access in trace {
+        writer.write(&access?)?;
+    }
+    let records = writer.records();
+    writer.finish()?;
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: BinaryHeader = BinaryHeader {
+        addr_width: 32,
+        data_width: 32,
+    };
+
+    fn access(kind: AccessKind, addr: u64, size: u32) -> Access {
+        Access { kind, addr, size }
+    }
+
+    fn encode(accesses: &[Access]) -> Vec<u8> {
+        let mut writer = BinaryWriter::new(Vec::new(), HEADER).unwrap();
+        for a in accesses {
+            writer.write(a).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn open_err(bytes: &[u8]) -> String {
+        BinaryReader::new(bytes).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn round_trips_with_its_header() {
+        let accesses = [
+            access(AccessKind::Read, 0x1000, 4),
+            access(AccessKind::Read, 0x1004, 4),
+            access(AccessKind::Write, 0x0ff8, 8),
+            access(AccessKind::InstrFetch, 0xffff_fffc, 3),
+            access(AccessKind::Read, 0, 1),
+        ];
+        let bytes = encode(&accesses);
+        assert_eq!(&bytes[..HEADER_BYTES], b"CTRC\x01\x20\x20\x00");
+        // A word-sized step of +4 is a flags byte and a one-byte delta.
+        assert_eq!(&bytes[11..13], [0x08, 0x08]);
+        let mut reader = BinaryReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.header(), HEADER);
+        let read: Vec<Access> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(read, accesses);
+    }
+
+    #[test]
+    fn truncated_varint_is_corrupt() {
+        let mut bytes = encode(&[access(AccessKind::Read, 0x10, 4)]);
+        // A read of one byte whose delta never ends.
+        bytes.extend([0x00, 0x80]);
+        let mut reader = BinaryReader::new(&bytes[..]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err().to_string();
+        assert_eq!(err, "offset 0xa: truncated record");
+        assert!(reader.next().is_none());
+
+        let mut bytes = encode(&[]);
+        bytes.push(0x00);
+        bytes.extend([0xff; MAX_VARINT_BYTES]);
+        let err = BinaryReader::new(&bytes[..])
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "offset 0x8: varint longer than 10 bytes");
+    }
+
+    #[test]
+    fn header_errors() {
+        let good = encode(&[]);
+        let mut bad_magic = good.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(open_err(&bad_magic), "offset 0x0: not a binary trace");
+        let mut bad_version = good.clone();
+        bad_version[4] = 2;
+        assert_eq!(open_err(&bad_version), "offset 0x0: unsupported version 2");
+        assert_eq!(open_err(&good[..5]), "offset 0x0: truncated header");
+        let mut bad_width = good;
+        bad_width[5] = 65;
+        assert_eq!(open_err(&bad_width), "offset 0x0: bad ADDR_WIDTH 65");
+    }
+
+    #[test]
+    fn addresses_must_fit_addr_width() {
+        let mut writer = BinaryWriter::new(Vec::new(), HEADER).unwrap();
+        let err = writer
+            .write(&access(AccessKind::Read, 1 << 32, 4))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(writer.records(), 0);
+        let wide = BinaryHeader {
+            addr_width: 65,
+            data_width: 32,
+        };
+        assert!(BinaryWriter::new(Vec::new(), wide).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! ChampSim binary traces.
+//!
+//! A trace is a sequence of 64-byte `input_instr` records, little-endian:
+//!
+//! | offset | field                                        |
+//! |--------|----------------------------------------------|
+//! | 0      | `ip`, u64                                    |
+//! | 8      | `is_branch`, `branch_taken`, u8 each         |
+//! | 10     | `destination_registers[2]`, u8 each          |
+//! | 12     | `source_registers[4]`, u8 each               |
+//! | 16     | `destination_memory[2]`, u64 each            |
+//! | 32     | `source_memory[4]`, u64 each                 |
+//!
+//! Each record is replayed as the fetch of `ip`, then a read of every
+//! non-zero source address, then a write of every non-zero destination
+//! address. Records carry no access sizes, so every access has
+//! `DEFAULT_SIZE`. `ChampSimWriter` packs an access stream back into
+//! records. ChampSim's traces are usually distributed xz-compressed
+//! and must be decompressed first; gzip is read directly.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use super::{Access, AccessKind, TraceError, TraceFormat, TraceReader, DEFAULT_SIZE};
+
+pub const RECORD_BYTES: usize = 64;
+
+fn u64_at(record: &[u8; RECORD_BYTES], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&record[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// The accesses of one record, in replay order.
+pub fn decode_record(record: &[u8; RECORD_BYTES]) -> Vec<Access> {
+    let access = |kind, addr| Access {
+        kind,
+        addr,
+        size: DEFAULT_SIZE,
+    };
+    let mut accesses = vec![access(AccessKind::InstrFetch, u64_at(record, 0))];
+    let sources = (0..4).map(|i| u64_at(record, 32 + 8 * i));
+    accesses.extend(
+        sources
+            .filter(|&a| a != 0)
+            .map(|a| access(AccessKind::Read, a)),
+    );
+    let destinations = (0..2).map(|i| u64_at(record, 16 + 8 * i));
+    accesses.extend(
+        destinations
+            .filter(|&a| a != 0)
+            .map(|a| access(AccessKind::Write, a)),
+    );
+    accesses
+}
+
+/// Reads ChampSim records from `R`.
+pub struct ChampSimReader<R> {
+    inner: R,
+    /// Byte offset of the next record.
+    offset: u64,
+    pending: VecDeque<Access>,
+    done: bool,
+}
+
+impl<R: Read> ChampSimReader<R> {
+    pub fn new(inner: R) -> Self {
+        ChampSimReader {
+            inner,
+            offset: 0,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Records read so far.
+    pub fn records(&self) -> u64 {
+        self.offset / RECORD_BYTES as u64
+    }
+
+    /// Fills `record`, returning how many bytes were available.
+    fn read_record(&mut self, record: &mut [u8; RECORD_BYTES]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < RECORD_BYTES {
+            match self.inner.read(&mut record[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+}
+
+impl<R: Read> Iterator for ChampSimReader<R> {
+    type Item = Result<Access, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(access) = self.pending.pop_front() {
+            return Some(Ok(access));
+        }
+        if self.done {
+            return None;
+        }
+        let mut record = [0u8; RECORD_BYTES];
+        let filled = match self.read_record(&mut record) {
+            Ok(filled) => filled,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(TraceError::Io(e)));
+            }
+        };
+        if filled < RECORD_BYTES {
+            self.done = true;
+            return (filled > 0).then(|| {
+                Err(TraceError::Corrupt {
+                    offset: self.offset,
+                    message: format!("truncated record: {} of {} bytes", filled, RECORD_BYTES),
+                })
+            });
+        }
+        self.offset += RECORD_BYTES as u64;
+        self.pending.extend(decode_record(&record));
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+impl<R: Read> TraceReader for ChampSimReader<R> {
+    fn format(&self) -> TraceFormat {
+        TraceFormat::ChampSim
+    }
+}
+
+/// Packs accesses into ChampSim records. A fetch opens a record; the reads
+/// and writes after it fill its source and destination slots until one runs
+/// out or a read follows a write, which would reorder them. A data access
+/// with no record to join gets one whose `ip` repeats the last fetch, so
+/// replaying with fetches as reads sees an extra fetch there. Address 0
+/// marks an empty slot in the format and cannot be stored.
+pub struct ChampSimWriter<W: Write> {
+    inner: W,
+    ip: u64,
+    sources: Vec<u64>,
+    destinations: Vec<u64>,
+    open: bool,
+    records: u64,
+}
+
+impl<W: Write> ChampSimWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChampSimWriter {
+            inner,
+            ip: 0,
+            sources: Vec::with_capacity(4),
+            destinations: Vec::with_capacity(2),
+            open: false,
+            records: 0,
+        }
+    }
+
+    /// Records written so far, not counting one still being filled.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    fn flush_record(&mut self) -> io::Result<()> {
+        if !self.open {
+            return Ok(());
+        }
+        let mut record = [0u8; RECORD_BYTES];
+        record[..8].copy_from_slice(&self.ip.to_le_bytes());
+        for (i, addr) in self.destinations.drain(..).enumerate() {
+            record[16 + 8 * i..24 + 8 * i].copy_from_slice(&addr.to_le_bytes());
+        }
+        for (i, addr) in self.sources.drain(..).enumerate() {
+            record[32 + 8 * i..40 + 8 * i].copy_from_slice(&addr.to_le_bytes());
+        }
+        self.inner.write_all(&record)?;
+        self.open = false;
+        self.records += 1;
+        Ok(())
+    }
+
+    pub fn write(&mut self, access: &Access) -> io::Result<()> {
+        if access.kind != AccessKind::InstrFetch && access.addr == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ChampSim records cannot hold a data access to address 0",
+            ));
+        }
+        let fits = self.open
+            && match access.kind {
+                AccessKind::InstrFetch => false,
+                AccessKind::Read => self.sources.len() < 4 && self.destinations.is_empty(),
+                AccessKind::Write => self.destinations.len() < 2,
+            };
+        if !fits {
+            self.flush_record()?;
+            self.open = true;
+        }
+        match access.kind {
+            AccessKind::InstrFetch => self.ip = access.addr,
+            AccessKind::Read => self.sources.push(access.addr),
+            AccessKind::Write => self.destinations.push(access.addr),
+        }
+        Ok(())
+    }
+
+    /// Writes the last record and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_record()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(kind: AccessKind, addr: u64) -> Access {
+        Access {
+            kind,
+            addr,
+            size: DEFAULT_SIZE,
+        }
+    }
+
+    #[test]
+    fn decodes_a_record() {
+        let mut record = [0u8; RECORD_BYTES];
+        record[..8].copy_from_slice(&0x40_1000u64.to_le_bytes());
+        record[24..32].copy_from_slice(&0x9000u64.to_le_bytes());
+        record[32..40].copy_from_slice(&0x8000u64.to_le_bytes());
+        record[48..56].copy_from_slice(&0x8040u64.to_le_bytes());
+        // Fetch, then the non-zero sources, then the non-zero destinations.
+        assert_eq!(
+            decode_record(&record),
+            [
+                access(AccessKind::InstrFetch, 0x40_1000),
+                access(AccessKind::Read, 0x8000),
+                access(AccessKind::Read, 0x8040),
+                access(AccessKind::Write, 0x9000),
+            ]
+        );
+    }
+
+    #[test]
+    fn writer_round_trips_through_the_reader() {
+        let accesses = [
+            access(AccessKind::InstrFetch, 0x100),
+            access(AccessKind::Read, 0x1000),
+            access(AccessKind::Write, 0x2000),
+            access(AccessKind::InstrFetch, 0x104),
+            access(AccessKind::Write, 0x2004),
+        ];
+        let mut writer = ChampSimWriter::new(Vec::new());
+        for a in &accesses {
+            writer.write(a).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes.len(), 2 * RECORD_BYTES);
+        let mut reader = ChampSimReader::new(&bytes[..]);
+        let read: Vec<Access> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(read, accesses);
+        assert_eq!(reader.records(), 2);
+    }
+
+    #[test]
+    fn writer_splits_records() {
+        // A read after a write starts a new record that repeats the fetch.
+        let mut writer = ChampSimWriter::new(Vec::new());
+        writer
+            .write(&access(AccessKind::InstrFetch, 0x100))
+            .unwrap();
+        writer.write(&access(AccessKind::Write, 0x2000)).unwrap();
+        writer.write(&access(AccessKind::Read, 0x1000)).unwrap();
+        assert_eq!(writer.records(), 1);
+        let bytes = writer.finish().unwrap();
+        let read: Vec<Access> = ChampSimReader::new(&bytes[..])
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            read,
+            [
+                access(AccessKind::InstrFetch, 0x100),
+                access(AccessKind::Write, 0x2000),
+                access(AccessKind::InstrFetch, 0x100),
+                access(AccessKind::Read, 0x1000),
+            ]
+        );
+
+        let mut writer = ChampSimWriter::new(Vec::new());
+        let err = writer.write(&access(AccessKind::Read, 0)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn truncated_record_is_corrupt() {
+        let bytes = [0u8; RECORD_BYTES + 10];
+        let mut reader = ChampSimReader::new(&bytes[..]);
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err().to_string();
+        assert_eq!(err, "offset 0x40: truncated record: 10 of 64 bytes");
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,200 @@
+//! The Dinero IV "din" trace format.
+//!
+//! One access per line: a decimal label, a hex address and, optionally, a
+//! hex size in bytes, separated by whitespace. Either hex field may carry a
+//! `0x` prefix. Labels:
+//!
+//! | label | meaning                                   |
+//! |-------|-------------------------------------------|
+//! | 0     | data read (`cpu_read`)                    |
+//! | 1     | data write (`cpu_write`)                  |
+//! | 2     | instruction fetch                         |
+//! | 3     | escape record, skipped                    |
+//! | 4     | cache flush, skipped (the RTL has none)   |
+//!
+//! Blank lines and anything after the size are ignored, as Dinero does.
+
+use std::io::BufRead;
+
+use super::{
+    read_text_line, Access, AccessKind, TraceError, TraceFormat, TraceReader, DEFAULT_SIZE,
+};
+
+pub(crate) fn parse_hex(field: &str) -> Option<u64> {
+    let digits = field
+        .strip_prefix("0x")
+        .or_else(|| field.strip_prefix("0X"))
+        .unwrap_or(field);
+    u64::from_str_radix(digits, 16).ok()
+}
+
+/// Parses one line; `Ok(None)` for lines that carry no access.
+pub fn parse_line(line: &str) -> Result<Option<Access>, String> {
+    let mut fields = line.split_whitespace();
+    let Some(label) = fields.next() else {
+        return Ok(None);
+    };
+    let kind = match label {
+        "0" => AccessKind::Read,
+        "1" => AccessKind::Write,
+        "2" => AccessKind::InstrFetch,
+        "3" | "4" => return Ok(None),
+        _ => return Err(format!("unknown label {:?}", label)),
+    };
+    let addr = fields.next().ok_or("missing address")?;
+    let addr = parse_hex(addr).ok_or_else(|| format!("bad address {:?}", addr))?;
+    let size = match fields.next() {
+        None => DEFAULT_SIZE,
+        Some(field) => parse_hex(field)
+            .and_then(|s| u32::try_from(s).ok())
+            .filter(|&s| s > 0)
+            .ok_or_else(|| format!("bad size {:?}", field))?,
+    };
+    Ok(Some(Access { kind, addr, size }))
+}
+
+/// The din line for `access`, newline included.
+pub fn format_line(access: &Access) -> String {
+    let label = match access.kind {
+        AccessKind::Read => 0,
+        AccessKind::Write => 1,
+        AccessKind::InstrFetch => 2,
+    };
+    format!("{} {:x} {:x}\n", label, access.addr, access.size)
+}
+
+/// Reads din records from `R`, which may be a `gzip::GzDecoder`.
+pub struct DinReader<R> {
+    inner: R,
+    line: u64,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> DinReader<R> {
+    pub fn new(inner: R) -> Self {
+        DinReader {
+            inner,
+            line: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// The number of the line last read, counting from 1.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+impl<R: BufRead> Iterator for DinReader<R> {
+    type Item = Result<Access, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line += 1;
+            let text = match read_text_line(&mut self.inner, &mut self.buf, self.line) {
+                Ok(Some(text)) => text,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            match parse_line(text) {
+                Ok(Some(access)) => return Some(Ok(access)),
+                Ok(None) => continue,
+                Err(message) => {
+                    return Some(Err(TraceError::Parse {
+                        line: self.line,
+                        message,
+                    }))
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> TraceReader for DinReader<R> {
+    fn format(&self) -> TraceFormat {
+        TraceFormat::Din
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::super::decompress;
+    use super::*;
+
+    /// `gzip -n -9` of `TEXT`.
+    const GZ: [u8; 48] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x33, 0x50, 0x30, 0x34, 0x30,
+        0x30, 0xe0, 0x32, 0x54, 0x30, 0xa8, 0x30, 0x32, 0x30, 0x30, 0x51, 0xb0, 0xe0, 0x32, 0x56,
+        0x30, 0xe0, 0x32, 0x52, 0x30, 0x31, 0x00, 0x01, 0x2e, 0x00, 0xde, 0x0c, 0x65, 0xa6, 0x1f,
+        0x00, 0x00, 0x00,
+    ];
+    const TEXT: &str = "0 1000\n1 0x2004 8\n3 0\n2 400000\n";
+
+    fn expected() -> Vec<Access> {
+        vec![
+            Access {
+                kind: AccessKind::Read,
+                addr: 0x1000,
+                size: DEFAULT_SIZE,
+            },
+            Access {
+                kind: AccessKind::Write,
+                addr: 0x2004,
+                size: 8,
+            },
+            Access {
+                kind: AccessKind::InstrFetch,
+                addr: 0x40_0000,
+                size: DEFAULT_SIZE,
+            },
+        ]
+    }
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(parse_line("  \n"), Ok(None));
+        assert_eq!(parse_line("4 0"), Ok(None));
+        assert!(parse_line("5 100").is_err());
+        assert!(parse_line("0").is_err());
+        assert!(parse_line("0 xyz").is_err());
+        assert!(parse_line("1 100 0").is_err());
+        for access in expected() {
+            assert_eq!(parse_line(&format_line(&access)), Ok(Some(access)));
+        }
+    }
+
+    #[test]
+    fn reads_plain_and_gzip() {
+        let plain: Vec<Access> = DinReader::new(TEXT.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(plain, expected());
+        let input = decompress(&GZ[..]).unwrap();
+        let gzip: Vec<Access> = DinReader::new(input).map(Result::unwrap).collect();
+        assert_eq!(gzip, expected());
+    }
+
+    #[test]
+    fn gzip_errors_keep_their_message() {
+        let mut corrupt = GZ;
+        corrupt[GZ.len() - 8] ^= 0xff;
+        let input = decompress(io::Cursor::new(corrupt)).unwrap();
+        let mut reader = DinReader::new(input);
+        let err = reader.next().unwrap().unwrap_err();
+        assert!(matches!(err, TraceError::Io(_)), "{:?}", err);
+        assert!(err.to_string().contains("gzip: CRC mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn errors_carry_the_line() {
+        let mut reader = DinReader::new(&b"0 10\n\xff 20\n7 30\n"[..]);
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err().to_string();
+        assert_eq!(err, "line 2: not valid UTF-8");
+        let err = reader.next().unwrap().unwrap_err().to_string();
+        assert_eq!(err, "line 3: unknown label \"7\"");
+        assert!(reader.next().is_none());
+    }
+}
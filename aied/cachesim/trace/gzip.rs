@@ -0,0 +1,550 @@
+//! A streaming gzip (RFC 1952) / DEFLATE (RFC 1951) decoder.
+//!
+//! Trace files are routinely tens of gigabytes compressed, so this decodes
+//! incrementally behind `io::Read` and never holds more than the 32KB
+//! back-reference window plus one refill of output. Concatenated members, as
+//! produced by `cat a.gz b.gz` or parallel compressors, are read in order,
+//! and zero padding after the last member ends the stream as it does for
+//! `gzip -d`. The CRC-32 and length in each member's trailer are checked.
+
+use std::io::{self, BufRead, Read};
+
+const WINDOW: usize = 32 * 1024;
+/// Decode this much ahead of the reader before handing data out.
+const REFILL: usize = 64 * 1024;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code-length code lengths are sent in a dynamic block header.
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Whether `head` starts with the gzip magic number.
+pub fn is_gzip(head: &[u8]) -> bool {
+    head.starts_with(&[0x1f, 0x8b])
+}
+
+fn corrupt(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("gzip: {}", message))
+}
+
+/// A canonical Huffman code, decoded a bit at a time as in zlib's `puff`.
+struct Huffman {
+    /// Number of codes of each length 0..=15.
+    count: [u16; 16],
+    /// Symbols ordered by code.
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut count = [0u16; 16];
+        for &len in lengths {
+            count[len as usize] += 1;
+        }
+        // Over-subscribed codes are invalid; incomplete ones are allowed
+        // (a distance code may have a single symbol).
+        let mut left = 1i32;
+        for &c in &count[1..] {
+            left = (left << 1) - c as i32;
+            if left < 0 {
+                return Err(corrupt("over-subscribed Huffman code"));
+            }
+        }
+        let mut offs = [0u16; 16];
+        for len in 1..15 {
+            offs[len + 1] = offs[len] + count[len];
+        }
+        let mut symbol = vec![0; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbol[offs[len as usize] as usize] = sym as u16;
+                offs[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { count, symbol })
+    }
+
+    fn fixed() -> (Huffman, Huffman) {
+        let mut lengths = [0u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        let lit = Huffman::new(&lengths).expect("fixed literal code");
+        let dist = Huffman::new(&[5; 30]).expect("fixed distance code");
+        (lit, dist)
+    }
+}
+
+enum Block {
+    /// Between blocks; `last` is set once the final block has been read.
+    Header {
+        last: bool,
+    },
+    Stored {
+        remaining: u16,
+        last: bool,
+    },
+    Huffman {
+        lit: Huffman,
+        dist: Huffman,
+        last: bool,
+    },
+    /// The member's trailer has been checked; look for another member.
+    MemberEnd,
+    Done,
+}
+
+/// Decompresses a gzip stream read from `R`.
+pub struct GzDecoder<R> {
+    inner: R,
+    bit_buf: u64,
+    bit_count: u32,
+    block: Block,
+    /// The last `WINDOW` bytes of output followed by output not yet read.
+    buf: Vec<u8>,
+    read_pos: usize,
+    /// Output up to here has been folded into `crc` and `member_len`.
+    checked: usize,
+    crc: u32,
+    member_len: u32,
+}
+
+impl<R: BufRead> GzDecoder<R> {
+    /// Reads the first member header; fails if `inner` is not gzip.
+    pub fn new(inner: R) -> io::Result<Self> {
+        let mut decoder = GzDecoder {
+            inner,
+            bit_buf: 0,
+            bit_count: 0,
+            block: Block::Header { last: false },
+            buf: Vec::with_capacity(WINDOW + REFILL),
+            read_pos: 0,
+            checked: 0,
+            crc: !0,
+            member_len: 0,
+        };
+        decoder.member_header()?;
+        Ok(decoder)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let available = self.inner.fill_buf()?;
+        let byte = *available
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "gzip: truncated"))?;
+        self.inner.consume(1);
+        Ok(byte)
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.bit_count < n {
+            self.bit_buf |= (self.byte()? as u64) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = (self.bit_buf & ((1u64 << n) - 1)) as u32;
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        let drop = self.bit_count % 8;
+        self.bit_buf >>= drop;
+        self.bit_count -= drop;
+    }
+
+    fn aligned_byte(&mut self) -> io::Result<u8> {
+        if self.bit_count >= 8 {
+            self.bits(8).map(|b| b as u8)
+        } else {
+            self.byte()
+        }
+    }
+
+    fn le_u32(&mut self) -> io::Result<u32> {
+        let mut value = 0;
+        for i in 0..4 {
+            value |= (self.aligned_byte()? as u32) << (8 * i);
+        }
+        Ok(value)
+    }
+
+    fn member_header(&mut self) -> io::Result<()> {
+        let mut fixed = [0u8; 10];
+        for b in fixed.iter_mut() {
+            *b = self.byte()?;
+        }
+        if !is_gzip(&fixed) {
+            return Err(corrupt("not a gzip stream"));
+        }
+        if fixed[2] != 8 {
+            return Err(corrupt("unsupported compression method"));
+        }
+        let flags = fixed[3];
+        if flags & 0x04 != 0 {
+            // FEXTRA
+            let len = self.byte()? as usize | (self.byte()? as usize) << 8;
+            for _ in 0..len {
+                self.byte()?;
+            }
+        }
+        for flag in [0x08, 0x10] {
+            // FNAME, FCOMMENT: zero-terminated
+            if flags & flag != 0 {
+                while self.byte()? != 0 {}
+            }
+        }
+        if flags & 0x02 != 0 {
+            // FHCRC
+            self.byte()?;
+            self.byte()?;
+        }
+        self.crc = !0;
+        self.member_len = 0;
+        self.block = Block::Header { last: false };
+        Ok(())
+    }
+
+    fn decode(&mut self, code: &Huffman) -> io::Result<u16> {
+        let (mut code_bits, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code_bits |= self.bits(1)? as i32;
+            let count = code.count[len] as i32;
+            if code_bits - count < first {
+                return Ok(code.symbol[(index + (code_bits - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code_bits <<= 1;
+        }
+        Err(corrupt("invalid Huffman code"))
+    }
+
+    fn dynamic_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let nlen = self.bits(5)? as usize + 257;
+        let ndist = self.bits(5)? as usize + 1;
+        let ncode = self.bits(4)? as usize + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err(corrupt("bad code counts"));
+        }
+        let mut clen = [0u8; 19];
+        for &i in &CLEN_ORDER[..ncode] {
+            clen[i] = self.bits(3)? as u8;
+        }
+        let clen_code = Huffman::new(&clen)?;
+
+        let mut lengths = vec![0u8; nlen + ndist];
+        let mut i = 0;
+        while i < nlen + ndist {
+            let sym = self.decode(&clen_code)?;
+            let (value, repeat) = match sym {
+                0..=15 => (sym as u8, 1),
+                16 => {
+                    let prev = *lengths[..i]
+                        .last()
+                        .ok_or_else(|| corrupt("repeat with no previous length"))?;
+                    (prev, 3 + self.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.bits(3)? as usize),
+                _ => (0, 11 + self.bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(corrupt("too many code lengths"));
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(corrupt("no end-of-block code"));
+        }
+        Ok((
+            Huffman::new(&lengths[..nlen])?,
+            Huffman::new(&lengths[nlen..])?,
+        ))
+    }
+
+    fn emit(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+
+    /// Checks the trailer of the member whose output is now complete.
+    fn member_trailer(&mut self) -> io::Result<()> {
+        self.align();
+        let crc = self.le_u32()?;
+        let len = self.le_u32()?;
+        if crc != !self.crc {
+            return Err(corrupt("CRC mismatch"));
+        }
+        if len != self.member_len {
+            return Err(corrupt("length mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Consumes the rest of the input, which must be all zeros.
+    fn zero_padding(&mut self) -> io::Result<()> {
+        loop {
+            let available = self.inner.fill_buf()?;
+            if available.is_empty() {
+                return Ok(());
+            }
+            if available.iter().any(|&b| b != 0) {
+                return Err(corrupt("garbage after zero padding"));
+            }
+            let len = available.len();
+            self.inner.consume(len);
+        }
+    }
+
+    /// Decodes until there is output to read or the stream ends.
+    fn refill(&mut self) -> io::Result<()> {
+        let start = self.buf.len();
+        while self.buf.len() - start < REFILL {
+            match std::mem::replace(&mut self.block, Block::Done) {
+                Block::Done => break,
+                Block::MemberEnd => match self.inner.fill_buf()?.first() {
+                    None => self.block = Block::Done,
+                    // Tape and dd pad with zeros, which gzip -d ignores.
+                    Some(0) => {
+                        self.zero_padding()?;
+                        self.block = Block::Done;
+                    }
+                    Some(_) => self.member_header()?,
+                },
+                Block::Header { last: true } => {
+                    self.checksum();
+                    self.member_trailer()?;
+                    self.block = Block::MemberEnd;
+                    break;
+                }
+                Block::Header { last: false } => {
+                    let last = self.bits(1)? == 1;
+                    self.block = match self.bits(2)? {
+                        0 => {
+                            self.align();
+                            let len = self.bits(16)? as u16;
+                            let nlen = self.bits(16)? as u16;
+                            if len != !nlen {
+                                return Err(corrupt("stored block length check failed"));
+                            }
+                            Block::Stored {
+                                remaining: len,
+                                last,
+                            }
+                        }
+                        1 => {
+                            let (lit, dist) = Huffman::fixed();
+                            Block::Huffman { lit, dist, last }
+                        }
+                        2 => {
+                            let (lit, dist) = self.dynamic_codes()?;
+                            Block::Huffman { lit, dist, last }
+                        }
+                        _ => return Err(corrupt("invalid block type")),
+                    };
+                }
+                Block::Stored { remaining, last } => {
+                    for _ in 0..remaining {
+                        let byte = self.aligned_byte()?;
+                        self.emit(byte);
+                    }
+                    self.block = Block::Header { last };
+                }
+                Block::Huffman { lit, dist, last } => {
+                    let mut end = false;
+                    while self.buf.len() - start < REFILL {
+                        let sym = self.decode(&lit)? as usize;
+                        if sym < 256 {
+                            self.emit(sym as u8);
+                        } else if sym == 256 {
+                            end = true;
+                            break;
+                        } else {
+                            let sym = sym - 257;
+                            if sym >= 29 {
+                                return Err(corrupt("invalid length symbol"));
+                            }
+                            let len = LENGTH_BASE[sym] as usize
+                                + self.bits(LENGTH_EXTRA[sym] as u32)? as usize;
+                            let dsym = self.decode(&dist)? as usize;
+                            if dsym >= 30 {
+                                return Err(corrupt("invalid distance symbol"));
+                            }
+                            let distance = DIST_BASE[dsym] as usize
+                                + self.bits(DIST_EXTRA[dsym] as u32)? as usize;
+                            if distance > self.buf.len() {
+                                return Err(corrupt("distance too far back"));
+                            }
+                            for _ in 0..len {
+                                let byte = self.buf[self.buf.len() - distance];
+                                self.emit(byte);
+                            }
+                        }
+                    }
+                    self.block = if end {
+                        Block::Header { last }
+                    } else {
+                        Block::Huffman { lit, dist, last }
+                    };
+                }
+            }
+        }
+        self.checksum();
+        Ok(())
+    }
+
+    /// Folds output not yet counted into the running CRC and length.
+    fn checksum(&mut self) {
+        let new = &self.buf[self.checked..];
+        self.crc = crc32_update(self.crc, new);
+        self.member_len = self.member_len.wrapping_add(new.len() as u32);
+        self.checked = self.buf.len();
+    }
+}
+
+impl<R: BufRead> Read for GzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.read_pos == self.buf.len() && !matches!(self.block, Block::Done) {
+            // Keep only the window before decoding more.
+            if self.buf.len() > WINDOW {
+                let drop = self.buf.len() - WINDOW;
+                self.buf.drain(..drop);
+                self.read_pos -= drop;
+                self.checked -= drop;
+            }
+            self.refill()?;
+        }
+        let n = out.len().min(self.buf.len() - self.read_pos);
+        out[..n].copy_from_slice(&self.buf[self.read_pos..self.read_pos + n]);
+        self.read_pos += n;
+        Ok(n)
+    }
+}
+
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    table
+}
+
+/// Continues a CRC-32 (pre- and post-inverted by the caller).
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    use std::sync::OnceLock;
+    static TABLE: OnceLock<[u32; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(crc32_table);
+    data.iter().fold(crc, |c, &b| {
+        table[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `gzip -n -1` of `hello hello hello`, one line: a fixed-Huffman block.
+    const HELLO: [u8; 29] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00, 0x3b, 0x7c, 0x8a, 0xdf, 0x12, 0x00, 0x00, 0x00,
+    ];
+
+    /// `gzip -n -9` of `trace()`: one dynamic-Huffman block.
+    const TRACE: [u8; 190] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x2d, 0x90, 0xbb, 0x11, 0x03,
+        0x21, 0x0c, 0x05, 0xf3, 0xab, 0xc2, 0x25, 0xe8, 0x07, 0x88, 0x36, 0x9c, 0x38, 0x06, 0x01,
+        0x35, 0xb8, 0x7c, 0x9f, 0xef, 0x29, 0x61, 0x18, 0xb1, 0xec, 0x32, 0x7c, 0x5e, 0xf4, 0xa5,
+        0xeb, 0x7d, 0xaf, 0xbc, 0x6b, 0xd8, 0xb3, 0xd3, 0x58, 0xee, 0xd7, 0xe7, 0x3f, 0x9b, 0x66,
+        0x81, 0x59, 0x9f, 0x9c, 0x9c, 0xf3, 0xb2, 0xe7, 0x54, 0xab, 0x77, 0xc7, 0xcc, 0x4e, 0x49,
+        0x4e, 0xab, 0x10, 0xee, 0x72, 0xec, 0xf4, 0x91, 0x0e, 0x70, 0x7b, 0xd4, 0x78, 0x0e, 0x65,
+        0xb1, 0x42, 0x37, 0xdb, 0x01, 0x25, 0x7d, 0x4f, 0x54, 0xbd, 0x34, 0xc8, 0xe4, 0x7e, 0x11,
+        0xa8, 0xa2, 0x84, 0xa6, 0x68, 0x0f, 0xb8, 0x84, 0x3c, 0x29, 0x6a, 0x05, 0x49, 0xdd, 0x9b,
+        0x21, 0xbb, 0x9f, 0xb8, 0x80, 0xe9, 0x9c, 0x1d, 0x4d, 0x1e, 0x52, 0x61, 0x53, 0xef, 0x92,
+        0x5c, 0x3d, 0x1b, 0x51, 0x2d, 0x75, 0xc0, 0xc7, 0xba, 0x5a, 0x72, 0x62, 0x8a, 0x2c, 0xd3,
+        0x38, 0x99, 0x3d, 0x3c, 0xc1, 0xdd, 0x9f, 0x84, 0xac, 0xcc, 0x63, 0xd0, 0x8d, 0x4a, 0x49,
+        0x79, 0x04, 0xaa, 0x4d, 0x3b, 0x64, 0x52, 0x46, 0x01, 0x65, 0xcc, 0x68, 0x8a, 0xb4, 0x15,
+        0xd7, 0x0f, 0xbc, 0x64, 0x8c, 0x1b, 0x83, 0x01, 0x00, 0x00,
+    ];
+
+    /// `gzip -n -9` of nothing.
+    const EMPTY: [u8; 20] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn trace() -> String {
+        (0..40u64)
+            .map(|i| {
+                let op = if i % 3 == 0 { 'W' } else { 'R' };
+                format!("{} {:#x}\n", op, (i * 2654435761 % 65536) * 4)
+            })
+            .collect()
+    }
+
+    fn gunzip(input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        GzDecoder::new(input)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn fixed_and_dynamic_blocks() {
+        assert_eq!(gunzip(&HELLO).unwrap(), b"hello hello hello\n");
+        assert_eq!(gunzip(&TRACE).unwrap(), trace().as_bytes());
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(gunzip(&EMPTY).unwrap(), b"");
+    }
+
+    #[test]
+    fn members_in_order() {
+        let stream = [&HELLO[..], &EMPTY, &TRACE, &HELLO].concat();
+        let expected = format!("hello hello hello\n{}hello hello hello\n", trace());
+        assert_eq!(gunzip(&stream).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn zero_padding_ends_the_stream() {
+        let padded = [&TRACE[..], &[0; 512]].concat();
+        assert_eq!(gunzip(&padded).unwrap(), trace().as_bytes());
+        let mut garbage = padded;
+        garbage.push(1);
+        assert!(gunzip(&garbage).is_err());
+    }
+
+    #[test]
+    fn corruption_is_an_error() {
+        let mut bad = HELLO;
+        bad[12] ^= 0x10;
+        assert!(gunzip(&bad).is_err());
+        assert!(gunzip(&HELLO[..HELLO.len() - 1]).is_err());
+        assert!(gunzip(b"R 0x40\n").is_err());
+    }
+}
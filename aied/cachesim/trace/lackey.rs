@@ -0,0 +1,186 @@
+//! Valgrind Lackey output, from `valgrind --tool=lackey --trace-mem=yes`.
+//!
+//! Each line is a kind, a hex address and a decimal size:
+//!
+//! ```text
+//! I  0400d7d4,8
+//!  S 7ff000398,8
+//!  L 04222cac,4
+//!  M 0421d2d0,4
+//! ```
+//!
+//! `I` is an instruction fetch, `L` a load and `S` a store. `M` modifies
+//! memory in place, a load followed by a store to the same address, and is
+//! replayed as both. Valgrind's own `==pid==` messages are skipped, so the
+//! tool's stderr can be read as is.
+
+use std::io::BufRead;
+
+use super::din::parse_hex;
+use super::{read_text_line, Access, AccessKind, TraceError, TraceFormat, TraceReader};
+
+/// Parses one line into up to two accesses; `Ok([None, None])` for lines
+/// that carry none.
+pub fn parse_line(line: &str) -> Result<[Option<Access>; 2], String> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with("==") || trimmed.starts_with("--") {
+        return Ok([None, None]);
+    }
+    let (kind, rest) = trimmed
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("expected a kind and an address in {:?}", trimmed))?;
+    let (addr, size) = rest
+        .trim()
+        .split_once(',')
+        .ok_or_else(|| format!("expected address,size in {:?}", rest.trim()))?;
+    let addr = parse_hex(addr).ok_or_else(|| format!("bad address {:?}", addr))?;
+    let size = size
+        .parse::<u32>()
+        .ok()
+        .filter(|&s| s > 0)
+        .ok_or_else(|| format!("bad size {:?}", size))?;
+    let access = |kind| Some(Access { kind, addr, size });
+    match kind {
+        "I" => Ok([access(AccessKind::InstrFetch), None]),
+        "L" => Ok([access(AccessKind::Read), None]),
+        "S" => Ok([access(AccessKind::Write), None]),
+        "M" => Ok([access(AccessKind::Read), access(AccessKind::Write)]),
+        _ => Err(format!("unknown kind {:?}", kind)),
+    }
+}
+
+/// The Lackey line for `access`, newline included. Stores come out as `S`;
+/// nothing is merged back into `M`.
+pub fn format_line(access: &Access) -> String {
+    let kind = match access.kind {
+        AccessKind::InstrFetch => "I ",
+        AccessKind::Read => " L",
+        AccessKind::Write => " S",
+    };
+    format!("{} {:08x},{}\n", kind, access.addr, access.size)
+}
+
+/// Reads Lackey records from `R`.
+pub struct LackeyReader<R> {
+    inner: R,
+    line: u64,
+    buf: Vec<u8>,
+    /// The store half of an `M` record.
+    pending: Option<Access>,
+}
+
+impl<R: BufRead> LackeyReader<R> {
+    pub fn new(inner: R) -> Self {
+        LackeyReader {
+            inner,
+            line: 0,
+            buf: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// The number of the line last read, counting from 1.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+impl<R: BufRead> Iterator for LackeyReader<R> {
+    type Item = Result<Access, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(access) = self.pending.take() {
+            return Some(Ok(access));
+        }
+        loop {
+            self.line += 1;
+            let text = match read_text_line(&mut self.inner, &mut self.buf, self.line) {
+                Ok(Some(text)) => text,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            match parse_line(text) {
+                Ok([Some(first), second]) => {
+                    self.pending = second;
+                    return Some(Ok(first));
+                }
+                Ok(_) => continue,
+                Err(message) => {
+                    return Some(Err(TraceError::Parse {
+                        line: self.line,
+                        message,
+                    }))
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> TraceReader for LackeyReader<R> {
+    fn format(&self) -> TraceFormat {
+        TraceFormat::Lackey
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(kind: AccessKind, addr: u64, size: u32) -> Access {
+        Access { kind, addr, size }
+    }
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(parse_line("==1234== Memcheck"), Ok([None, None]));
+        assert_eq!(parse_line("   "), Ok([None, None]));
+        assert_eq!(
+            parse_line("I  0400d7d4,8"),
+            Ok([Some(access(AccessKind::InstrFetch, 0x400d7d4, 8)), None])
+        );
+        assert_eq!(
+            parse_line(" M 0421d2d0,4"),
+            Ok([
+                Some(access(AccessKind::Read, 0x421d2d0, 4)),
+                Some(access(AccessKind::Write, 0x421d2d0, 4)),
+            ])
+        );
+        assert!(parse_line(" X 10,4").is_err());
+        assert!(parse_line(" L 10").is_err());
+        assert!(parse_line(" L 10,0").is_err());
+        assert!(parse_line(" L zz,4").is_err());
+        for kind in [AccessKind::InstrFetch, AccessKind::Read, AccessKind::Write] {
+            let a = access(kind, 0x7ff000398, 8);
+            assert_eq!(parse_line(&format_line(&a)), Ok([Some(a), None]));
+        }
+    }
+
+    #[test]
+    fn modify_replays_as_a_load_and_a_store() {
+        let text = "==7== start\nI  1000,4\n M 2000,8\n S 3000,4\n";
+        let accesses: Vec<Access> = LackeyReader::new(text.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            accesses,
+            [
+                access(AccessKind::InstrFetch, 0x1000, 4),
+                access(AccessKind::Read, 0x2000, 8),
+                access(AccessKind::Write, 0x2000, 8),
+                access(AccessKind::Write, 0x3000, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_carry_the_line() {
+        let mut reader = LackeyReader::new(&b" L 10,4\n\xff\n Q 10,4\n"[..]);
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err().to_string();
+        assert_eq!(err, "line 2: not valid UTF-8");
+        let err = reader.next().unwrap().unwrap_err().to_string();
+        assert_eq!(err, "line 3: unknown kind \"Q\"");
+        assert!(reader.next().is_none());
+        assert_eq!(reader.line(), 4);
+    }
+}
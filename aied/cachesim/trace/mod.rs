@@ -0,0 +1,376 @@
+//! Memory-access traces and the drivers that replay them.
+//!
+//! A trace is an iterator of `Result<Access, TraceError>`, so a reader
+//! streams records from disk while the cache consumes them and a bad record
+//! stops the run with its location. Every reader implements `TraceReader`:
+//! `din` reads Dinero IV traces, `lackey` Valgrind Lackey output and
+//! `champsim` ChampSim's binary records and `binary` this toolkit's own
+//! compact format, which `binary::convert` writes from any of the others.
+//! `open` sees through gzip compression by its magic number, whatever the
+//! file is called, and `write_trace` writes any access stream, such as the
+//! synthetic workloads in `synth`, in any of the formats.
+//!
+//! The caches have no instruction port, so `IfetchMode` decides whether a
+//! fetch is replayed as a `cpu_read` or dropped.
+
+pub mod binary;
+pub mod champsim;
+pub mod din;
+pub mod gzip;
+pub mod lackey;
+pub mod synth;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::fsm::{CpuRequest, CycleModel};
+use super::functional::{FunctionalCache, Stats};
+use super::memory::Memory;
+use super::system::{System, TimingStats};
+
+pub use binary::{BinaryHeader, BinaryReader, BinaryWriter};
+pub use champsim::{ChampSimReader, ChampSimWriter};
+pub use din::DinReader;
+pub use lackey::LackeyReader;
+pub use synth::Workload;
+
+/// The size of an access whose record gives none.
+pub const DEFAULT_SIZE: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+    InstrFetch,
+}
+
+/// One record of a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub addr: u64,
+    /// Bytes accessed. The models take one word per request, so this is
+    /// carried along but does not split an access.
+    pub size: u32,
+}
+
+/// How instruction fetches reach a data cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum IfetchMode {
+    /// Drop them, for a data-cache study.
+    #[default]
+    Skip,
+    /// Replay them as reads, as a unified cache would see them.
+    AsRead,
+}
+
+impl IfetchMode {
+    /// `Some(is_write)` for an access the cache should see.
+    pub fn direction(self, access: &Access) -> Option<bool> {
+        match (access.kind, self) {
+            (AccessKind::Read, _) => Some(false),
+            (AccessKind::Write, _) => Some(true),
+            (AccessKind::InstrFetch, IfetchMode::AsRead) => Some(false),
+            (AccessKind::InstrFetch, IfetchMode::Skip) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    /// A malformed record, at a 1-based line number.
+    Parse {
+        line: u64,
+        message: String,
+    },
+    /// A malformed binary record, at a byte offset.
+    Corrupt {
+        offset: u64,
+        message: String,
+    },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Io(e) => write!(f, "trace I/O error: {}", e),
+            TraceError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            TraceError::Corrupt { offset, message } => {
+                write!(f, "offset {:#x}: {}", offset, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TraceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TraceError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> Self {
+        TraceError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TraceFormat {
+    Din,
+    Lackey,
+    ChampSim,
+    Binary,
+}
+
+impl TraceFormat {
+    pub const ALL: [TraceFormat; 4] = [
+        TraceFormat::Din,
+        TraceFormat::Lackey,
+        TraceFormat::ChampSim,
+        TraceFormat::Binary,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TraceFormat::Din => "din",
+            TraceFormat::Lackey => "lackey",
+            TraceFormat::ChampSim => "champsim",
+            TraceFormat::Binary => "binary",
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TraceFormat::ALL
+            .into_iter()
+            .find(|format| format.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown trace format {:?}", s))
+    }
+}
+
+/// A reader for one trace format. The drivers take any iterator of
+/// accesses; this adds what a tool needs to report on the input.
+pub trait TraceReader: Iterator<Item = Result<Access, TraceError>> {
+    fn format(&self) -> TraceFormat;
+}
+
+/// Reads the next line of a text trace into `buf`; `None` at the end of the
+/// input. Only a line that is not UTF-8 is a `Parse` error, at `line`;
+/// errors from the input itself, gzip's included, stay `Io`.
+pub(crate) fn read_text_line<'a, R: BufRead>(
+    inner: &mut R,
+    buf: &'a mut Vec<u8>,
+    line: u64,
+) -> Result<Option<&'a str>, TraceError> {
+    buf.clear();
+    if inner.read_until(b'\n', buf)? == 0 {
+        return Ok(None);
+    }
+    std::str::from_utf8(buf)
+        .map(Some)
+        .map_err(|_| TraceError::Parse {
+            line,
+            message: "not valid UTF-8".into(),
+        })
+}
+
+/// Wraps `reader` in a gzip decoder if the stream starts with the gzip magic.
+pub fn decompress<R: BufRead + 'static>(mut reader: R) -> io::Result<Box<dyn BufRead>> {
+    if gzip::is_gzip(reader.fill_buf()?) {
+        Ok(Box::new(BufReader::new(gzip::GzDecoder::new(reader)?)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Opens a trace file, decompressing it if it is gzip.
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead>> {
+    decompress(BufReader::new(File::open(path)?))
+}
+
+/// Opens a din trace, plain or gzip.
+pub fn open_din<P: AsRef<Path>>(path: P) -> io::Result<DinReader<Box<dyn BufRead>>> {
+    Ok(DinReader::new(open(path)?))
+}
+
+/// Reads a trace of the given format from `reader`, plain or gzip. A binary
+/// trace's header is read and checked here.
+pub fn reader<R: BufRead + 'static>(
+    reader: R,
+    format: TraceFormat,
+) -> Result<Box<dyn TraceReader>, TraceError> {
+    let input = decompress(reader)?;
+    Ok(match format {
+        TraceFormat::Din => Box::new(DinReader::new(input)),
+        TraceFormat::Lackey => Box::new(LackeyReader::new(input)),
+        TraceFormat::ChampSim => Box::new(ChampSimReader::new(input)),
+        TraceFormat::Binary => Box::new(BinaryReader::new(input)?),
+    })
+}
+
+/// Opens a trace file of the given format, plain or gzip.
+pub fn open_trace<P: AsRef<Path>>(
+    path: P,
+    format: TraceFormat,
+) -> Result<Box<dyn TraceReader>, TraceError> {
+    reader(BufReader::new(File::open(path)?), format)
+}
+
+/// Converts a trace file of any format to the binary format and returns the
+/// number of records written.
+pub fn convert_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    format: TraceFormat,
+    output: Q,
+    header: BinaryHeader,
+) -> Result<u64, TraceError> {
+    let out = io::BufWriter::new(File::create(output)?);
+    binary::convert(open_trace(input, format)?, out, header)
+}
+
+/// Writes `accesses` to `out` in `format` and returns how many were written.
+/// `header` is only used by the binary format.
+pub fn write_trace<I, W>(
+    accesses: I,
+    format: TraceFormat,
+    out: W,
+    header: BinaryHeader,
+) -> io::Result<u64>
+where
+    I: IntoIterator<Item = Access>,
+    W: Write,
+{
+    let mut out = BufWriter::new(out);
+    let mut count = 0;
+    match format {
+        TraceFormat::Din | TraceFormat::Lackey => {
+            let line = match format {
+                TraceFormat::Din => din::format_line,
+                _ => lackey::format_line,
+            };
+            for access in accesses {
+                out.write_all(line(&access).as_bytes())?;
+                count += 1;
+            }
+            out.flush()?;
+        }
+        TraceFormat::ChampSim => {
+            let mut writer = ChampSimWriter::new(out);
+            for access in accesses {
+                writer.write(&access)?;
+                count += 1;
+            }
+            writer.finish()?;
+        }
+        TraceFormat::Binary => {
+            let mut writer = BinaryWriter::new(out, header)?;
+            for access in accesses {
+                writer.write(&access)?;
+            }
+            count = writer.records();
+            writer.finish()?;
+        }
+    }
+    Ok(count)
+}
+
+/// Replays a trace through a functional cache and returns its counters. The
+/// write buffer, if any, is flushed at the end.
+pub fn run_functional<I>(
+    cache: &mut FunctionalCache,
+    trace: I,
+    ifetch: IfetchMode,
+) -> Result<Stats, TraceError>
+where
+    I: IntoIterator<Item = Result<Access, TraceError>>,
+{
+    for access in trace {
+        let access = access?;
+        if let Some(write) = ifetch.direction(&access) {
+            cache.access(access.addr, write);
+        }
+    }
+    cache.flush_write_buffer();
+    Ok(*cache.stats())
+}
+
+/// Counters from replaying a trace through a cycle model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CycleStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub misses: u64,
+    pub write_backs: u64,
+    /// Cycles from the first request to the last completion.
+    pub cycles: u64,
+    /// The system's cycle counts over the run.
+    pub timing: TimingStats,
+}
+
+impl CycleStats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    /// Average cycles per access.
+    pub fn cycles_per_access(&self) -> f64 {
+        if self.accesses() == 0 {
+            0.0
+        } else {
+            self.cycles as f64 / self.accesses() as f64
+        }
+    }
+}
+
+/// Replays a trace through a cycle model, one access at a time as the CPU in
+/// usage.rs would. A write stores the low bits of its address, so the data
+/// arrays hold something recognizable afterwards.
+pub fn run_cycle<C, M, I>(
+    system: &mut System<C, M>,
+    trace: I,
+    ifetch: IfetchMode,
+) -> Result<CycleStats, TraceError>
+where
+    C: CycleModel,
+    M: Memory,
+    I: IntoIterator<Item = Result<Access, TraceError>>,
+{
+    let mut stats = CycleStats::default();
+    let before = *system.timing();
+    for access in trace {
+        let access = access?;
+        let request = match ifetch.direction(&access) {
+            Some(true) => {
+                stats.writes += 1;
+                CpuRequest::write(access.addr, access.addr)
+            }
+            Some(false) => {
+                stats.reads += 1;
+                CpuRequest::read(access.addr)
+            }
+            None => continue,
+        };
+        let result = system.access(request);
+        stats.misses += !result.hit as u64;
+        stats.write_backs += result.write_back as u64;
+        stats.cycles += result.cycles;
+    }
+    stats.timing = system.timing().since(&before);
+    Ok(stats)
+}
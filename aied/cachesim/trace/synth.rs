@@ -0,0 +1,516 @@
+//! Synthetic workloads for driving the caches without a recorded trace.
+//!
+//! A `Workload` describes an access pattern; `accesses(seed)` streams it
+//! lazily, so even a large matrix multiply costs no memory up front. The
+//! same workload and seed always give the same stream. Patterns that read
+//! and write in a fixed order (the matrix and stencil kernels, pointer
+//! chasing) use the seed only where they need randomness; `write_ratio`
+//! makes the others' stores random as well.
+//!
+//! Array kernels are laid out row-major, one array after another from
+//! `base`, with `elem` bytes per element.
+
+use std::fmt;
+
+use super::super::rng::Rng;
+use super::{Access, AccessKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Workload {
+    /// `count` consecutive `size`-byte accesses, wrapping within
+    /// `footprint` bytes.
+    Sequential {
+        base: u64,
+        footprint: u64,
+        count: u64,
+        size: u32,
+        write_ratio: f64,
+    },
+    /// Every `stride` bytes, wrapping within `footprint` bytes.
+    Strided {
+        base: u64,
+        footprint: u64,
+        stride: u64,
+        count: u64,
+        size: u32,
+        write_ratio: f64,
+    },
+    /// Aligned `size`-byte accesses anywhere in `footprint` bytes.
+    Uniform {
+        base: u64,
+        footprint: u64,
+        count: u64,
+        size: u32,
+        write_ratio: f64,
+    },
+    /// `items` aligned `size`-byte items, item of popularity rank r chosen
+    /// with probability proportional to 1 / r^`alpha`. Ranks are scattered
+    /// over the footprint, so hot items do not share sets by construction.
+    Zipf {
+        base: u64,
+        items: u64,
+        alpha: f64,
+        count: u64,
+        size: u32,
+        write_ratio: f64,
+    },
+    /// `count` 8-byte loads following a random single cycle through `nodes`
+    /// nodes of `node_bytes` each, as a linked list walk would.
+    PointerChase {
+        base: u64,
+        nodes: u64,
+        node_bytes: u64,
+        count: u64,
+    },
+    /// C = A * B for `n` x `n` matrices. Untiled it is the i-j-k loop nest;
+    /// with a `tile`, the same nest over `tile`-sized blocks, reading each
+    /// C element before accumulating into it.
+    MatMul {
+        base: u64,
+        n: u64,
+        elem: u32,
+        tile: Option<u64>,
+    },
+    /// A 5-point Jacobi stencil over a `rows` x `cols` grid, ping-ponging
+    /// between two grids for `iterations` sweeps of the interior.
+    Stencil {
+        base: u64,
+        rows: u64,
+        cols: u64,
+        elem: u32,
+        iterations: u64,
+    },
+}
+
+fn read(addr: u64, size: u32) -> Access {
+    Access {
+        kind: AccessKind::Read,
+        addr,
+        size,
+    }
+}
+
+fn write(addr: u64, size: u32) -> Access {
+    Access {
+        kind: AccessKind::Write,
+        addr,
+        size,
+    }
+}
+
+/// A read or, with probability `write_ratio`, a write.
+fn read_or_write(rng: &mut Rng, write_ratio: f64, addr: u64, size: u32) -> Access {
+    if rng.chance(write_ratio) {
+        write(addr, size)
+    } else {
+        read(addr, size)
+    }
+}
+
+impl Workload {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Workload::Sequential { .. } => "sequential",
+            Workload::Strided { .. } => "strided",
+            Workload::Uniform { .. } => "uniform",
+            Workload::Zipf { .. } => "zipf",
+            Workload::PointerChase { .. } => "pointer-chase",
+            Workload::MatMul { tile: None, .. } => "matmul",
+            Workload::MatMul { tile: Some(_), .. } => "matmul-tiled",
+            Workload::Stencil { .. } => "stencil",
+        }
+    }
+
+    /// Checks the parameters describe a non-empty pattern.
+    pub fn validate(&self) -> Result<(), String> {
+        let ok = match *self {
+            Workload::Sequential {
+                footprint,
+                size,
+                write_ratio,
+                ..
+            }
+            | Workload::Uniform {
+                footprint,
+                size,
+                write_ratio,
+                ..
+            } => size > 0 && footprint >= size as u64 && (0.0..=1.0).contains(&write_ratio),
+            Workload::Strided {
+                footprint,
+                stride,
+                size,
+                write_ratio,
+                ..
+            } => {
+                size > 0
+                    && stride > 0
+                    && footprint >= size as u64
+                    && (0.0..=1.0).contains(&write_ratio)
+            }
+            Workload::Zipf {
+                items,
+                alpha,
+                size,
+                write_ratio,
+                ..
+            } => items > 0 && alpha > 0.0 && size > 0 && (0.0..=1.0).contains(&write_ratio),
+            Workload::PointerChase {
+                nodes, node_bytes, ..
+            } => nodes > 0 && node_bytes >= 8,
+            Workload::MatMul { n, elem, tile, .. } => n > 0 && elem > 0 && tile != Some(0),
+            Workload::Stencil {
+                rows, cols, elem, ..
+            } => rows >= 3 && cols >= 3 && elem > 0,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(format!(
+                "invalid parameters for {}: {:?}",
+                self.name(),
+                self
+            ))
+        }
+    }
+
+    /// The access stream for `seed`.
+    pub fn accesses(&self, seed: u64) -> Result<Box<dyn Iterator<Item = Access>>, String> {
+        self.validate()?;
+        let mut rng = Rng::new(seed);
+        Ok(match *self {
+            Workload::Sequential {
+                base,
+                footprint,
+                count,
+                size,
+                write_ratio,
+            } => {
+                let slots = footprint / size as u64;
+                Box::new((0..count).map(move |i| {
+                    let addr = base + (i % slots) * size as u64;
+                    read_or_write(&mut rng, write_ratio, addr, size)
+                }))
+            }
+            Workload::Strided {
+                base,
+                footprint,
+                stride,
+                count,
+                size,
+                write_ratio,
+            } => Box::new((0..count).map(move |i| {
+                let offset = (i as u128 * stride as u128 % footprint as u128) as u64;
+                read_or_write(&mut rng, write_ratio, base + offset, size)
+            })),
+            Workload::Uniform {
+                base,
+                footprint,
+                count,
+                size,
+                write_ratio,
+            } => {
+                let slots = footprint / size as u64;
+                Box::new((0..count).map(move |_| {
+                    let addr = base + rng.below(slots) * size as u64;
+                    read_or_write(&mut rng, write_ratio, addr, size)
+                }))
+            }
+            Workload::Zipf {
+                base,
+                items,
+                alpha,
+                count,
+                size,
+                write_ratio,
+            } => {
+                let mut cdf = Vec::with_capacity(items as usize);
+                let mut total = 0.0;
+                for rank in 1..=items {
+                    total += 1.0 / (rank as f64).powf(alpha);
+                    cdf.push(total);
+                }
+                let slot = shuffled(items, &mut rng);
+                Box::new((0..count).map(move |_| {
+                    let u = rng.unit() * total;
+                    let rank = cdf.partition_point(|&c| c <= u).min(cdf.len() - 1);
+                    let addr = base + slot[rank] * size as u64;
+                    read_or_write(&mut rng, write_ratio, addr, size)
+                }))
+            }
+            Workload::PointerChase {
+                base,
+                nodes,
+                node_bytes,
+                count,
+            } => {
+                // Sattolo's algorithm: a random permutation with one cycle.
+                let mut next: Vec<u64> = (0..nodes).collect();
+                for i in (1..nodes as usize).rev() {
+                    let j = rng.below(i as u64) as usize;
+                    next.swap(i, j);
+                }
+                let mut node = 0u64;
+                Box::new((0..count).map(move |_| {
+                    let access = read(base + node * node_bytes, 8);
+                    node = next[node as usize];
+                    access
+                }))
+            }
+            Workload::MatMul {
+                base,
+                n,
+                elem,
+                tile,
+            } => matmul(base, n, elem, tile),
+            Workload::Stencil {
+                base,
+                rows,
+                cols,
+                elem,
+                iterations,
+            } => stencil(base, rows, cols, elem, iterations),
+        })
+    }
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// `0..n` in a random order.
+fn shuffled(n: u64, rng: &mut Rng) -> Vec<u64> {
+    let mut order: Vec<u64> = (0..n).collect();
+    for i in (1..n as usize).rev() {
+        let j = rng.below(i as u64 + 1) as usize;
+        order.swap(i, j);
+    }
+    order
+}
+
+fn matmul(base: u64, n: u64, elem: u32, tile: Option<u64>) -> Box<dyn Iterator<Item = Access>> {
+    let e = elem as u64;
+    let a = move |i: u64, k: u64| base + (i * n + k) * e;
+    let b = move |k: u64, j: u64| base + (n * n + k * n + j) * e;
+    let c = move |i: u64, j: u64| base + (2 * n * n + i * n + j) * e;
+    match tile {
+        None => Box::new((0..n).flat_map(move |i| {
+            (0..n).flat_map(move |j| {
+                (0..n)
+                    .flat_map(move |k| [read(a(i, k), elem), read(b(k, j), elem)])
+                    .chain(std::iter::once(write(c(i, j), elem)))
+            })
+        })),
+        Some(t) => {
+            let blocks = move || (0..n).step_by(t as usize);
+            Box::new(blocks().flat_map(move |ii| {
+                blocks().flat_map(move |jj| {
+                    blocks().flat_map(move |kk| {
+                        (ii..(ii + t).min(n)).flat_map(move |i| {
+                            (jj..(jj + t).min(n)).flat_map(move |j| {
+                                std::iter::once(read(c(i, j), elem))
+                                    .chain((kk..(kk + t).min(n)).flat_map(move |k| {
+                                        [read(a(i, k), elem), read(b(k, j), elem)]
+                                    }))
+                                    .chain(std::iter::once(write(c(i, j), elem)))
+                            })
+                        })
+                    })
+                })
+            }))
+        }
+    }
+}
+
+fn stencil(
+    base: u64,
+    rows: u64,
+    cols: u64,
+    elem: u32,
+    iterations: u64,
+) -> Box<dyn Iterator<Item = Access>> {
+    let e = elem as u64;
+    let grid_bytes = rows * cols * e;
+    Box::new((0..iterations).flat_map(move |t| {
+        let (src, dst) = if t % 2 == 0 {
+            (base, base + grid_bytes)
+        } else {
+            (base + grid_bytes, base)
+        };
+        let at = move |grid: u64, r: u64, c: u64| grid + (r * cols + c) * e;
+        (1..rows - 1).flat_map(move |r| {
+            (1..cols - 1).flat_map(move |c| {
+                [
+                    read(at(src, r - 1, c), elem),
+                    read(at(src, r, c - 1), elem),
+                    read(at(src, r, c), elem),
+                    read(at(src, r, c + 1), elem),
+                    read(at(src, r + 1, c), elem),
+                    write(at(dst, r, c), elem),
+                ]
+            })
+        })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn workloads() -> Vec<Workload> {
+        vec![
+            Workload::Sequential {
+                base: 0x1000,
+                footprint: 256,
+                count: 100,
+                size: 4,
+                write_ratio: 0.3,
+            },
+            Workload::Strided {
+                base: 0,
+                footprint: 4096,
+                stride: 96,
+                count: 100,
+                size: 4,
+                write_ratio: 0.3,
+            },
+            Workload::Uniform {
+                base: 0,
+                footprint: 1 << 20,
+                count: 100,
+                size: 8,
+                write_ratio: 0.3,
+            },
+            Workload::Zipf {
+                base: 0,
+                items: 64,
+                alpha: 1.0,
+                count: 100,
+                size: 32,
+                write_ratio: 0.3,
+            },
+            Workload::PointerChase {
+                base: 0,
+                nodes: 16,
+                node_bytes: 64,
+                count: 40,
+            },
+            Workload::MatMul {
+                base: 0,
+                n: 4,
+                elem: 8,
+                tile: Some(2),
+            },
+            Workload::Stencil {
+                base: 0,
+                rows: 4,
+                cols: 5,
+                elem: 8,
+                iterations: 2,
+            },
+        ]
+    }
+
+    fn run(workload: &Workload, seed: u64) -> Vec<Access> {
+        workload.accesses(seed).unwrap().collect()
+    }
+
+    #[test]
+    fn same_seed_same_stream() {
+        for workload in workloads() {
+            assert_eq!(run(&workload, 7), run(&workload, 7), "{}", workload);
+        }
+        let uniform = &workloads()[2];
+        assert_ne!(run(uniform, 7), run(uniform, 8));
+    }
+
+    #[test]
+    fn pointer_chase_is_one_cycle() {
+        let chase = &workloads()[4];
+        let accesses = run(chase, 3);
+        let first: HashSet<u64> = accesses[..16].iter().map(|a| a.addr).collect();
+        assert_eq!(first.len(), 16);
+        assert_eq!(accesses[0].addr, accesses[16].addr);
+    }
+
+    #[test]
+    fn matmul_loop_nest() {
+        let untiled = Workload::MatMul {
+            base: 0,
+            n: 4,
+            elem: 8,
+            tile: None,
+        };
+        let accesses = run(&untiled, 0);
+        // n^2 elements of C, each 2n reads and a write.
+        assert_eq!(accesses.len(), 16 * 9);
+        // A[0][0], B[0][0], A[0][1], B[1][0]; B starts at n^2 elements.
+        let addrs: Vec<u64> = accesses[..4].iter().map(|a| a.addr).collect();
+        assert_eq!(addrs, [0, 128, 8, 160]);
+        assert_eq!(accesses[8], write(256, 8));
+
+        // Tiling reorders the same A and B reads and adds a read of C per
+        // block of k.
+        let tiled = run(&workloads()[5], 0);
+        assert_eq!(tiled.len(), 16 * 2 * (1 + 4 + 1));
+        let operands = |accesses: &[Access]| {
+            let mut addrs: Vec<u64> = accesses
+                .iter()
+                .filter(|a| a.addr < 256)
+                .map(|a| a.addr)
+                .collect();
+            addrs.sort_unstable();
+            addrs
+        };
+        assert_eq!(operands(&tiled), operands(&accesses));
+    }
+
+    #[test]
+    fn stencil_sweeps_the_interior() {
+        let accesses = run(&workloads()[6], 0);
+        // Two sweeps of a 2 x 3 interior, five reads and a write each.
+        assert_eq!(accesses.len(), 2 * 6 * 6);
+        // The first sweep writes the second grid, the next writes back.
+        assert_eq!(accesses[5], write(160 + 6 * 8, 8));
+        assert_eq!(accesses[41], write(6 * 8, 8));
+    }
+
+    #[test]
+    fn rejects_empty_patterns() {
+        let bad = [
+            Workload::Sequential {
+                base: 0,
+                footprint: 2,
+                count: 1,
+                size: 4,
+                write_ratio: 0.0,
+            },
+            Workload::Uniform {
+                base: 0,
+                footprint: 64,
+                count: 1,
+                size: 4,
+                write_ratio: 1.5,
+            },
+            Workload::PointerChase {
+                base: 0,
+                nodes: 4,
+                node_bytes: 4,
+                count: 1,
+            },
+            Workload::MatMul {
+                base: 0,
+                n: 4,
+                elem: 8,
+                tile: Some(0),
+            },
+        ];
+        for workload in bad {
+            assert!(workload.accesses(0).is_err(), "{:?}", workload);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! CPU transactions rebuilt from per-cycle port values.
+//!
+//! A transaction starts when the CPU presents a request in S_IDLE and ends
+//! in the S_COMPARE_TAG cycle that drops `cpu_wait`, as in
+//! `System::access`. Passing through S_ALLOCATE makes it a miss, and the
+//! address on `mem_addr` in S_WRITE_BACK is its write-back. The same
+//! `TransactionLog` runs over `vcd` cycles from an RTL waveform, with
+//! `from_waves`, and over `System` cycle records, so the two logs can be
+//! diffed entry by entry.
+
+use std::fmt::{self, Write};
+
+use super::fsm::{CpuRequest, CycleModel, State};
+use super::memory::Memory;
+use super::protocol::PortSample;
+use super::system::{CycleRecord, System};
+use super::trace::TraceError;
+use super::vcd::WaveCycle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    /// The cycle the request was presented in.
+    pub cycle: u64,
+    pub addr: u64,
+    pub write: bool,
+    /// `cpu_wdata`, for writes.
+    pub wdata: Option<u64>,
+    /// `cpu_rdata` in the completing cycle, for reads; `None` if undriven.
+    pub rdata: Option<u64>,
+    pub hit: bool,
+    /// The block address written back before the fill, if any.
+    pub write_back: Option<u64>,
+    /// Cycles from the request to its completion, inclusive.
+    pub cycles: u64,
+    /// Cycles with `cpu_wait` high.
+    pub stall_cycles: u64,
+}
+
+impl Transaction {
+    /// The request that replays this transaction.
+    pub fn request(&self) -> CpuRequest {
+        if self.write {
+            CpuRequest::write(self.addr, self.wdata.unwrap_or(0))
+        } else {
+            CpuRequest::read(self.addr)
+        }
+    }
+}
+
+fn opt_hex(v: Option<u64>) -> String {
+    v.map_or_else(|| "-".to_string(), |v| format!("{:#x}", v))
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "@{} {} {:#x} data {} {}{} {} cycles ({} stalled)",
+            self.cycle,
+            if self.write { "write" } else { "read" },
+            self.addr,
+            opt_hex(if self.write { self.wdata } else { self.rdata }),
+            if self.hit { "hit" } else { "miss" },
+            self.write_back
+                .map_or_else(String::new, |a| format!(", write-back {:#x}", a)),
+            self.cycles,
+            self.stall_cycles
+        )
+    }
+}
+
+/// Builds transactions from consecutive cycles.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionLog {
+    open: Option<Transaction>,
+    done: Vec<Transaction>,
+}
+
+impl TransactionLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds the next cycle: its number, `state_reg` and ports. Returns the
+    /// transaction it completed, if any. A request cut short by a return to
+    /// S_IDLE, as after a reset, is dropped.
+    pub fn cycle(&mut self, cycle: u64, state: State, ports: &PortSample) -> Option<&Transaction> {
+        let (cpu, out) = (&ports.cpu, &ports.out);
+        if state == State::Idle {
+            self.open = cpu.is_active().then(|| Transaction {
+                cycle,
+                addr: cpu.cpu_addr,
+                write: cpu.cpu_write,
+                wdata: cpu.cpu_write.then_some(cpu.cpu_wdata),
+                rdata: None,
+                hit: true,
+                write_back: None,
+                cycles: 1,
+                stall_cycles: 0,
+            });
+            return None;
+        }
+        let t = self.open.as_mut()?;
+        t.cycles += 1;
+        t.stall_cycles += out.cpu_wait as u64;
+        match state {
+            State::Allocate => t.hit = false,
+            State::WriteBack => t.write_back = t.write_back.or(out.mem_addr),
+            State::CompareTag if !out.cpu_wait => {
+                if !t.write {
+                    t.rdata = out.cpu_rdata;
+                }
+                self.done.extend(self.open.take());
+                return self.done.last();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.done
+    }
+
+    pub fn into_transactions(self) -> Vec<Transaction> {
+        self.done
+    }
+
+    /// The log of a `System`'s cycle records.
+    pub fn from_records<'a, I>(records: I) -> Self
+    where
+        I: IntoIterator<Item = &'a CycleRecord>,
+    {
+        let mut log = Self::new();
+        for r in records {
+            log.cycle(r.cycle, r.state, &PortSample::from(r));
+        }
+        log
+    }
+
+    /// The log of a waveform's cycles, as `CycleSampler` yields them. A
+    /// cycle whose `state_reg` holds no valid encoding is an error: without
+    /// the state, where transactions start and end is unknown.
+    pub fn from_waves<I>(cycles: I) -> Result<Self, TraceError>
+    where
+        I: IntoIterator<Item = Result<WaveCycle, TraceError>>,
+    {
+        let mut log = Self::new();
+        for wave in cycles {
+            let wave = wave?;
+            let state = wave.state.ok_or_else(|| TraceError::Parse {
+                line: 0,
+                message: format!(
+                    "cycle {} (time {}): state_reg holds no valid state",
+                    wave.cycle, wave.time
+                ),
+            })?;
+            log.cycle(wave.cycle, state, &wave.ports);
+        }
+        Ok(log)
+    }
+}
+
+/// Replays the requests of `log` through `system` and returns the golden
+/// model's log of them. `system` should start from reset, as the RTL did.
+pub fn replay<C: CycleModel, M: Memory>(
+    system: &mut System<C, M>,
+    log: &[Transaction],
+) -> Vec<Transaction> {
+    system.record_cycles();
+    system.take_cycle_records();
+    for t in log {
+        system.access(t.request());
+    }
+    TransactionLog::from_records(&system.take_cycle_records()).into_transactions()
+}
+
+/// One field that differs between two logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Position in the logs.
+    pub index: usize,
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "transaction {}: {} is {}, expected {}",
+            self.index, self.field, self.actual, self.expected
+        )
+    }
+}
+
+/// The differences between `expected` and `actual`, entry by entry. With
+/// `timing` false, cycle numbers and counts are left out, for logs taken
+/// against memories of different latency.
+pub fn diff(expected: &[Transaction], actual: &[Transaction], timing: bool) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for (index, (e, a)) in expected.iter().zip(actual).enumerate() {
+        let mut check = |field, ev: String, av: String| {
+            if ev != av {
+                mismatches.push(Mismatch {
+                    index,
+                    field,
+                    expected: ev,
+                    actual: av,
+                });
+            }
+        };
+        check("addr", format!("{:#x}", e.addr), format!("{:#x}", a.addr));
+        check("write", e.write.to_string(), a.write.to_string());
+        check("wdata", opt_hex(e.wdata), opt_hex(a.wdata));
+        check("rdata", opt_hex(e.rdata), opt_hex(a.rdata));
+        check("hit", e.hit.to_string(), a.hit.to_string());
+        check("write_back", opt_hex(e.write_back), opt_hex(a.write_back));
+        if timing {
+            check("cycle", e.cycle.to_string(), a.cycle.to_string());
+            check("cycles", e.cycles.to_string(), a.cycles.to_string());
+            check(
+                "stall_cycles",
+                e.stall_cycles.to_string(),
+                a.stall_cycles.to_string(),
+            );
+        }
+    }
+    if expected.len() != actual.len() {
+        mismatches.push(Mismatch {
+            index: expected.len().min(actual.len()),
+            field: "count",
+            expected: expected.len().to_string(),
+            actual: actual.len().to_string(),
+        });
+    }
+    mismatches
+}
+
+fn json_opt(v: Option<u64>) -> String {
+    v.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+/// The log as a JSON array, one object per transaction.
+pub fn to_json(log: &[Transaction]) -> String {
+    let mut out = String::from("[\n");
+    for (i, t) in log.iter().enumerate() {
+        let _ = write!(
+            out,
+            "  {{\"cycle\": {}, \"addr\": {}, \"write\": {}, \"wdata\": {}, \"rdata\": {}, \
+             \"hit\": {}, \"write_back\": {}, \"cycles\": {}, \"stall_cycles\": {}}}",
+            t.cycle,
+            t.addr,
+            t.write,
+            json_opt(t.wdata),
+            json_opt(t.rdata),
+            t.hit,
+            json_opt(t.write_back),
+            t.cycles,
+            t.stall_cycles
+        );
+        out.push_str(if i + 1 < log.len() { ",\n" } else { "\n" });
+    }
+    out.push(']');
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::direct_mapped::DirectMappedCache;
+    use super::super::fsm::CacheOutputs;
+    use super::super::memory::MainMemory;
+    use super::super::params::CacheParams;
+    use super::super::vcd::CycleSampler;
+    use super::*;
+
+    const HEADER: &str = "$timescale 1ns $end\n\
+        $scope module tb $end\n\
+        $scope module dut $end\n\
+        $var wire 1 ! clk $end\n\
+        $var wire 1 \" rst_n $end\n\
+        $var wire 32 # cpu_addr [31:0] $end\n\
+        $var wire 1 $ cpu_read $end\n\
+        $var wire 1 % cpu_write $end\n\
+        $var wire 32 & cpu_wdata [31:0] $end\n\
+        $var reg 32 ' cpu_rdata [31:0] $end\n\
+        $var reg 1 ( cpu_wait $end\n\
+        $var reg 32 ) mem_addr [31:0] $end\n\
+        $var reg 1 * mem_read $end\n\
+        $var reg 1 + mem_write $end\n\
+        $var reg 32 , mem_wdata [31:0] $end\n\
+        $var wire 1 - mem_wait $end\n\
+        $var reg 3 . state_reg [2:0] $end\n\
+        $upscope $end\n\
+        $upscope $end\n\
+        $enddefinitions $end\n\
+        #0\n0!\n0\"\n#5\n1!\n";
+
+    fn bits(v: Option<u64>) -> String {
+        v.map_or_else(|| "bx".to_string(), |v| format!("b{:b}", v))
+    }
+
+    /// Cycle `k` of a dump: every port set at time 10k, `clk` rising at
+    /// 10k + 5. `state` is `state_reg`'s bits.
+    fn cycle(k: u64, state: &str, cpu: CpuRequest, out: CacheOutputs, mem_wait: bool) -> String {
+        let b = |v: bool| v as u8;
+        format!(
+            "#{}\n0!\n1\"\nb{:b} #\n{}$\n{}%\nb{:b} &\n{} '\n{}(\n{} )\n{}*\n{}+\n{} ,\n{}-\nb{} .\n#{}\n1!\n",
+            10 * k,
+            cpu.cpu_addr,
+            b(cpu.cpu_read),
+            b(cpu.cpu_write),
+            cpu.cpu_wdata,
+            bits(out.cpu_rdata),
+            b(out.cpu_wait),
+            bits(out.mem_addr),
+            b(out.mem_read),
+            b(out.mem_write),
+            bits(out.mem_wdata.map(|w| w[0] as u64)),
+            b(mem_wait),
+            state,
+            10 * k + 5
+        )
+    }
+
+    /// A read miss that writes back a dirty line, with memory waiting one
+    /// cycle on the write-back, then a write hit to the filled line.
+    fn dump() -> String {
+        let read = CpuRequest::read(0x1_0040);
+        let write = CpuRequest::write(0x1_0044, 0x99);
+        let wait = CacheOutputs {
+            cpu_wait: true,
+            ..CacheOutputs::default()
+        };
+        let write_back = CacheOutputs {
+            mem_write: true,
+            mem_addr: Some(0x40),
+            mem_wdata: Some(vec![0xaa]),
+            ..wait.clone()
+        };
+        let fill = CacheOutputs {
+            mem_read: true,
+            mem_addr: Some(0x1_0040),
+            ..wait.clone()
+        };
+        let done = CacheOutputs {
+            cpu_rdata: Some(0x1234),
+            ..CacheOutputs::default()
+        };
+        let idle = CacheOutputs::default();
+        let mut dump = HEADER.to_string();
+        for (k, (state, cpu, out, mem_wait)) in [
+            ("000", read, idle.clone(), false),
+            ("001", read, wait.clone(), false),
+            ("010", read, wait, false),
+            ("011", read, write_back.clone(), true),
+            ("011", read, write_back, false),
+            ("100", read, fill, false),
+            ("001", read, done, false),
+            ("000", write, idle.clone(), false),
+            ("001", write, idle.clone(), false),
+            ("000", CpuRequest::idle(), idle, false),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            dump.push_str(&cycle(k as u64 + 1, state, cpu, out, mem_wait));
+        }
+        dump
+    }
+
+    #[test]
+    fn transactions_from_a_waveform() {
+        let dump = dump();
+        let sampler = CycleSampler::open(dump.as_bytes(), None).unwrap();
+        assert_eq!(sampler.ports().scope, ["tb", "dut"]);
+        let log = TransactionLog::from_waves(sampler).unwrap();
+        assert_eq!(
+            log.transactions(),
+            [
+                Transaction {
+                    cycle: 0,
+                    addr: 0x1_0040,
+                    write: false,
+                    wdata: None,
+                    rdata: Some(0x1234),
+                    hit: false,
+                    write_back: Some(0x40),
+                    cycles: 7,
+                    stall_cycles: 5,
+                },
+                Transaction {
+                    cycle: 7,
+                    addr: 0x1_0044,
+                    write: true,
+                    wdata: Some(0x99),
+                    rdata: None,
+                    hit: true,
+                    write_back: None,
+                    cycles: 2,
+                    stall_cycles: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            log.transactions()[0].to_string(),
+            "@0 read 0x10040 data 0x1234 miss, write-back 0x40 7 cycles (5 stalled)"
+        );
+    }
+
+    #[test]
+    fn unknown_state_is_an_error() {
+        let dump = dump().replacen("b011 .", "bx .", 1);
+        let sampler = CycleSampler::open(dump.as_bytes(), None).unwrap();
+        let err = TransactionLog::from_waves(sampler).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "line 0: cycle 3 (time 45): state_reg holds no valid state"
+        );
+    }
+
+    #[test]
+    fn replay_and_diff() {
+        let dump = dump();
+        let sampler = CycleSampler::open(dump.as_bytes(), None).unwrap();
+        let wave = TransactionLog::from_waves(sampler).unwrap();
+        let mut system = System::new(
+            DirectMappedCache::new(CacheParams::default()).unwrap(),
+            MainMemory::new(32, 0),
+        );
+        // From reset the golden model has nothing to write back, and
+        // memory reads as zero.
+        let golden = replay(&mut system, wave.transactions());
+        let mismatches = diff(&golden, wave.transactions(), false);
+        let fields: Vec<&str> = mismatches.iter().map(|m| m.field).collect();
+        assert_eq!(fields, ["rdata", "write_back"]);
+        assert_eq!(
+            mismatches[1].to_string(),
+            "transaction 0: write_back is 0x40, expected -"
+        );
+        assert!(diff(&golden, &golden, true).is_empty());
+        let count = diff(&golden, &golden[..1], true);
+        assert_eq!(count[0].field, "count");
+        assert!(to_json(&golden[1..]).contains("\"write\": true, \"wdata\": 153, \"rdata\": null"));
+    }
+}
@@ -0,0 +1,673 @@
+//! Reading the cache's ports back out of a VCD waveform.
+//!
+//! `VcdReader` streams any IEEE 1364 value change dump: it parses the header
+//! into the declared variables, then yields timestamps and value changes as
+//! they come. `CachePorts` finds the cache's ports and `state_reg` among the
+//! variables, and `CycleSampler` turns the changes into one sample per
+//! rising edge of `clk`, taken from the values just before the edge, which
+//! is when the RTL's `always_ff` block sees them. Cycles in reset are
+//! skipped.
+//!
+//! Values are four-state; an `x` or `z` anywhere in an output makes it
+//! `None`, as the golden models report undriven outputs.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use super::fsm::{CacheOutputs, CpuRequest, State};
+use super::protocol::PortSample;
+use super::trace::TraceError;
+
+/// A four-state value, most significant bit first, one of `01xz` per bit.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Logic(String);
+
+impl Logic {
+    /// All `x`, as every variable starts.
+    pub fn unknown(width: u32) -> Self {
+        Logic("x".repeat(width.max(1) as usize))
+    }
+
+    /// Parses `bits`, extending it on the left to `width` as VCD does: with
+    /// `x` or `z` if that is the leftmost bit, else with `0`.
+    pub fn parse(bits: &str, width: u32) -> Option<Self> {
+        let bits = bits.to_ascii_lowercase();
+        if bits.is_empty() || !bits.chars().all(|c| matches!(c, '0' | '1' | 'x' | 'z')) {
+            return None;
+        }
+        let width = width.max(1) as usize;
+        let value = if bits.len() >= width {
+            bits[bits.len() - width..].to_string()
+        } else {
+            let fill = match bits.as_bytes()[0] {
+                b'x' => 'x',
+                b'z' => 'z',
+                _ => '0',
+            };
+            let mut value: String = std::iter::repeat_n(fill, width - bits.len()).collect();
+            value.push_str(&bits);
+            value
+        };
+        Some(Logic(value))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.0.len() as u32
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.0.bytes().all(|b| b == b'0' || b == b'1')
+    }
+
+    /// The low 64 bits, if every bit is known.
+    pub fn to_u64(&self) -> Option<u64> {
+        if !self.is_known() {
+            return None;
+        }
+        let low = &self.0[self.0.len().saturating_sub(64)..];
+        u64::from_str_radix(low, 2).ok()
+    }
+
+    /// The value as `len` little-endian bytes, if every bit is known.
+    pub fn to_bytes(&self, len: usize) -> Option<Vec<u8>> {
+        if !self.is_known() {
+            return None;
+        }
+        let bits = self.0.as_bytes();
+        let mut bytes = vec![0u8; len];
+        for (i, &bit) in bits.iter().rev().enumerate().take(len * 8) {
+            if bit == b'1' {
+                bytes[i / 8] |= 1 << (i % 8);
+            }
+        }
+        Some(bytes)
+    }
+
+    /// A single known bit; `x` and `z` read as low.
+    pub fn is_high(&self) -> bool {
+        self.0.ends_with('1')
+    }
+}
+
+/// A declared variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VcdVar {
+    /// Enclosing scopes, outermost first.
+    pub scope: Vec<String>,
+    pub name: String,
+    pub width: u32,
+    /// Index of the signal behind the identifier code; variables declared
+    /// with the same code share it.
+    pub signal: usize,
+}
+
+impl VcdVar {
+    /// The dotted hierarchical name.
+    pub fn path(&self) -> String {
+        let mut path = self.scope.join(".");
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(&self.name);
+        path
+    }
+}
+
+/// What the dump declares before `$enddefinitions`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VcdHeader {
+    pub timescale: Option<String>,
+    pub vars: Vec<VcdVar>,
+    /// Width of each signal.
+    pub signal_widths: Vec<u32>,
+}
+
+impl VcdHeader {
+    /// Variables called `name` in any scope.
+    pub fn find<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a VcdVar> + 'a {
+        self.vars.iter().filter(move |v| v.name == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Time(u64),
+    Value { signal: usize, value: Logic },
+}
+
+/// Whitespace-separated tokens with the line each came from.
+struct Tokens<R> {
+    input: R,
+    line: u64,
+    pending: Vec<String>,
+}
+
+impl<R: BufRead> Tokens<R> {
+    fn next(&mut self) -> Result<Option<String>, TraceError> {
+        while self.pending.is_empty() {
+            let mut buf = String::new();
+            if self.input.read_line(&mut buf)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            self.pending = buf.split_whitespace().rev().map(str::to_string).collect();
+        }
+        Ok(self.pending.pop())
+    }
+
+    fn error(&self, message: impl Into<String>) -> TraceError {
+        TraceError::Parse {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    /// The tokens up to the next `$end`, which is consumed.
+    fn until_end(&mut self) -> Result<Vec<String>, TraceError> {
+        let mut tokens = Vec::new();
+        loop {
+            match self.next()? {
+                Some(t) if t == "$end" => return Ok(tokens),
+                Some(t) => tokens.push(t),
+                None => return Err(self.error("missing $end")),
+            }
+        }
+    }
+}
+
+pub struct VcdReader<R> {
+    tokens: Tokens<R>,
+    header: VcdHeader,
+    ids: HashMap<String, usize>,
+}
+
+impl<R: BufRead> VcdReader<R> {
+    /// Reads the header.
+    pub fn new(input: R) -> Result<Self, TraceError> {
+        let mut tokens = Tokens {
+            input,
+            line: 0,
+            pending: Vec::new(),
+        };
+        let mut header = VcdHeader::default();
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        let mut scope = Vec::new();
+        loop {
+            let Some(token) = tokens.next()? else {
+                return Err(tokens.error("no $enddefinitions"));
+            };
+            match token.as_str() {
+                "$enddefinitions" => {
+                    tokens.until_end()?;
+                    break;
+                }
+                "$timescale" => header.timescale = Some(tokens.until_end()?.join("")),
+                "$scope" => {
+                    let body = tokens.until_end()?;
+                    scope.push(body.get(1).cloned().unwrap_or_default());
+                }
+                "$upscope" => {
+                    tokens.until_end()?;
+                    scope.pop();
+                }
+                "$var" => {
+                    let body = tokens.until_end()?;
+                    let [_, width, id, name, ..] = body.as_slice() else {
+                        return Err(tokens.error(format!("short $var: {}", body.join(" "))));
+                    };
+                    let width: u32 = width
+                        .parse()
+                        .map_err(|_| tokens.error(format!("bad $var width {:?}", width)))?;
+                    // A bit range may trail the name, separately or not.
+                    let name = name.split('[').next().unwrap_or(name).to_string();
+                    let next = header.signal_widths.len();
+                    let signal = *ids.entry(id.clone()).or_insert(next);
+                    if signal == next {
+                        header.signal_widths.push(width);
+                    }
+                    header.vars.push(VcdVar {
+                        scope: scope.clone(),
+                        name,
+                        width,
+                        signal,
+                    });
+                }
+                t if t.starts_with('$') => {
+                    tokens.until_end()?;
+                }
+                t => return Err(tokens.error(format!("unexpected {:?} in header", t))),
+            }
+        }
+        Ok(VcdReader {
+            tokens,
+            header,
+            ids,
+        })
+    }
+
+    pub fn header(&self) -> &VcdHeader {
+        &self.header
+    }
+
+    /// The next timestamp or value change; `None` at the end of the dump.
+    /// Changes to identifiers the header never declared are an error.
+    pub fn next_change(&mut self) -> Result<Option<Change>, TraceError> {
+        loop {
+            let Some(token) = self.tokens.next()? else {
+                return Ok(None);
+            };
+            let Some((head, rest)) = token.split_at_checked(1) else {
+                return Err(self.tokens.error(format!("unexpected {:?}", token)));
+            };
+            match head {
+                "#" => {
+                    let time = rest
+                        .parse()
+                        .map_err(|_| self.tokens.error(format!("bad timestamp {:?}", token)))?;
+                    return Ok(Some(Change::Time(time)));
+                }
+                // $dumpvars and friends only bracket ordinary changes.
+                "$" => {
+                    if token == "$comment" {
+                        self.tokens.until_end()?;
+                    }
+                }
+                "b" | "B" | "r" | "R" | "s" | "S" => {
+                    let Some(id) = self.tokens.next()? else {
+                        return Err(self.tokens.error("value without identifier"));
+                    };
+                    let signal = self.signal(&id)?;
+                    if head.eq_ignore_ascii_case("b") {
+                        let width = self.header.signal_widths[signal];
+                        let value = Logic::parse(rest, width)
+                            .ok_or_else(|| self.tokens.error(format!("bad vector {:?}", token)))?;
+                        return Ok(Some(Change::Value { signal, value }));
+                    }
+                    // Real and string values have no place on the cache's ports.
+                }
+                "0" | "1" | "x" | "X" | "z" | "Z" => {
+                    let signal = self.signal(rest)?;
+                    let width = self.header.signal_widths[signal];
+                    let value = Logic::parse(head, width).expect("a valid bit");
+                    return Ok(Some(Change::Value { signal, value }));
+                }
+                _ => return Err(self.tokens.error(format!("unexpected {:?}", token))),
+            }
+        }
+    }
+
+    fn signal(&self, id: &str) -> Result<usize, TraceError> {
+        self.ids
+            .get(id)
+            .copied()
+            .ok_or_else(|| self.tokens.error(format!("undeclared identifier {:?}", id)))
+    }
+}
+
+/// The signals of the cache's ports and `state_reg`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachePorts {
+    /// The scope they were found in.
+    pub scope: Vec<String>,
+    pub clk: usize,
+    pub rst_n: usize,
+    pub cpu_addr: usize,
+    pub cpu_read: usize,
+    pub cpu_write: usize,
+    pub cpu_wdata: usize,
+    pub cpu_rdata: usize,
+    pub cpu_wait: usize,
+    pub mem_addr: usize,
+    pub mem_read: usize,
+    pub mem_write: usize,
+    pub mem_wdata: usize,
+    pub mem_wait: usize,
+    pub state_reg: usize,
+    /// Bytes in `mem_wdata`.
+    pub block_bytes: usize,
+}
+
+impl CachePorts {
+    /// Finds the ports in the scope whose dotted path is `scope`, or, if
+    /// `None`, in the first scope that declares `state_reg`.
+    pub fn find(header: &VcdHeader, scope: Option<&str>) -> Result<Self, String> {
+        let scope: Vec<String> = match scope {
+            Some(path) => path.split('.').map(str::to_string).collect(),
+            None => header
+                .find("state_reg")
+                .next()
+                .map(|v| v.scope.clone())
+                .ok_or("no state_reg in the dump; was the cache's scope dumped?")?,
+        };
+        let var = |name: &str| {
+            header
+                .vars
+                .iter()
+                .find(|v| v.scope == scope && v.name == name)
+                .ok_or_else(|| format!("no {} in {}", name, scope.join(".")))
+        };
+        let signal = |name: &str| var(name).map(|v| v.signal);
+        Ok(CachePorts {
+            clk: signal("clk")?,
+            rst_n: signal("rst_n")?,
+            cpu_addr: signal("cpu_addr")?,
+            cpu_read: signal("cpu_read")?,
+            cpu_write: signal("cpu_write")?,
+            cpu_wdata: signal("cpu_wdata")?,
+            cpu_rdata: signal("cpu_rdata")?,
+            cpu_wait: signal("cpu_wait")?,
+            mem_addr: signal("mem_addr")?,
+            mem_read: signal("mem_read")?,
+            mem_write: signal("mem_write")?,
+            mem_wdata: signal("mem_wdata")?,
+            mem_wait: signal("mem_wait")?,
+            state_reg: signal("state_reg")?,
+            block_bytes: var("mem_wdata")?.width.div_ceil(8) as usize,
+            scope,
+        })
+    }
+}
+
+/// The ports of one clock cycle, read from a waveform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaveCycle {
+    /// Rising edges of `clk` out of reset before this one.
+    pub cycle: u64,
+    /// The time of the rising edge that ends the cycle.
+    pub time: u64,
+    /// `None` if `state_reg` held no valid encoding.
+    pub state: Option<State>,
+    pub ports: PortSample,
+}
+
+/// Yields a `WaveCycle` per rising edge of `clk` out of reset.
+pub struct CycleSampler<R> {
+    reader: VcdReader<R>,
+    ports: CachePorts,
+    values: Vec<Logic>,
+    /// Changes at the current timestamp, not yet applied.
+    pending: Vec<(usize, Logic)>,
+    time: u64,
+    cycle: u64,
+    done: bool,
+}
+
+impl<R: BufRead> CycleSampler<R> {
+    pub fn new(reader: VcdReader<R>, ports: CachePorts) -> Self {
+        let values = reader
+            .header()
+            .signal_widths
+            .iter()
+            .map(|&w| Logic::unknown(w))
+            .collect();
+        CycleSampler {
+            reader,
+            ports,
+            values,
+            pending: Vec::new(),
+            time: 0,
+            cycle: 0,
+            done: false,
+        }
+    }
+
+    /// Reads the header of `input` and samples the cache in `scope`, or the
+    /// first scope with a `state_reg`.
+    pub fn open(input: R, scope: Option<&str>) -> Result<Self, TraceError> {
+        let reader = VcdReader::new(input)?;
+        let ports = CachePorts::find(reader.header(), scope)
+            .map_err(|message| TraceError::Parse { line: 0, message })?;
+        Ok(Self::new(reader, ports))
+    }
+
+    pub fn ports(&self) -> &CachePorts {
+        &self.ports
+    }
+
+    fn sample(&self) -> WaveCycle {
+        let p = &self.ports;
+        let v = &self.values;
+        let cpu = CpuRequest {
+            cpu_addr: v[p.cpu_addr].to_u64().unwrap_or(0),
+            cpu_read: v[p.cpu_read].is_high(),
+            cpu_write: v[p.cpu_write].is_high(),
+            cpu_wdata: v[p.cpu_wdata].to_u64().unwrap_or(0),
+        };
+        let out = CacheOutputs {
+            cpu_rdata: v[p.cpu_rdata].to_u64(),
+            cpu_wait: v[p.cpu_wait].is_high(),
+            mem_addr: v[p.mem_addr].to_u64(),
+            mem_read: v[p.mem_read].is_high(),
+            mem_write: v[p.mem_write].is_high(),
+            mem_wdata: v[p.mem_wdata].to_bytes(p.block_bytes),
+        };
+        WaveCycle {
+            cycle: self.cycle,
+            time: self.time,
+            state: v[p.state_reg]
+                .to_u64()
+                .and_then(|s| u8::try_from(s).ok())
+                .and_then(State::from_encoding),
+            ports: PortSample {
+                cpu,
+                out,
+                mem_wait: v[p.mem_wait].is_high(),
+            },
+        }
+    }
+
+    /// Applies the changes of the timestamp just finished; returns the
+    /// cycle it closed, if `clk` rose out of reset.
+    fn settle(&mut self) -> Option<WaveCycle> {
+        let clk = self.ports.clk;
+        let rising = self.values[clk].as_str() == "0"
+            && self
+                .pending
+                .iter()
+                .any(|(s, v)| *s == clk && v.as_str() == "1");
+        let cycle = if rising && self.values[self.ports.rst_n].is_high() {
+            let sample = self.sample();
+            self.cycle += 1;
+            Some(sample)
+        } else {
+            None
+        };
+        for (signal, value) in self.pending.drain(..) {
+            self.values[signal] = value;
+        }
+        cycle
+    }
+}
+
+impl<R: BufRead> Iterator for CycleSampler<R> {
+    type Item = Result<WaveCycle, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.reader.next_change() {
+                Ok(Some(Change::Time(time))) => {
+                    let cycle = self.settle();
+                    self.time = time;
+                    if cycle.is_some() {
+                        return cycle.map(Ok);
+                    }
+                }
+                Ok(Some(Change::Value { signal, value })) => self.pending.push((signal, value)),
+                Ok(None) => {
+                    self.done = true;
+                    return self.settle().map(Ok);
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "$timescale 1ns $end\n\
+        $scope module tb $end\n\
+        $var wire 1 ! clk $end\n\
+        $upscope $end\n\
+        $enddefinitions $end\n";
+
+    #[test]
+    fn multi_byte_token_is_an_error() {
+        let dump = format!("{}#0\n1!\n\u{e9}1!\n", HEADER);
+        let mut reader = VcdReader::new(dump.as_bytes()).unwrap();
+        assert_eq!(reader.next_change().unwrap(), Some(Change::Time(0)));
+        assert!(reader.next_change().unwrap().is_some());
+        assert!(matches!(
+            reader.next_change(),
+            Err(TraceError::Parse { line: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn logic_values() {
+        assert_eq!(Logic::parse("1", 4).unwrap().as_str(), "0001");
+        assert_eq!(Logic::parse("x1", 4).unwrap().as_str(), "xxx1");
+        assert_eq!(Logic::parse("Z", 2).unwrap().as_str(), "zz");
+        assert_eq!(Logic::parse("10110", 4).unwrap().as_str(), "0110");
+        assert!(Logic::parse("12", 4).is_none());
+        assert!(Logic::parse("", 4).is_none());
+        let word = Logic::parse("1000000011", 16).unwrap();
+        assert_eq!(word.to_u64(), Some(0x203));
+        assert_eq!(word.to_bytes(2), Some(vec![0x03, 0x02]));
+        assert_eq!(Logic::unknown(3).to_u64(), None);
+        assert!(!Logic::unknown(1).is_high());
+    }
+
+    #[test]
+    fn parses_the_header() {
+        let dump = "$date today $end\n\
+            $timescale 1 ps $end\n\
+            $scope module top $end\n\
+            $var wire 1 ! clk $end\n\
+            $scope module cache $end\n\
+            $var wire 1 ! clk $end\n\
+            $var reg 8 \" data[7:0] $end\n\
+            $var reg 3 # state_reg [2:0] $end\n\
+            $upscope $end\n\
+            $upscope $end\n\
+            $enddefinitions $end\n\
+            #0\n$dumpvars\nb101 #\n$end\n#10\n1!\n";
+        let mut reader = VcdReader::new(dump.as_bytes()).unwrap();
+        let header = reader.header().clone();
+        assert_eq!(header.timescale.as_deref(), Some("1ps"));
+        let paths: Vec<String> = header.vars.iter().map(VcdVar::path).collect();
+        assert_eq!(
+            paths,
+            [
+                "top.clk",
+                "top.cache.clk",
+                "top.cache.data",
+                "top.cache.state_reg"
+            ]
+        );
+        // Both clocks share the identifier, and so the signal.
+        assert_eq!(header.signal_widths, [1, 8, 3]);
+        assert_eq!(
+            header.find("clk").map(|v| v.signal).collect::<Vec<_>>(),
+            [0, 0]
+        );
+        let changes: Vec<Change> = std::iter::from_fn(|| reader.next_change().unwrap()).collect();
+        assert_eq!(
+            changes,
+            [
+                Change::Time(0),
+                Change::Value {
+                    signal: 2,
+                    value: Logic::parse("101", 3).unwrap(),
+                },
+                Change::Time(10),
+                Change::Value {
+                    signal: 0,
+                    value: Logic::parse("1", 1).unwrap(),
+                },
+            ]
+        );
+        let err = CachePorts::find(&header, None).unwrap_err();
+        assert_eq!(err, "no rst_n in top.cache");
+    }
+
+    #[test]
+    fn header_errors() {
+        let err = |dump: &str| VcdReader::new(dump.as_bytes()).err().unwrap().to_string();
+        assert_eq!(err("$scope module a $end\n"), "line 1: no $enddefinitions");
+        assert_eq!(
+            err("$var wire ! clk $end\n$enddefinitions $end\n"),
+            "line 1: short $var: wire ! clk"
+        );
+        assert_eq!(err("$timescale 1ns\n"), "line 1: missing $end");
+        let dump = format!("{}#0\n1?\n", HEADER);
+        let mut reader = VcdReader::new(dump.as_bytes()).unwrap();
+        reader.next_change().unwrap();
+        assert_eq!(
+            reader.next_change().unwrap_err().to_string(),
+            "line 7: undeclared identifier \"?\""
+        );
+    }
+
+    #[test]
+    fn samples_before_each_rising_edge_out_of_reset() {
+        let names = [
+            "clk",
+            "rst_n",
+            "cpu_addr",
+            "cpu_read",
+            "cpu_write",
+            "cpu_wdata",
+            "cpu_rdata",
+            "cpu_wait",
+            "mem_addr",
+            "mem_read",
+            "mem_write",
+            "mem_wdata",
+            "mem_wait",
+            "state_reg",
+        ];
+        let mut dump = String::from("$scope module cache $end\n");
+        for (i, name) in names.iter().enumerate() {
+            let width = match *name {
+                "cpu_addr" | "cpu_wdata" | "cpu_rdata" | "mem_addr" => 32,
+                "mem_wdata" => 64,
+                "state_reg" => 3,
+                _ => 1,
+            };
+            let id = (b'a' + i as u8) as char;
+            dump.push_str(&format!("$var wire {} {} {} $end\n", width, id, name));
+        }
+        // clk is a, rst_n b, cpu_addr c, cpu_read d, cpu_wait h, state_reg n.
+        dump.push_str(
+            "$upscope $end\n$enddefinitions $end\n\
+             #0\n0a\n0b\nb0 n\n#5\n1a\n\
+             #10\n0a\n1b\n1d\nb100 c\n#15\n1a\n\
+             #20\n0a\n1h\nb1 n\n#25\n1a\n#30\n0a\n",
+        );
+        let sampler = CycleSampler::open(dump.as_bytes(), None).unwrap();
+        assert_eq!(sampler.ports().block_bytes, 8);
+        let cycles: Vec<WaveCycle> = sampler.map(Result::unwrap).collect();
+        // The edge at 5 is in reset.
+        assert_eq!(cycles.len(), 2);
+        assert_eq!((cycles[0].cycle, cycles[0].time), (0, 15));
+        assert_eq!(cycles[0].state, Some(State::Idle));
+        assert_eq!(cycles[0].ports.cpu, CpuRequest::read(4));
+        assert!(!cycles[0].ports.out.cpu_wait);
+        // Never-driven outputs are still x.
+        assert_eq!(cycles[0].ports.out.mem_addr, None);
+        assert_eq!((cycles[1].cycle, cycles[1].time), (1, 25));
+        assert_eq!(cycles[1].state, Some(State::CompareTag));
+        assert!(cycles[1].ports.out.cpu_wait);
+    }
+}
@@ -0,0 +1,264 @@
+//! Write-hit and write-miss policies for the functional cache.
+//!
+//! Both RTL caches are write-back and write-allocate: a write hit only sets
+//! `dirty_array`, and a write miss fetches the block in S_READ_FROM_MEM. The
+//! functional model can also write through on hits, write around on misses
+//! (no-write-allocate), and put a coalescing write buffer between the stores
+//! that go to memory and memory itself.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WriteHit {
+    /// Mark the line dirty and write it back when it is evicted.
+    WriteBack,
+    /// Send every store to memory as well; lines are never dirty.
+    WriteThrough,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WriteMiss {
+    /// Fetch the block, then handle the store as a hit.
+    WriteAllocate,
+    /// Send the store to memory and leave the cache untouched.
+    NoWriteAllocate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WritePolicy {
+    pub hit: WriteHit,
+    pub miss: WriteMiss,
+    /// Entries in the write buffer; `None` sends each store straight out.
+    pub write_buffer: Option<usize>,
+}
+
+impl Default for WritePolicy {
+    /// What the RTL does: write-back, write-allocate, no write buffer.
+    fn default() -> Self {
+        WritePolicy {
+            hit: WriteHit::WriteBack,
+            miss: WriteMiss::WriteAllocate,
+            write_buffer: None,
+        }
+    }
+}
+
+impl WritePolicy {
+    /// Whether any store can bypass the cache on its way to memory, which is
+    /// when a write buffer makes a difference.
+    pub fn stores_to_memory(&self) -> bool {
+        self.hit == WriteHit::WriteThrough || self.miss == WriteMiss::NoWriteAllocate
+    }
+
+    /// Every hit/miss combination, each policy that stores to memory also
+    /// with a buffer of `buffer_entries`.
+    pub fn combinations(buffer_entries: usize) -> Vec<WritePolicy> {
+        let mut all = Vec::new();
+        for hit in [WriteHit::WriteBack, WriteHit::WriteThrough] {
+            for miss in [WriteMiss::WriteAllocate, WriteMiss::NoWriteAllocate] {
+                let policy = WritePolicy {
+                    hit,
+                    miss,
+                    write_buffer: None,
+                };
+                all.push(policy);
+                if policy.stores_to_memory() {
+                    all.push(WritePolicy {
+                        write_buffer: Some(buffer_entries),
+                        ..policy
+                    });
+                }
+            }
+        }
+        all
+    }
+}
+
+impl fmt::Display for WritePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hit = match self.hit {
+            WriteHit::WriteBack => "wb",
+            WriteHit::WriteThrough => "wt",
+        };
+        let miss = match self.miss {
+            WriteMiss::WriteAllocate => "wa",
+            WriteMiss::NoWriteAllocate => "nwa",
+        };
+        write!(f, "{}+{}", hit, miss)?;
+        if let Some(entries) = self.write_buffer {
+            write!(f, "+buf{}", entries)?;
+        }
+        Ok(())
+    }
+}
+
+/// A buffered store on its way to memory: one block and the words written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferedStore {
+    pub block_addr: u64,
+    words: Vec<u64>,
+}
+
+impl BufferedStore {
+    /// Distinct words written to the block while it sat in the buffer.
+    pub fn word_count(&self) -> u32 {
+        self.words.iter().map(|w| w.count_ones()).sum()
+    }
+}
+
+/// A FIFO write buffer that merges stores to the same block. A store to a
+/// new block when the buffer is full drains the oldest entry.
+#[derive(Debug, Clone)]
+pub struct WriteBuffer {
+    capacity: usize,
+    words_per_block: usize,
+    entries: VecDeque<BufferedStore>,
+    coalesced: u64,
+}
+
+impl WriteBuffer {
+    pub fn new(capacity: usize, words_per_block: usize) -> Self {
+        WriteBuffer {
+            capacity: capacity.max(1),
+            words_per_block,
+            entries: VecDeque::new(),
+            coalesced: 0,
+        }
+    }
+
+    /// Stores merged into an entry that was already buffered.
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Buffers a store, returning the entry it pushed out, if any.
+    pub fn store(&mut self, block_addr: u64, word: usize) -> Option<BufferedStore> {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.block_addr == block_addr) {
+            entry.words[word / 64] |= 1 << (word % 64);
+            self.coalesced += 1;
+            return None;
+        }
+        let drained = if self.entries.len() == self.capacity {
+            self.entries.pop_front()
+        } else {
+            None
+        };
+        let mut words = vec![0; self.words_per_block.div_ceil(64)];
+        words[word / 64] |= 1 << (word % 64);
+        self.entries.push_back(BufferedStore { block_addr, words });
+        drained
+    }
+
+    /// Removes the entry for `block_addr` so it can be written before the
+    /// block is read from memory.
+    pub fn take(&mut self, block_addr: u64) -> Option<BufferedStore> {
+        let pos = self
+            .entries
+            .iter()
+            .position(|e| e.block_addr == block_addr)?;
+        self.entries.remove(pos)
+    }
+
+    /// Empties the buffer, oldest entry first.
+    pub fn drain_all(&mut self) -> Vec<BufferedStore> {
+        self.entries.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::functional::compare_write_policies;
+    use super::super::params::CacheParams;
+    use super::super::policy::PolicyKind;
+    use super::*;
+
+    #[test]
+    fn buffer_coalesces_and_drains_oldest() {
+        let mut buffer = WriteBuffer::new(2, 8);
+        assert_eq!(buffer.store(0x00, 0), None);
+        assert_eq!(buffer.store(0x00, 3), None);
+        assert_eq!(buffer.store(0x20, 1), None);
+        assert_eq!(buffer.coalesced(), 1);
+        let drained = buffer.store(0x40, 0).unwrap();
+        assert_eq!(drained.block_addr, 0x00);
+        assert_eq!(drained.word_count(), 2);
+        assert_eq!(buffer.take(0x40).map(|s| s.word_count()), Some(1));
+        assert_eq!(buffer.drain_all().len(), 1);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn memory_traffic_per_policy() {
+        let wb = WritePolicy::default();
+        let wt = WritePolicy {
+            hit: WriteHit::WriteThrough,
+            ..wb
+        };
+        let nwa = WritePolicy {
+            miss: WriteMiss::NoWriteAllocate,
+            ..wb
+        };
+        let buffered = WritePolicy {
+            hit: WriteHit::WriteThrough,
+            miss: WriteMiss::NoWriteAllocate,
+            write_buffer: Some(4),
+        };
+        assert_eq!(buffered.to_string(), "wt+nwa+buf4");
+        // 0x1_0040 maps to the same direct-mapped line as 0x40.
+        let accesses = [
+            (0x40, true),
+            (0x44, true),
+            (0x40, false),
+            (0x1_0040, true),
+            (0x40, false),
+        ];
+        let rows = compare_write_policies(
+            CacheParams::default(),
+            PolicyKind::Lru,
+            &[wb, wt, nwa, buffered],
+            &accesses,
+        )
+        .unwrap();
+        let traffic: Vec<(u64, u64, u64, u64, u64)> = rows
+            .iter()
+            .map(|(_, s)| {
+                (
+                    s.fills,
+                    s.write_backs,
+                    s.mem_stores,
+                    s.mem_read_bytes,
+                    s.mem_write_bytes,
+                )
+            })
+            .collect();
+        assert_eq!(
+            traffic,
+            [
+                (3, 2, 0, 96, 64),
+                (3, 0, 3, 96, 12),
+                (1, 0, 3, 32, 12),
+                // The two stores to 0x40 merge and drain before its fill.
+                (1, 0, 2, 32, 12),
+            ]
+        );
+        assert_eq!(rows[2].1.misses(), 4);
+    }
+
+    #[test]
+    fn combinations_buffer_only_stores() {
+        let all = WritePolicy::combinations(8);
+        assert_eq!(all.len(), 7);
+        assert!(all
+            .iter()
+            .all(|p| p.write_buffer.is_none() || p.stores_to_memory()));
+    }
+}